@@ -0,0 +1,90 @@
+//! Benchmarks of the hot paths of the software renderer (run with `cargo bench --features bench`).
+//!
+//! The renderer is a module of the game binary (there is no library to link to),
+//! so its source is included here as is.
+
+// Only some of the renderer is benchmarked.
+#![allow(dead_code)]
+// The bench is built with `cfg(test)` but without the test harness, so the `use`s of the test
+// modules of the included files go unused.
+#![allow(unused_imports)]
+
+#[path = "../src/coords.rs"]
+mod coords;
+#[path = "../src/renderer.rs"]
+mod renderer;
+
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion};
+
+use crate::coords::*;
+use crate::renderer::*;
+
+/// A full HD frame, a common window size for fullscreen play.
+const SCREEN_DIMS: (i32, i32) = (1920, 1080);
+
+/// A grass tile, as the most common sprite there is.
+fn tile_sprite() -> Rect {
+	Rect::tile((0, 0).into(), TILE_PX)
+}
+
+/// One tile sprite blitted at the zooms the game uses (the destination side is `TILE_PX` times
+/// the zoom), with the nearest neighbor scaling of the pixel art.
+fn draw_sprite(c: &mut Criterion) {
+	let mut group = c.benchmark_group("draw_sprite zoom");
+	for zoom in [1, 2, 4, 8] {
+		group.bench_with_input(BenchmarkId::from_parameter(zoom), &zoom, |b, &zoom| {
+			let mut renderer = SoftwareRenderer::new(SCREEN_DIMS.into(), Color::BLACK);
+			let dst = Rect::xywh(100, 100, TILE_PX * zoom, TILE_PX * zoom);
+			b.iter(|| renderer.draw_sprite(dst, tile_sprite(), DrawSpriteEffects::none()));
+		});
+	}
+	group.finish();
+}
+
+fn clear(c: &mut Criterion) {
+	c.bench_function("clear 1920x1080", |b| {
+		let mut renderer = SoftwareRenderer::new(SCREEN_DIMS.into(), Color::BLACK);
+		b.iter(|| renderer.clear());
+	});
+}
+
+/// A HUD panel sized rect and a rect over the whole frame, opaque and translucent
+/// (only opaque colors can be written without blending).
+fn draw_rect(c: &mut Criterion) {
+	for (name, color) in [
+		("opaque", Color::rgb_u8(60, 60, 60)),
+		("translucent", Color::rgba_u8(0, 0, 0, 150)),
+	] {
+		let mut group = c.benchmark_group(format!("draw_rect {name}"));
+		for (w, h) in [(400, 200), SCREEN_DIMS] {
+			let id = BenchmarkId::from_parameter(format!("{w}x{h}"));
+			group.bench_with_input(id, &(w, h), |b, &dims| {
+				let mut renderer = SoftwareRenderer::new(SCREEN_DIMS.into(), Color::BLACK);
+				let dst = Rect { top_left: (0, 0).into(), dims: dims.into() };
+				b.iter(|| renderer.draw_rect(dst, color));
+			});
+		}
+		group.finish();
+	}
+}
+
+/// Covering the whole frame with tiles, as drawing the ground of the map does every frame
+/// (at the default zoom of 4).
+fn full_screen_of_tiles(c: &mut Criterion) {
+	c.bench_function("full screen of tiles 1920x1080", |b| {
+		let mut renderer = SoftwareRenderer::new(SCREEN_DIMS.into(), Color::BLACK);
+		let side = TILE_PX * 4;
+		let columns = (SCREEN_DIMS.0 + side - 1) / side;
+		let rows = (SCREEN_DIMS.1 + side - 1) / side;
+		b.iter(|| {
+			renderer.clear();
+			for coords in Dimensions::from((columns, rows)).iter() {
+				let dst = Rect::xywh(coords.x * side, coords.y * side, side, side);
+				renderer.draw_sprite(dst, tile_sprite(), DrawSpriteEffects::none());
+			}
+		});
+	});
+}
+
+criterion_group!(benches, draw_sprite, clear, draw_rect, full_screen_of_tiles);
+criterion_main!(benches);
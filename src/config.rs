@@ -0,0 +1,228 @@
+//! The settings of the player, kept in a local TOML file.
+//!
+//! A missing file is written with the default settings (so that there is something to edit).
+//! Each setting of the file that is missing or invalid falls back to its default
+//! (with a warning if it is invalid), a typo in the file is no reason to prevent playing.
+//!
+//! Replays don't record the settings, a replay played with other key bindings or another
+//! difficulty won't replay the same run.
+
+use std::path::Path;
+
+use serde::{Deserialize, Serialize};
+use winit::event::VirtualKeyCode;
+
+#[derive(Default, Serialize, Deserialize)]
+pub struct Config {
+	pub audio: AudioConfig,
+	pub gameplay: GameplayConfig,
+	pub keys: KeyBindings,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct AudioConfig {
+	/// Multiplies the volume of everything.
+	pub master_volume: f32,
+	#[allow(dead_code)] // There is no music yet, but the setting is there for when there is.
+	pub music_volume: f32,
+	/// When the caravan moves several tiles in one go, a step sound plays every that many tiles
+	/// (starting with the first one), 0 for no step sounds.
+	pub caravan_step_cadence: i32,
+}
+
+impl Default for AudioConfig {
+	fn default() -> AudioConfig {
+		AudioConfig { master_volume: 1.0, music_volume: 1.0, caravan_step_cadence: 2 }
+	}
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct GameplayConfig {
+	pub starting_crystals: i32,
+	pub difficulty: Difficulty,
+	/// The player phase ends by itself (as if the skip turn key was pressed) when the player
+	/// does nothing for that many seconds, 0 to never end it (the default).
+	pub idle_turn_end_seconds: f32,
+	/// The move of an enemy onto the caravan is shown in slow motion.
+	pub slow_motion: bool,
+}
+
+impl Default for GameplayConfig {
+	fn default() -> GameplayConfig {
+		GameplayConfig {
+			starting_crystals: 20,
+			difficulty: Difficulty::Normal,
+			idle_turn_end_seconds: 0.0,
+			slow_motion: true,
+		}
+	}
+}
+
+/// How hard the waves of enemies are (see `Balance::with_difficulty`).
+#[derive(Clone, Copy, Serialize, Deserialize)]
+pub enum Difficulty {
+	Easy,
+	Normal,
+	Hard,
+}
+
+/// The keys of the gameplay actions (the debug keys are not in there).
+/// The key names are the ones of `winit::event::VirtualKeyCode` (like "A", "Space" or "Left").
+#[derive(Serialize, Deserialize)]
+pub struct KeyBindings {
+	pub step_forward: VirtualKeyCode,
+	pub scroll_forward: VirtualKeyCode,
+	pub skip_turn: VirtualKeyCode,
+	pub auto_advance: VirtualKeyCode,
+	pub auto_mining: VirtualKeyCode,
+	pub cycle_tower: VirtualKeyCode,
+	pub cycle_target_priority: VirtualKeyCode,
+	pub hold_fire: VirtualKeyCode,
+	pub fast_forward: VirtualKeyCode,
+	pub game_speed: VirtualKeyCode,
+	pub recenter_camera: VirtualKeyCode,
+	pub zoom_to_fit: VirtualKeyCode,
+	pub pan_left: VirtualKeyCode,
+	pub pan_right: VirtualKeyCode,
+	pub edge_scroll: VirtualKeyCode,
+	pub spawn_indicator: VirtualKeyCode,
+	pub caravan_trail: VirtualKeyCode,
+	pub enemy_moves: VirtualKeyCode,
+	pub weather: VirtualKeyCode,
+	pub console: VirtualKeyCode,
+	/// Only once the run is over.
+	pub new_run: VirtualKeyCode,
+	pub build_mode: VirtualKeyCode,
+	/// The build mode keys (they take precedence over the other bindings while in build mode).
+	pub place_tower: VirtualKeyCode,
+	pub rotate_tower: VirtualKeyCode,
+	pub cursor_up: VirtualKeyCode,
+	pub cursor_down: VirtualKeyCode,
+	pub cursor_left: VirtualKeyCode,
+	pub cursor_right: VirtualKeyCode,
+}
+
+impl Default for KeyBindings {
+	fn default() -> KeyBindings {
+		KeyBindings {
+			step_forward: VirtualKeyCode::Space,
+			scroll_forward: VirtualKeyCode::Return,
+			skip_turn: VirtualKeyCode::S,
+			auto_advance: VirtualKeyCode::A,
+			auto_mining: VirtualKeyCode::M,
+			cycle_tower: VirtualKeyCode::T,
+			cycle_target_priority: VirtualKeyCode::R,
+			hold_fire: VirtualKeyCode::H,
+			fast_forward: VirtualKeyCode::F,
+			game_speed: VirtualKeyCode::G,
+			recenter_camera: VirtualKeyCode::C,
+			zoom_to_fit: VirtualKeyCode::Z,
+			pan_left: VirtualKeyCode::Left,
+			pan_right: VirtualKeyCode::Right,
+			edge_scroll: VirtualKeyCode::E,
+			spawn_indicator: VirtualKeyCode::I,
+			caravan_trail: VirtualKeyCode::P,
+			enemy_moves: VirtualKeyCode::O,
+			weather: VirtualKeyCode::W,
+			console: VirtualKeyCode::Tab,
+			new_run: VirtualKeyCode::N,
+			build_mode: VirtualKeyCode::B,
+			place_tower: VirtualKeyCode::Return,
+			rotate_tower: VirtualKeyCode::Q,
+			cursor_up: VirtualKeyCode::Up,
+			cursor_down: VirtualKeyCode::Down,
+			cursor_left: VirtualKeyCode::Left,
+			cursor_right: VirtualKeyCode::Right,
+		}
+	}
+}
+
+impl Config {
+	/// Never fails, the problems are logged and the defaults are used instead.
+	pub fn load_or_default(path: &Path) -> Config {
+		let text = match std::fs::read_to_string(path) {
+			Ok(text) => text,
+			Err(error) if error.kind() == std::io::ErrorKind::NotFound => {
+				log::info!(
+					"no config file at \"{}\", writing the default one",
+					path.display()
+				);
+				let config = Config::default();
+				if let Err(error) = config.save(path) {
+					log::warn!(
+						"failed to write the config \"{}\" ({error})",
+						path.display()
+					);
+				}
+				return config;
+			},
+			Err(error) => {
+				log::warn!(
+					"failed to read the config \"{}\" ({error}), using the default one",
+					path.display()
+				);
+				return Config::default();
+			},
+		};
+		let file_table: toml::Table = match text.parse() {
+			Ok(table) => table,
+			Err(error) => {
+				log::warn!(
+					"failed to parse the config \"{}\" ({error}), using the default one",
+					path.display()
+				);
+				return Config::default();
+			},
+		};
+		Config::from_table_with_fallbacks(file_table)
+	}
+
+	/// The settings of the table are put one by one over the default ones, the ones that
+	/// would make the config invalid are left out (so they keep their default value).
+	fn from_table_with_fallbacks(file_table: toml::Table) -> Config {
+		let mut table = toml::Table::try_from(Config::default()).unwrap();
+		for (section_name, section) in file_table {
+			let (Some(toml::Value::Table(default_section)), toml::Value::Table(section)) =
+				(table.get(&section_name), section)
+			else {
+				log::warn!("config: unknown section \"{section_name}\"");
+				continue;
+			};
+			let default_section = default_section.clone();
+			for (name, value) in section {
+				if !default_section.contains_key(&name) {
+					log::warn!("config: unknown setting \"{section_name}.{name}\"");
+					continue;
+				}
+				// TOML has `inf` and `nan`, no setting makes sense with these.
+				if matches!(value, toml::Value::Float(float) if !float.is_finite()) {
+					log::warn!(
+						"config: invalid value {value} for \"{section_name}.{name}\" \
+						(not a finite number), using the default one"
+					);
+					continue;
+				}
+				let mut tentative_table = table.clone();
+				if let Some(toml::Value::Table(tentative_section)) =
+					tentative_table.get_mut(&section_name)
+				{
+					tentative_section.insert(name.clone(), value.clone());
+				}
+				match tentative_table.clone().try_into::<Config>() {
+					Ok(_) => table = tentative_table,
+					Err(error) => log::warn!(
+						"config: invalid value {value} for \"{section_name}.{name}\" ({}), \
+						using the default one",
+						error.message()
+					),
+				}
+			}
+		}
+		table.try_into().unwrap()
+	}
+
+	pub fn save(&self, path: &Path) -> Result<(), std::io::Error> {
+		let text = toml::to_string_pretty(self).unwrap();
+		std::fs::write(path, text)
+	}
+}
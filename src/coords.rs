@@ -4,6 +4,12 @@ pub struct Coords {
 	pub x: i32,
 	pub y: i32,
 }
+/// Like `(3, 4)`.
+impl std::fmt::Display for Coords {
+	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+		write!(f, "({}, {})", self.x, self.y)
+	}
+}
 impl From<(i32, i32)> for Coords {
 	fn from((x, y): (i32, i32)) -> Coords {
 		Coords { x, y }
@@ -17,6 +23,24 @@ impl std::ops::Mul<i32> for Coords {
 		self
 	}
 }
+/// Rounds to the nearest integer coordinates.
+impl std::ops::Mul<f32> for Coords {
+	type Output = Coords;
+	fn mul(mut self, rhs: f32) -> Coords {
+		self.x = (self.x as f32 * rhs).round() as i32;
+		self.y = (self.y as f32 * rhs).round() as i32;
+		self
+	}
+}
+/// Adds the coords as if the right-hand side was a `CoordsDelta`.
+impl std::ops::Add<Coords> for Coords {
+	type Output = Coords;
+	fn add(mut self, rhs: Coords) -> Coords {
+		self.x += rhs.x;
+		self.y += rhs.y;
+		self
+	}
+}
 impl std::ops::Add<CoordsDelta> for Coords {
 	type Output = Coords;
 	fn add(mut self, rhs: CoordsDelta) -> Coords {
@@ -55,16 +79,30 @@ impl std::ops::Sub<Coords> for Coords {
 impl Coords {
 	/// Manhattan distance.
 	pub fn _dist(self, rhs: Coords) -> i32 {
-		(self.x.abs_diff(rhs.x) + self.y.abs_diff(rhs.y)) as i32
+		(self - rhs).manhattan()
 	}
 }
 
+/// Coordinates of a tile in the map grid, not to be mixed up with `PixelCoords`.
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub struct TileCoords(pub Coords);
+
+/// Coordinates of a pixel on the screen, not to be mixed up with `TileCoords`.
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub struct PixelCoords(pub Coords);
+
 /// Represents a difference between two `Coords`s.
 #[derive(Clone, Copy, PartialEq, Eq, Debug)]
 pub struct CoordsDelta {
 	pub dx: i32,
 	pub dy: i32,
 }
+/// Like `(+1, -2)`, the signs tell it apart from `Coords`.
+impl std::fmt::Display for CoordsDelta {
+	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+		write!(f, "({:+}, {:+})", self.dx, self.dy)
+	}
+}
 impl CoordsDelta {
 	pub const UP: Self = CoordsDelta { dx: 0, dy: -1 };
 	pub const RIGHT: Self = CoordsDelta { dx: 1, dy: 0 };
@@ -74,6 +112,21 @@ impl CoordsDelta {
 	pub fn iter_4_directions() -> impl Iterator<Item = CoordsDelta> {
 		[Self::UP, Self::RIGHT, Self::DOWN, Self::LEFT].into_iter()
 	}
+
+	/// The squared length of the delta as the crow flies, to compare distances without roots.
+	pub fn length_squared(self) -> i32 {
+		self.dx * self.dx + self.dy * self.dy
+	}
+
+	/// The length of the delta when only moving horizontally or vertically.
+	pub fn manhattan(self) -> i32 {
+		self.dx.abs() + self.dy.abs()
+	}
+
+	/// A quarter turn clockwise (on the screen, where y goes down).
+	pub fn rotated_clockwise(self) -> CoordsDelta {
+		CoordsDelta { dx: -self.dy, dy: self.dx }
+	}
 }
 impl From<(i32, i32)> for CoordsDelta {
 	fn from((dx, dy): (i32, i32)) -> CoordsDelta {
@@ -98,6 +151,28 @@ impl std::ops::Neg for CoordsDelta {
 		self
 	}
 }
+impl std::ops::Add<CoordsDelta> for CoordsDelta {
+	type Output = CoordsDelta;
+	fn add(mut self, rhs: CoordsDelta) -> CoordsDelta {
+		self.dx += rhs.dx;
+		self.dy += rhs.dy;
+		self
+	}
+}
+impl std::ops::AddAssign<CoordsDelta> for CoordsDelta {
+	fn add_assign(&mut self, rhs: CoordsDelta) {
+		self.dx += rhs.dx;
+		self.dy += rhs.dy;
+	}
+}
+impl std::ops::Sub<CoordsDelta> for CoordsDelta {
+	type Output = CoordsDelta;
+	fn sub(mut self, rhs: CoordsDelta) -> CoordsDelta {
+		self.dx -= rhs.dx;
+		self.dy -= rhs.dy;
+		self
+	}
+}
 impl std::ops::Mul<i32> for CoordsDelta {
 	type Output = CoordsDelta;
 	fn mul(mut self, rhs: i32) -> CoordsDelta {
@@ -116,11 +191,17 @@ impl std::ops::Div<i32> for CoordsDelta {
 }
 
 /// Represents the (integer) size of a rectangular area (but not its position).
-#[derive(Clone, Copy)]
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
 pub struct Dimensions {
 	pub w: i32,
 	pub h: i32,
 }
+/// Like `12x8`.
+impl std::fmt::Display for Dimensions {
+	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+		write!(f, "{}x{}", self.w, self.h)
+	}
+}
 impl From<(i32, i32)> for Dimensions {
 	fn from((w, h): (i32, i32)) -> Dimensions {
 		Dimensions { w, h }
@@ -136,7 +217,7 @@ impl Dimensions {
 		Dimensions { w: side, h: side }
 	}
 
-	pub fn _area(self) -> usize {
+	pub fn area(self) -> usize {
 		self.w as usize * self.h as usize
 	}
 
@@ -160,6 +241,14 @@ impl std::ops::Mul<i32> for Dimensions {
 		self
 	}
 }
+impl std::ops::Div<i32> for Dimensions {
+	type Output = Dimensions;
+	fn div(mut self, rhs: i32) -> Dimensions {
+		self.w /= rhs;
+		self.h /= rhs;
+		self
+	}
+}
 impl std::ops::Add<CoordsDelta> for Dimensions {
 	type Output = Dimensions;
 	fn add(mut self, rhs: CoordsDelta) -> Dimensions {
@@ -178,52 +267,28 @@ impl std::ops::Sub<CoordsDelta> for Dimensions {
 }
 impl Dimensions {
 	pub fn iter(self) -> IterCoordsRect {
-		IterCoordsRect::new(
-			Rect { top_left: (0, 0).into(), dims: self },
-			IterCoordsRectOrder::TopToBottomThenLeftToRight,
-		)
-	}
-	pub fn iter_left_to_right(self) -> IterCoordsRect {
-		IterCoordsRect::new(
-			Rect { top_left: (0, 0).into(), dims: self },
-			IterCoordsRectOrder::LeftToRightThenTopToBottom,
-		)
+		IterCoordsRect::new(Rect { top_left: (0, 0).into(), dims: self })
 	}
 }
 
+/// Goes through the rows from top to bottom, each row from left to right.
 pub struct IterCoordsRect {
 	current: Coords,
 	rect: Rect,
-	order: IterCoordsRectOrder,
-}
-enum IterCoordsRectOrder {
-	TopToBottomThenLeftToRight,
-	LeftToRightThenTopToBottom,
 }
 impl IterCoordsRect {
-	fn new(rect: Rect, order: IterCoordsRectOrder) -> IterCoordsRect {
-		IterCoordsRect { current: rect.top_left, rect, order }
+	fn new(rect: Rect) -> IterCoordsRect {
+		IterCoordsRect { current: rect.top_left, rect }
 	}
 }
 impl Iterator for IterCoordsRect {
 	type Item = Coords;
 	fn next(&mut self) -> Option<Coords> {
 		let coords = self.current;
-		match self.order {
-			IterCoordsRectOrder::TopToBottomThenLeftToRight => {
-				self.current.x += 1;
-				if !self.rect.contains(self.current) {
-					self.current.x = self.rect.left();
-					self.current.y += 1;
-				}
-			},
-			IterCoordsRectOrder::LeftToRightThenTopToBottom => {
-				self.current.y += 1;
-				if !self.rect.contains(self.current) {
-					self.current.y = self.rect.top();
-					self.current.x += 1;
-				}
-			},
+		self.current.x += 1;
+		if !self.rect.contains(self.current) {
+			self.current.x = self.rect.left();
+			self.current.y += 1;
 		}
 		if self.rect.contains(coords) {
 			Some(coords)
@@ -233,11 +298,17 @@ impl Iterator for IterCoordsRect {
 	}
 }
 
-#[derive(Clone, Copy)]
+#[derive(Clone, Copy, Debug)]
 pub struct Rect {
 	pub top_left: Coords,
 	pub dims: Dimensions,
 }
+/// Like `[3,4 12x8]`, the top left corner then the dimensions.
+impl std::fmt::Display for Rect {
+	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+		write!(f, "[{},{} {}]", self.top_left.x, self.top_left.y, self.dims)
+	}
+}
 impl Rect {
 	pub fn xywh(x: i32, y: i32, w: i32, h: i32) -> Rect {
 		Rect { top_left: (x, y).into(), dims: (w, h).into() }
@@ -250,6 +321,18 @@ impl Rect {
 		}
 	}
 
+	/// The rect of the given `dims` that has the given `center` (see `Rect::center`),
+	/// so that `Rect::from_center_size(center, dims).center() == center` for any `dims`.
+	pub fn from_center_size(center: Coords, dims: Dimensions) -> Rect {
+		Rect { top_left: center - CoordsDelta::from(dims) / 2, dims }
+	}
+
+	/// The middle pixel of the rect. When a dimension is even there are two middle pixels
+	/// and this rounds towards the bottom right one.
+	pub fn center(self) -> Coords {
+		self.top_left + CoordsDelta::from(self.dims) / 2
+	}
+
 	pub fn top(self) -> i32 {
 		self.top_left.y
 	}
@@ -271,7 +354,7 @@ impl Rect {
 	}
 
 	pub fn iter(self) -> IterCoordsRect {
-		IterCoordsRect::new(self, IterCoordsRectOrder::TopToBottomThenLeftToRight)
+		IterCoordsRect::new(self)
 	}
 
 	pub fn add_margin(self, margin: i32) -> Rect {
@@ -280,6 +363,15 @@ impl Rect {
 			dims: self.dims + (margin * 2, margin * 2).into(),
 		}
 	}
+
+	/// The part that is in both rects (with zero dims if there is none).
+	pub fn intersection(self, other: Rect) -> Rect {
+		let left = self.left().max(other.left());
+		let top = self.top().max(other.top());
+		let right = self.right_excluded().min(other.right_excluded());
+		let bottom = self.bottom_excluded().min(other.bottom_excluded());
+		Rect::xywh(left, top, (right - left).max(0), (bottom - top).max(0))
+	}
 }
 
 #[derive(Clone)]
@@ -311,6 +403,31 @@ impl<T> Grid<T> {
 			None
 		}
 	}
+
+	/// Like `get` but for `coords` that are known to be in the grid, being out of bounds is a bug.
+	///
+	/// Debug builds check it and panic with the coords and dims. Release builds don't, so an `x`
+	/// out of bounds gives a tile of another row (and a `y` out of bounds still panics).
+	#[track_caller]
+	pub fn at(&self, coords: Coords) -> &T {
+		&self.content[self.index_of_coords_in_bounds(coords)]
+	}
+	/// Like `get_mut` but see `at`.
+	#[track_caller]
+	pub fn at_mut(&mut self, coords: Coords) -> &mut T {
+		let index = self.index_of_coords_in_bounds(coords);
+		&mut self.content[index]
+	}
+
+	#[track_caller]
+	fn index_of_coords_in_bounds(&self, coords: Coords) -> usize {
+		debug_assert!(
+			self.dims.contains(coords),
+			"coords {coords} out of the bounds of a grid of dims {}",
+			self.dims
+		);
+		(coords.y * self.dims.w + coords.x) as usize
+	}
 }
 
 impl<T: Clone> Grid<T> {
@@ -321,7 +438,7 @@ impl<T: Clone> Grid<T> {
 			assert_eq!(self.dims.h, rhs.dims.h);
 			Grid::new((self.dims.w + rhs.dims.w, self.dims.h).into(), |coords| {
 				if coords.x < self.dims.w {
-					self.get(coords).unwrap().clone()
+					self.at(coords).clone()
 				} else {
 					rhs.get(coords - CoordsDelta::from((self.dims.w, 0)))
 						.unwrap()
@@ -331,3 +448,42 @@ impl<T: Clone> Grid<T> {
 		}
 	}
 }
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn rect_from_center_size_round_trips() {
+		let center = Coords { x: 10, y: -5 };
+		for (w, h) in [(1, 1), (2, 2), (3, 4), (4, 3), (7, 7), (8, 10)] {
+			let dims = Dimensions { w, h };
+			let rect = Rect::from_center_size(center, dims);
+			assert_eq!(rect.center(), center);
+			assert_eq!(rect.dims, dims);
+		}
+		// With an even side, the center is the bottom right of the two middle pixels.
+		assert_eq!(Rect::xywh(0, 0, 4, 4).center(), Coords { x: 2, y: 2 });
+		assert_eq!(Rect::xywh(0, 0, 3, 3).center(), Coords { x: 1, y: 1 });
+	}
+
+	#[test]
+	fn coords_ops() {
+		let a = Coords { x: 3, y: -2 };
+		let b = Coords { x: -1, y: 5 };
+		assert_eq!(a + b, Coords { x: 2, y: 3 });
+		// Multiplying by a float rounds to the nearest pixel.
+		assert_eq!(a * 1.5, Coords { x: 5, y: -3 });
+		assert_eq!(a * 0.0, Coords { x: 0, y: 0 });
+		assert_eq!(Dimensions { w: 9, h: 4 } / 2, Dimensions { w: 4, h: 2 });
+		let delta = CoordsDelta { dx: 3, dy: -4 };
+		assert_eq!(
+			delta + CoordsDelta { dx: -1, dy: 1 },
+			CoordsDelta { dx: 2, dy: -3 }
+		);
+		assert_eq!(delta.length_squared(), 25);
+		assert_eq!(delta.manhattan(), 7);
+		assert_eq!((-delta).manhattan(), 7);
+		assert_eq!(CoordsDelta { dx: 0, dy: 0 }.manhattan(), 0);
+	}
+}
@@ -0,0 +1,448 @@
+//! A compact binary format for the map grid (for save files and such).
+//!
+//! It starts with a header: the `MAGIC` bytes, the format version (one byte), then the width
+//! and the height of the grid (little endian `u32`s). Then the tiles follow, row by row,
+//! each being its ground followed by its object (if any), see `write_tile`.
+//!
+//! The small object animations are not saved (they are only cosmetic).
+
+use crate::coords::*;
+use crate::renderer::Color;
+use crate::{Enemy, Ground, Obj, Path, TargetPriority, Team, Tile, Tower};
+
+const MAGIC: &[u8; 4] = b"TDGR";
+const VERSION: u8 = 5;
+
+#[derive(Debug)]
+pub enum GridBytesError {
+	NotAGrid,
+	/// Probably made by a newer version of the game.
+	UnsupportedVersion(u8),
+	UnexpectedEnd,
+	/// Too big to fit in an `i32` or too many tiles for the data that follows.
+	InvalidDimensions {
+		w: u32,
+		h: u32,
+	},
+	InvalidTag {
+		what: &'static str,
+		tag: u8,
+	},
+	/// A path direction that is not one of the 4 directions, or a part that does not point
+	/// to the tile of its big object (up and/or left of it).
+	InvalidDirection {
+		what: &'static str,
+		direction: CoordsDelta,
+	},
+	TrailingBytes,
+}
+
+impl std::fmt::Display for GridBytesError {
+	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+		match self {
+			GridBytesError::NotAGrid => write!(f, "this is not a grid (bad magic bytes)"),
+			GridBytesError::UnsupportedVersion(version) => write!(
+				f,
+				"the grid format version is {version} but only version {VERSION} is supported"
+			),
+			GridBytesError::UnexpectedEnd => write!(f, "the grid data ends too early"),
+			GridBytesError::InvalidDimensions { w, h } => {
+				write!(f, "invalid grid dimensions {w}x{h}")
+			},
+			GridBytesError::InvalidTag { what, tag } => write!(f, "invalid {what} tag {tag}"),
+			GridBytesError::InvalidDirection { what, direction } => {
+				write!(f, "invalid {what} ({}, {})", direction.dx, direction.dy)
+			},
+			GridBytesError::TrailingBytes => write!(f, "there is stuff after the grid data"),
+		}
+	}
+}
+
+impl Grid<Tile> {
+	pub fn to_bytes(&self) -> Vec<u8> {
+		let mut bytes = vec![];
+		bytes.extend_from_slice(MAGIC);
+		bytes.push(VERSION);
+		bytes.extend_from_slice(&(self.dims.w as u32).to_le_bytes());
+		bytes.extend_from_slice(&(self.dims.h as u32).to_le_bytes());
+		for coords in self.dims.iter() {
+			write_tile(&mut bytes, self.at(coords));
+		}
+		bytes
+	}
+
+	pub fn from_bytes(bytes: &[u8]) -> Result<Grid<Tile>, GridBytesError> {
+		let mut reader = ByteReader { bytes };
+		if reader.take(MAGIC.len())? != MAGIC {
+			return Err(GridBytesError::NotAGrid);
+		}
+		let version = reader.u8()?;
+		if version != VERSION {
+			return Err(GridBytesError::UnsupportedVersion(version));
+		}
+		let (raw_w, raw_h) = (reader.u32()?, reader.u32()?);
+		let invalid_dims = || GridBytesError::InvalidDimensions { w: raw_w, h: raw_h };
+		let (Ok(w), Ok(h)) = (i32::try_from(raw_w), i32::try_from(raw_h)) else {
+			return Err(invalid_dims());
+		};
+		// A tile takes at least 2 bytes (its ground tag and its object tag), so the dimensions
+		// are checked against the data before allocating anything for them.
+		let tile_count = w.checked_mul(h).ok_or_else(invalid_dims)? as usize;
+		if reader.bytes.len() / 2 < tile_count {
+			return Err(invalid_dims());
+		}
+		let dims: Dimensions = (w, h).into();
+		let mut tiles = Vec::with_capacity(dims.area());
+		for _ in 0..dims.area() {
+			tiles.push(read_tile(&mut reader)?);
+		}
+		if !reader.bytes.is_empty() {
+			return Err(GridBytesError::TrailingBytes);
+		}
+		// `Grid::new` visits the coords in the same order as `Dimensions::iter` (used to write).
+		let mut tiles = tiles.into_iter();
+		Ok(Grid::new(dims, |_coords| tiles.next().unwrap()))
+	}
+}
+
+/// A tile is its ground then its object.
+///
+/// The ground is a tag byte (0 grass, 1 path, 2 water, 3 bridge) followed by the visual variant
+/// (`u32`) for grass or by the path (see `write_path`) for path and bridge.
+///
+/// The object is a tag byte (0 none, 1 caravan, 2 tree, 3 rock, 4 crystal, 5 enemy, 6 tower,
+/// 7 part) followed by the visual variant (`u32`) for trees, rocks and crystals, or by the actions,
+/// hp, fire and shield (`i32`s) for enemies and towers. Enemies then have a byte telling if they
+/// have a base tint (followed by its 4 RGBA bytes if so) and a variant byte (0 basic,
+/// 1 regenerator followed by its max hp and its turns since hit as `i32`s), and towers a variant
+/// byte, a target priority byte (0 first, 1 last, 2 strongest, 3 weakest) and an enabled byte
+/// (0 or 1). Parts have the direction to their anchor (two `i8`s, x then y) and a team byte
+/// (0 none, 1 player, 2 enemy).
+fn write_tile(bytes: &mut Vec<u8>, tile: &Tile) {
+	match &tile.ground {
+		Ground::Grass { visual_variant } => {
+			bytes.push(0);
+			bytes.extend_from_slice(&visual_variant.to_le_bytes());
+		},
+		Ground::Path(path) => {
+			bytes.push(1);
+			write_path(bytes, path);
+		},
+		Ground::Water => bytes.push(2),
+		Ground::Bridge(path) => {
+			bytes.push(3);
+			write_path(bytes, path);
+		},
+	}
+	match &tile.obj {
+		None => bytes.push(0),
+		Some(Obj::Caravan) => bytes.push(1),
+		Some(Obj::Tree { visual_variant }) => {
+			bytes.push(2);
+			bytes.extend_from_slice(&visual_variant.to_le_bytes());
+		},
+		Some(Obj::Rock { visual_variant }) => {
+			bytes.push(3);
+			bytes.extend_from_slice(&visual_variant.to_le_bytes());
+		},
+		Some(Obj::Crystal { visual_variant }) => {
+			bytes.push(4);
+			bytes.extend_from_slice(&visual_variant.to_le_bytes());
+		},
+		Some(Obj::Enemy { actions, hp, fire, shield, base_tint, variant, .. }) => {
+			bytes.push(5);
+			for value in [actions, hp, fire, shield] {
+				bytes.extend_from_slice(&value.to_le_bytes());
+			}
+			match base_tint {
+				None => bytes.push(0),
+				Some(color) => {
+					bytes.push(1);
+					bytes.extend_from_slice(&color.raw());
+				},
+			}
+			match variant {
+				Enemy::Basic => bytes.push(0),
+				Enemy::Regenerator { max_hp, turns_since_hit } => {
+					bytes.push(1);
+					bytes.extend_from_slice(&max_hp.to_le_bytes());
+					bytes.extend_from_slice(&turns_since_hit.to_le_bytes());
+				},
+			}
+		},
+		Some(Obj::Tower { actions, hp, fire, shield, variant, target_priority, enabled, .. }) => {
+			bytes.push(6);
+			for value in [actions, hp, fire, shield] {
+				bytes.extend_from_slice(&value.to_le_bytes());
+			}
+			bytes.push(match variant {
+				Tower::Basic => 0,
+				Tower::Pink => 1,
+				Tower::Blue => 2,
+				Tower::Fortress => 3,
+				Tower::Storm => 4,
+				Tower::Support => 5,
+			});
+			bytes.push(match target_priority {
+				TargetPriority::First => 0,
+				TargetPriority::Last => 1,
+				TargetPriority::Strongest => 2,
+				TargetPriority::Weakest => 3,
+			});
+			bytes.push(*enabled as u8);
+		},
+		Some(Obj::Part { to_anchor, team }) => {
+			bytes.push(7);
+			bytes.push(to_anchor.dx as i8 as u8);
+			bytes.push(to_anchor.dy as i8 as u8);
+			bytes.push(match team {
+				None => 0,
+				Some(Team::Player) => 1,
+				Some(Team::Enemy) => 2,
+			});
+		},
+	}
+}
+
+fn read_tile(reader: &mut ByteReader) -> Result<Tile, GridBytesError> {
+	let ground = match reader.u8()? {
+		0 => Ground::Grass { visual_variant: reader.u32()? },
+		1 => Ground::Path(read_path(reader)?),
+		2 => Ground::Water,
+		3 => Ground::Bridge(read_path(reader)?),
+		tag => return Err(GridBytesError::InvalidTag { what: "ground", tag }),
+	};
+	let obj = match reader.u8()? {
+		0 => None,
+		1 => Some(Obj::Caravan),
+		2 => Some(Obj::Tree { visual_variant: reader.u32()? }),
+		3 => Some(Obj::Rock { visual_variant: reader.u32()? }),
+		4 => Some(Obj::Crystal { visual_variant: reader.u32()? }),
+		5 => {
+			let (actions, hp, fire) = (reader.i32()?, reader.i32()?, reader.i32()?);
+			let shield = reader.i32()?;
+			let base_tint = match reader.u8()? {
+				0 => None,
+				1 => {
+					let [r, g, b, a] = [reader.u8()?, reader.u8()?, reader.u8()?, reader.u8()?];
+					Some(Color::rgba_u8(r, g, b, a))
+				},
+				tag => return Err(GridBytesError::InvalidTag { what: "base tint", tag }),
+			};
+			let variant = match reader.u8()? {
+				0 => Enemy::Basic,
+				1 => Enemy::Regenerator { max_hp: reader.i32()?, turns_since_hit: reader.i32()? },
+				tag => return Err(GridBytesError::InvalidTag { what: "enemy variant", tag }),
+			};
+			Some(Obj::Enemy {
+				actions,
+				hp,
+				fire,
+				shield,
+				alive_animation: None,
+				colored_animation: None,
+				base_tint,
+				variant,
+			})
+		},
+		6 => {
+			let (actions, hp, fire) = (reader.i32()?, reader.i32()?, reader.i32()?);
+			let shield = reader.i32()?;
+			let variant = match reader.u8()? {
+				0 => Tower::Basic,
+				1 => Tower::Pink,
+				2 => Tower::Blue,
+				3 => Tower::Fortress,
+				4 => Tower::Storm,
+				5 => Tower::Support,
+				tag => return Err(GridBytesError::InvalidTag { what: "tower variant", tag }),
+			};
+			let target_priority = match reader.u8()? {
+				0 => TargetPriority::First,
+				1 => TargetPriority::Last,
+				2 => TargetPriority::Strongest,
+				3 => TargetPriority::Weakest,
+				tag => return Err(GridBytesError::InvalidTag { what: "target priority", tag }),
+			};
+			let enabled = match reader.u8()? {
+				0 => false,
+				1 => true,
+				tag => return Err(GridBytesError::InvalidTag { what: "enabled", tag }),
+			};
+			Some(Obj::Tower {
+				actions,
+				hp,
+				fire,
+				shield,
+				colored_animation: None,
+				variant,
+				target_priority,
+				enabled,
+			})
+		},
+		7 => {
+			let to_anchor = read_direction(reader)?;
+			// Big objects are at most 2x2 and anchored at their top left tile.
+			if ![(-1, 0), (0, -1), (-1, -1)]
+				.map(CoordsDelta::from)
+				.contains(&to_anchor)
+			{
+				return Err(GridBytesError::InvalidDirection {
+					what: "part anchor direction",
+					direction: to_anchor,
+				});
+			}
+			let team = match reader.u8()? {
+				0 => None,
+				1 => Some(Team::Player),
+				2 => Some(Team::Enemy),
+				tag => return Err(GridBytesError::InvalidTag { what: "team", tag }),
+			};
+			Some(Obj::Part { to_anchor, team })
+		},
+		tag => return Err(GridBytesError::InvalidTag { what: "object", tag }),
+	};
+	Ok(Tile { ground, obj })
+}
+
+/// The forward and backward directions (each as two `i8`s, x then y)
+/// followed by the distance (`i32`).
+fn write_path(bytes: &mut Vec<u8>, path: &Path) {
+	for direction in [path.forward, path.backward] {
+		bytes.push(direction.dx as i8 as u8);
+		bytes.push(direction.dy as i8 as u8);
+	}
+	bytes.extend_from_slice(&path.distance.to_le_bytes());
+}
+
+/// Following a path that does not go in one of the 4 directions (like one that stays in place)
+/// would never end, so these are rejected.
+fn read_path(reader: &mut ByteReader) -> Result<Path, GridBytesError> {
+	let forward = read_direction(reader)?;
+	let backward = read_direction(reader)?;
+	for direction in [forward, backward] {
+		if !CoordsDelta::iter_4_directions().any(|valid| valid == direction) {
+			return Err(GridBytesError::InvalidDirection { what: "path direction", direction });
+		}
+	}
+	let distance = reader.i32()?;
+	Ok(Path { forward, backward, distance })
+}
+
+fn read_direction(reader: &mut ByteReader) -> Result<CoordsDelta, GridBytesError> {
+	Ok((reader.u8()? as i8 as i32, reader.u8()? as i8 as i32).into())
+}
+
+/// Reads from the front of a byte slice, failing (instead of panicking) if it is too short.
+struct ByteReader<'a> {
+	bytes: &'a [u8],
+}
+
+impl<'a> ByteReader<'a> {
+	fn take(&mut self, len: usize) -> Result<&'a [u8], GridBytesError> {
+		if self.bytes.len() < len {
+			return Err(GridBytesError::UnexpectedEnd);
+		}
+		let (taken, rest) = self.bytes.split_at(len);
+		self.bytes = rest;
+		Ok(taken)
+	}
+
+	fn u8(&mut self) -> Result<u8, GridBytesError> {
+		Ok(self.take(1)?[0])
+	}
+
+	fn u32(&mut self) -> Result<u32, GridBytesError> {
+		Ok(u32::from_le_bytes(self.take(4)?.try_into().unwrap()))
+	}
+
+	fn i32(&mut self) -> Result<i32, GridBytesError> {
+		Ok(i32::from_le_bytes(self.take(4)?.try_into().unwrap()))
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::{rand_wrapper::seed_rng, Biome, Chunk};
+
+	fn header(w: u32, h: u32) -> Vec<u8> {
+		let mut bytes = MAGIC.to_vec();
+		bytes.push(VERSION);
+		bytes.extend_from_slice(&w.to_le_bytes());
+		bytes.extend_from_slice(&h.to_le_bytes());
+		bytes
+	}
+
+	#[test]
+	fn generated_chunk_round_trips() {
+		seed_rng(1127);
+		let mut grid = Chunk::generate((10, 10).into(), None, Biome::Plains).grid;
+		// Some objects that chunks don't have on their own.
+		let mut enemy = crate::Map::roll_enemy();
+		if let Obj::Enemy { base_tint, .. } = &mut enemy {
+			*base_tint = Some(Color::rgba_u8(1, 2, 3, 4));
+		}
+		grid.at_mut((0, 0).into()).obj = Some(enemy);
+		grid.at_mut((1, 0).into()).obj = Some(Tower::Storm.new_obj());
+		grid.at_mut((2, 0).into()).obj = Some(Obj::Caravan);
+		let bytes = grid.to_bytes();
+		let loaded = Grid::from_bytes(&bytes).unwrap();
+		assert_eq!(loaded.dims, grid.dims);
+		assert_eq!(loaded.to_bytes(), bytes);
+	}
+
+	#[test]
+	fn rejects_bad_data() {
+		assert!(matches!(
+			Grid::from_bytes(b"nope"),
+			Err(GridBytesError::NotAGrid)
+		));
+		let mut bytes = header(1, 1);
+		bytes[MAGIC.len()] = VERSION + 1;
+		assert!(matches!(
+			Grid::from_bytes(&bytes),
+			Err(GridBytesError::UnsupportedVersion(_))
+		));
+		// Negative once read as `i32`s.
+		assert!(matches!(
+			Grid::from_bytes(&header(u32::MAX, 1)),
+			Err(GridBytesError::InvalidDimensions { .. })
+		));
+		// More tiles than an `i32` can count, or than there is data for.
+		for (w, h) in [(1 << 20, 1 << 20), (1000, 1000)] {
+			assert!(matches!(
+				Grid::from_bytes(&header(w, h)),
+				Err(GridBytesError::InvalidDimensions { .. })
+			));
+		}
+		let mut bytes = header(1, 1);
+		bytes.extend_from_slice(&[9, 0]);
+		assert!(matches!(
+			Grid::from_bytes(&bytes),
+			Err(GridBytesError::InvalidTag { what: "ground", tag: 9 })
+		));
+		bytes[MAGIC.len() + 9] = 2;
+		assert!(Grid::from_bytes(&bytes).is_ok());
+		// A path that stays in place, and a part that is its own anchor.
+		let mut path_bytes = header(1, 1);
+		path_bytes.extend_from_slice(&[1, 0, 0, 0xff, 0]);
+		path_bytes.extend_from_slice(&0i32.to_le_bytes());
+		path_bytes.push(0);
+		assert!(matches!(
+			Grid::from_bytes(&path_bytes),
+			Err(GridBytesError::InvalidDirection { what: "path direction", .. })
+		));
+		let mut part_bytes = header(1, 1);
+		part_bytes.extend_from_slice(&[2, 7, 0, 0, 0]);
+		assert!(matches!(
+			Grid::from_bytes(&part_bytes),
+			Err(GridBytesError::InvalidDirection { what: "part anchor direction", .. })
+		));
+		bytes.push(0);
+		assert!(matches!(
+			Grid::from_bytes(&bytes),
+			Err(GridBytesError::TrailingBytes)
+		));
+	}
+}
@@ -0,0 +1,150 @@
+//! The best runs, kept in a local JSON file.
+//!
+//! Runs are ranked by the distance traveled by the caravan, ties are broken by the turn count
+//! (fewer turns is better).
+
+use std::{
+	fs::File,
+	io::{BufReader, BufWriter},
+	path::Path,
+	time::{SystemTime, UNIX_EPOCH},
+};
+
+use serde::{Deserialize, Serialize};
+
+#[derive(Clone, Serialize, Deserialize)]
+pub struct LeaderboardEntry {
+	pub distance_traveled: i32,
+	pub turn_count: i32,
+	/// When the run ended, in seconds since the Unix epoch.
+	pub unix_time: u64,
+}
+
+impl LeaderboardEntry {
+	/// The entry for a run that ends now.
+	pub fn now(distance_traveled: i32, turn_count: i32) -> LeaderboardEntry {
+		let unix_time = SystemTime::now()
+			.duration_since(UNIX_EPOCH)
+			.map(|duration| duration.as_secs())
+			.unwrap_or(0);
+		LeaderboardEntry { distance_traveled, turn_count, unix_time }
+	}
+
+	/// The (UTC) date of the end of the run, as `YYYY-MM-DD`.
+	pub fn date(&self) -> String {
+		// Days to civil date, see http://howardhinnant.github.io/date_algorithms.html#civil_from_days
+		let days = (self.unix_time / (60 * 60 * 24)) as i64 + 719468;
+		let era = days.div_euclid(146097);
+		let day_of_era = days.rem_euclid(146097);
+		let year_of_era =
+			(day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146096) / 365;
+		let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+		let shifted_month = (5 * day_of_year + 2) / 153;
+		let day = day_of_year - (153 * shifted_month + 2) / 5 + 1;
+		let month = if shifted_month < 10 {
+			shifted_month + 3
+		} else {
+			shifted_month - 9
+		};
+		let year = year_of_era + era * 400 + (month <= 2) as i64;
+		format!("{year:04}-{month:02}-{day:02}")
+	}
+
+	/// The greater the better.
+	fn score(&self) -> (i32, i32) {
+		(self.distance_traveled, -self.turn_count)
+	}
+}
+
+#[derive(Debug)]
+pub enum LeaderboardError {
+	Io(std::io::Error),
+	Json(serde_json::Error),
+}
+
+impl std::fmt::Display for LeaderboardError {
+	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+		match self {
+			LeaderboardError::Io(error) => write!(f, "{error}"),
+			LeaderboardError::Json(error) => write!(f, "{error}"),
+		}
+	}
+}
+
+impl From<std::io::Error> for LeaderboardError {
+	fn from(error: std::io::Error) -> LeaderboardError {
+		LeaderboardError::Io(error)
+	}
+}
+
+impl From<serde_json::Error> for LeaderboardError {
+	fn from(error: serde_json::Error) -> LeaderboardError {
+		LeaderboardError::Json(error)
+	}
+}
+
+/// The best runs, from the best to the worst.
+#[derive(Default, Serialize, Deserialize)]
+pub struct Leaderboard {
+	entries: Vec<LeaderboardEntry>,
+}
+
+impl Leaderboard {
+	/// Only that many runs are kept.
+	pub const MAX_ENTRIES: usize = 10;
+
+	/// A missing or unreadable file gives an empty leaderboard (that will overwrite the file
+	/// when saved), there is no reason to prevent playing because of it.
+	pub fn load_or_empty(path: &Path) -> Leaderboard {
+		match Leaderboard::load(path) {
+			Ok(leaderboard) => leaderboard,
+			Err(LeaderboardError::Io(error)) if error.kind() == std::io::ErrorKind::NotFound => {
+				log::info!("no leaderboard file at \"{}\" yet", path.display());
+				Leaderboard::default()
+			},
+			Err(error) => {
+				log::warn!(
+					"failed to load the leaderboard \"{}\" ({error}), starting a new one",
+					path.display()
+				);
+				Leaderboard::default()
+			},
+		}
+	}
+
+	fn load(path: &Path) -> Result<Leaderboard, LeaderboardError> {
+		let mut leaderboard: Leaderboard =
+			serde_json::from_reader(BufReader::new(File::open(path)?))?;
+		// The file could have been edited by hand.
+		leaderboard
+			.entries
+			.sort_by_key(|entry| std::cmp::Reverse(entry.score()));
+		leaderboard.entries.truncate(Leaderboard::MAX_ENTRIES);
+		Ok(leaderboard)
+	}
+
+	pub fn save(&self, path: &Path) -> Result<(), LeaderboardError> {
+		serde_json::to_writer_pretty(BufWriter::new(File::create(path)?), self)?;
+		Ok(())
+	}
+
+	/// Returns the rank (0 is the best) of the entry if it made it into the leaderboard.
+	pub fn add(&mut self, entry: LeaderboardEntry) -> Option<usize> {
+		// After the entries that are at least as good, so that older runs win ties.
+		let rank = self
+			.entries
+			.iter()
+			.position(|other| entry.score() > other.score())
+			.unwrap_or(self.entries.len());
+		if Leaderboard::MAX_ENTRIES <= rank {
+			return None;
+		}
+		self.entries.insert(rank, entry);
+		self.entries.truncate(Leaderboard::MAX_ENTRIES);
+		Some(rank)
+	}
+
+	pub fn entries(&self) -> &[LeaderboardEntry] {
+		&self.entries
+	}
+}
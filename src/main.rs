@@ -1,21 +1,55 @@
+mod config;
 mod coords;
+mod grid_bytes;
+mod leaderboard;
 mod renderer;
+mod replay;
+mod tween;
 
+use crate::config::*;
 use crate::coords::*;
+use crate::leaderboard::*;
 use crate::renderer::*;
+use crate::replay::*;
+use crate::tween::*;
 
 mod rand_wrapper {
 	use rand::distributions::uniform::{SampleRange, SampleUniform};
+	use rand::{rngs::StdRng, thread_rng, Rng, SeedableRng};
+	use std::cell::RefCell;
+
+	thread_local! {
+		/// The RNG of the game logic, it can be seeded so that a run can be reproduced.
+		static GAME_RNG: RefCell<StdRng> = RefCell::new(StdRng::from_entropy());
+	}
+
+	pub fn seed_rng(seed: u64) {
+		GAME_RNG.with(|rng| *rng.borrow_mut() = StdRng::seed_from_u64(seed));
+	}
+
+	pub fn random_seed() -> u64 {
+		thread_rng().gen()
+	}
 
 	/// Just a wrapper around `rand::rng::Rng::gen_range`.
 	/// It gets a random value in the given range,
-	/// using the thread-local RNG given by `rand::thread_rng`.
+	/// using the game RNG (that can be seeded with `seed_rng`).
 	pub fn rand_range<T, R>(range: R) -> T
 	where
 		T: SampleUniform,
 		R: SampleRange<T>,
 	{
-		use rand::{thread_rng, Rng};
+		GAME_RNG.with(|rng| rng.borrow_mut().gen_range(range))
+	}
+
+	/// Same as `rand_range` but for randomness that has no effect on the game (like some
+	/// visual effects) and that happens at a pace that depends on the framerate.
+	/// It does not use the game RNG so that a seeded run plays the same at any framerate.
+	pub fn cosmetic_rand_range<T, R>(range: R) -> T
+	where
+		T: SampleUniform,
+		R: SampleRange<T>,
+	{
 		thread_rng().gen_range(range)
 	}
 }
@@ -32,6 +66,8 @@ mod rodio_wrapper {
 		Pew,
 		Hit,
 		Step,
+		/// The same step sound but softer, as the caravan may take many steps in a row.
+		CaravanStep,
 		Mine,
 		Place,
 	}
@@ -41,7 +77,9 @@ mod rodio_wrapper {
 			match self {
 				SoundEffect::Pew => include_bytes!("../assets/sounds/pew01.wav").as_slice(),
 				SoundEffect::Hit => include_bytes!("../assets/sounds/hit01.wav").as_slice(),
-				SoundEffect::Step => include_bytes!("../assets/sounds/step01.wav").as_slice(),
+				SoundEffect::Step | SoundEffect::CaravanStep => {
+					include_bytes!("../assets/sounds/step01.wav").as_slice()
+				},
 				SoundEffect::Mine => include_bytes!("../assets/sounds/mine01.wav").as_slice(),
 				SoundEffect::Place => include_bytes!("../assets/sounds/place01.wav").as_slice(),
 			}
@@ -52,6 +90,7 @@ mod rodio_wrapper {
 				SoundEffect::Pew => 0.4,
 				SoundEffect::Hit => 0.4,
 				SoundEffect::Step => 0.15,
+				SoundEffect::CaravanStep => 0.1,
 				SoundEffect::Mine => 0.3,
 				SoundEffect::Place => 0.6,
 			}
@@ -59,27 +98,44 @@ mod rodio_wrapper {
 	}
 
 	/// Just a wrapper around whatever `rodio::OutputStream::try_default` returns.
+	/// If there is no audio device available, then it is a silent player that plays nothing.
 	pub struct AudioPlayer {
-		_stream: OutputStream,
-		stream_handle: OutputStreamHandle,
+		stream: Option<(OutputStream, OutputStreamHandle)>,
+		/// Multiplies the volume of every sound effect.
+		master_volume: f32,
 	}
 
 	impl AudioPlayer {
-		pub fn new() -> AudioPlayer {
-			let (_stream, stream_handle) = rodio::OutputStream::try_default().unwrap();
-			AudioPlayer { _stream, stream_handle }
+		pub fn new(master_volume: f32) -> AudioPlayer {
+			let stream = match rodio::OutputStream::try_default() {
+				Ok(stream) => {
+					log::info!("audio output stream opened (master volume {master_volume})");
+					Some(stream)
+				},
+				Err(error) => {
+					log::warn!("audio is disabled, could not open an audio output stream: {error}");
+					None
+				},
+			};
+			AudioPlayer { stream, master_volume }
+		}
+
+		pub fn is_available(&self) -> bool {
+			self.stream.is_some()
 		}
 
 		pub fn play_sound_effect(&self, sound_effect: SoundEffect) {
+			let Some((_stream, stream_handle)) = &self.stream else {
+				return;
+			};
 			// TODO: See if we can call `Decoder::new` only once per sound effect
 			// (in `AudioPlayer::new`) instead of here.
-			self
-				.stream_handle
+			stream_handle
 				.play_raw(
 					Decoder::new(BufReader::new(Cursor::new(sound_effect.bytes())))
 						.unwrap()
 						.convert_samples()
-						.amplify(sound_effect.volume()),
+						.amplify(sound_effect.volume() * self.master_volume),
 				)
 				.unwrap();
 		}
@@ -104,23 +160,31 @@ struct Path {
 /// The ground of a tile doesn't move (unlike `Obj`s).
 #[derive(Clone)]
 enum Ground {
-	Grass { visual_variant: u32 },
+	Grass {
+		visual_variant: u32,
+	},
 	Path(Path),
 	Water,
+	/// A part of the path that goes over water.
+	Bridge(Path),
 }
 impl Ground {
+	/// Bridges are part of the path too.
 	fn is_path(&self) -> bool {
-		matches!(self, Ground::Path(_))
+		matches!(self, Ground::Path(_) | Ground::Bridge(_))
 	}
 	fn is_water(&self) -> bool {
 		matches!(self, Ground::Water)
 	}
+	fn is_bridge(&self) -> bool {
+		matches!(self, Ground::Bridge(_))
+	}
 	fn is_grass(&self) -> bool {
 		matches!(self, Ground::Grass { .. })
 	}
 
 	fn path(&self) -> Option<&Path> {
-		if let Ground::Path(path) = self {
+		if let Ground::Path(path) | Ground::Bridge(path) = self {
 			Some(path)
 		} else {
 			None
@@ -135,26 +199,227 @@ use std::time::{Duration, Instant};
 ///
 /// For example, an animation can contain a `TimeProgression` that allows to set its duration
 /// and to know at every frame at which point we are in the progression of the animation.
+///
+/// The time used is the one of the `AnimationClock`.
 #[derive(Clone)]
 struct TimeProgression {
-	start: Instant,
+	start: Duration,
 	duration: Duration,
 }
 
 impl TimeProgression {
 	fn new(duration: Duration) -> TimeProgression {
-		TimeProgression { start: Instant::now(), duration }
+		TimeProgression { start: AnimationClock::now(), duration }
+	}
+
+	/// For the animations of the gameplay, which go faster or slower with the game speed
+	/// (as it is when they start, see `GameSpeed`).
+	fn gameplay(duration: Duration) -> TimeProgression {
+		TimeProgression::new(duration.div_f32(AnimationClock::game_speed().factor()))
 	}
 
 	/// Returns 0.0 if the represented period is just starting, 1.0 if it is just ending,
 	/// and some ratio representing the progression when it is between its start and end.
 	fn progress(&self) -> f32 {
-		Instant::now().duration_since(self.start).as_secs_f32() / self.duration.as_secs_f32()
+		AnimationClock::now()
+			.saturating_sub(self.start)
+			.as_secs_f32()
+			/ self.duration.as_secs_f32()
 	}
 
 	fn is_done(&self) -> bool {
 		1.0 <= self.progress()
 	}
+
+	/// Pushes the start of the period later by the given duration, as if it had been paused
+	/// for that long.
+	fn delay(&mut self, duration: Duration) {
+		self.start += duration;
+	}
+
+	/// Same as `progress` but starting over from 0.0 each time the period ends.
+	fn looping_progress(&self) -> f32 {
+		self.progress().fract()
+	}
+}
+
+/// The clock that drives the animations (through `TimeProgression`).
+///
+/// By default it just follows the wall clock. In fixed-step mode it only advances once per frame,
+/// by the accumulated frame durations cut in fixed steps and with a limit on how much it can
+/// advance in one frame, so that a frame that takes too long can't make an animation jump
+/// to its end without being drawn in between.
+struct AnimationClock {
+	wall_clock_start: Instant,
+	fixed_step: bool,
+	/// The time of the clock in fixed-step mode.
+	fixed_step_now: Duration,
+	/// Frame time that is not yet enough to make a step.
+	accumulator: Duration,
+	game_speed: GameSpeed,
+	/// Whether the move of an enemy onto the caravan is in slow motion
+	/// (see `AnimationClock::SLOW_MOTION_FACTOR`).
+	slow_motion: bool,
+}
+
+thread_local! {
+	static ANIMATION_CLOCK: std::cell::RefCell<AnimationClock> =
+		std::cell::RefCell::new(AnimationClock {
+			wall_clock_start: Instant::now(),
+			fixed_step: false,
+			fixed_step_now: Duration::ZERO,
+			accumulator: Duration::ZERO,
+			game_speed: GameSpeed::Normal,
+			slow_motion: true,
+		});
+}
+
+impl AnimationClock {
+	const STEP: Duration = Duration::from_micros(1_000_000 / 240);
+	/// The clock doesn't advance by more than that many steps in one frame,
+	/// which is less than the shortest animations (0.05 seconds) so that they get drawn at least
+	/// once before they end.
+	const MAX_STEPS_PER_FRAME: u32 = 10;
+	/// How much longer the move of an enemy onto the caravan takes in slow motion,
+	/// so that the player sees it coming.
+	const SLOW_MOTION_FACTOR: f32 = 12.0;
+
+	fn now() -> Duration {
+		ANIMATION_CLOCK.with(|clock| {
+			let clock = clock.borrow();
+			if clock.fixed_step {
+				clock.fixed_step_now
+			} else {
+				Instant::now().duration_since(clock.wall_clock_start)
+			}
+		})
+	}
+
+	/// Should be set before any animation starts, as the two modes don't have the same time.
+	fn set_fixed_step(fixed_step: bool) {
+		ANIMATION_CLOCK.with(|clock| clock.borrow_mut().fixed_step = fixed_step);
+	}
+
+	fn game_speed() -> GameSpeed {
+		ANIMATION_CLOCK.with(|clock| clock.borrow().game_speed)
+	}
+
+	fn set_game_speed(game_speed: GameSpeed) {
+		ANIMATION_CLOCK.with(|clock| clock.borrow_mut().game_speed = game_speed);
+	}
+
+	fn slow_motion() -> bool {
+		ANIMATION_CLOCK.with(|clock| clock.borrow().slow_motion)
+	}
+
+	fn set_slow_motion(slow_motion: bool) {
+		ANIMATION_CLOCK.with(|clock| clock.borrow_mut().slow_motion = slow_motion);
+	}
+
+	/// To call once per frame with the duration of the last frame.
+	fn advance(frame_dt: Duration) {
+		ANIMATION_CLOCK.with(|clock| {
+			let mut clock = clock.borrow_mut();
+			clock.accumulator += frame_dt;
+			let mut steps = 0;
+			while clock.accumulator >= AnimationClock::STEP {
+				if steps >= AnimationClock::MAX_STEPS_PER_FRAME {
+					// The time that could not be caught up with is dropped,
+					// the animations just get slowed down during a hitch.
+					clock.accumulator = Duration::ZERO;
+					break;
+				}
+				clock.fixed_step_now += AnimationClock::STEP;
+				clock.accumulator -= AnimationClock::STEP;
+				steps += 1;
+			}
+		});
+	}
+}
+
+/// How fast the gameplay animations (moves, shots, hits, etc.) go, their durations are divided
+/// by the factor. The rest (camera moves, water, toasts, the turn timer, etc.) is not affected.
+///
+/// In fixed-step mode (see `AnimationClock`) the fastest speeds make the gameplay animations
+/// shorter than the steps a frame can advance by, so some may end without being drawn.
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum GameSpeed {
+	Half,
+	Normal,
+	Double,
+	Quadruple,
+}
+
+impl GameSpeed {
+	fn factor(self) -> f32 {
+		match self {
+			GameSpeed::Half => 0.5,
+			GameSpeed::Normal => 1.0,
+			GameSpeed::Double => 2.0,
+			GameSpeed::Quadruple => 4.0,
+		}
+	}
+
+	fn name(self) -> &'static str {
+		match self {
+			GameSpeed::Half => "0.5x",
+			GameSpeed::Normal => "1x",
+			GameSpeed::Double => "2x",
+			GameSpeed::Quadruple => "4x",
+		}
+	}
+
+	fn next(self) -> GameSpeed {
+		match self {
+			GameSpeed::Half => GameSpeed::Normal,
+			GameSpeed::Normal => GameSpeed::Double,
+			GameSpeed::Double => GameSpeed::Quadruple,
+			GameSpeed::Quadruple => GameSpeed::Half,
+		}
+	}
+}
+
+/// Which one of the enemies that it sees a tower shoots at.
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum TargetPriority {
+	/// The enemy that is the closest to the caravan (along the path).
+	First,
+	/// The enemy that is the furthest from the caravan (along the path).
+	Last,
+	/// The enemy with the most hp (the closest to the caravan among them).
+	Strongest,
+	/// The enemy with the least hp (the closest to the caravan among them).
+	Weakest,
+}
+
+impl TargetPriority {
+	fn name(self) -> &'static str {
+		match self {
+			TargetPriority::First => "first",
+			TargetPriority::Last => "last",
+			TargetPriority::Strongest => "strongest",
+			TargetPriority::Weakest => "weakest",
+		}
+	}
+
+	fn next(self) -> TargetPriority {
+		match self {
+			TargetPriority::First => TargetPriority::Last,
+			TargetPriority::Last => TargetPriority::Strongest,
+			TargetPriority::Strongest => TargetPriority::Weakest,
+			TargetPriority::Weakest => TargetPriority::First,
+		}
+	}
+
+	/// The lower the more the enemy with the given hp and path distance is to be targeted.
+	fn rank(self, hp: i32, path_dist: i32) -> (i32, i32) {
+		match self {
+			TargetPriority::First => (path_dist, 0),
+			TargetPriority::Last => (-path_dist, 0),
+			TargetPriority::Strongest => (-hp, path_dist),
+			TargetPriority::Weakest => (hp, path_dist),
+		}
+	}
 }
 
 #[derive(Clone)]
@@ -162,30 +427,67 @@ enum Tower {
 	Basic,
 	Pink,
 	Blue,
+	/// A big tower that takes 2x2 tiles, see `Obj::footprint`.
+	Fortress,
+	/// Its shots chain from enemy to enemy, see `ShotCascade::Chain`.
+	Storm,
+	/// Shoots nothing, but the towers next to it deal more damages, see `Map::is_supported`.
+	Support,
 }
 impl Tower {
+	/// The tower object as it is when it is just placed.
+	fn new_obj(&self) -> Obj {
+		Obj::Tower {
+			actions: 0,
+			hp: self.initial_hp(),
+			fire: 0,
+			shield: 0,
+			colored_animation: None,
+			variant: self.clone(),
+			target_priority: TargetPriority::First,
+			enabled: true,
+		}
+	}
 	fn initial_hp(&self) -> i32 {
 		match self {
 			Tower::Basic => 3,
 			Tower::Pink => 4,
 			Tower::Blue => 3,
+			Tower::Fortress => 12,
+			Tower::Storm => 3,
+			Tower::Support => 3,
 		}
 	}
-	fn shot(&self) -> Shot {
+	fn name(&self) -> &'static str {
 		match self {
+			Tower::Basic => "basic tower",
+			Tower::Pink => "pink tower",
+			Tower::Blue => "blue tower",
+			Tower::Fortress => "fortress",
+			Tower::Storm => "storm tower",
+			Tower::Support => "support tower",
+		}
+	}
+	/// The shot fired in the tower phase, `None` for the towers that don't shoot.
+	fn shot(&self) -> Option<Shot> {
+		Some(match self {
 			Tower::Basic => Shot {
 				damages: 1,
 				fire: 0,
+				shield: 0,
 				additional_actions: 0,
 				cascade: ShotCascade::None,
 			},
+			// What it hits is not harmed but shielded for a while, the sides take the damages.
 			Tower::Pink => Shot {
-				damages: -1,
+				damages: 0,
 				fire: 0,
+				shield: 2,
 				additional_actions: 0,
 				cascade: ShotCascade::SplitInTwo(Box::new(Shot {
 					damages: 3,
 					fire: 0,
+					shield: 0,
 					additional_actions: 0,
 					cascade: ShotCascade::None,
 				})),
@@ -194,52 +496,139 @@ impl Tower {
 				damages: 0,
 				additional_actions: 2,
 				fire: 0,
+				shield: 0,
 				cascade: ShotCascade::Piercing(Box::new(Shot {
 					damages: 1,
 					additional_actions: 0,
 					fire: 0,
+					shield: 0,
 					cascade: ShotCascade::Piercing(Box::new(Shot {
 						damages: 0,
 						additional_actions: 0,
 						fire: 4,
+						shield: 0,
 						cascade: ShotCascade::None,
 					})),
 				})),
 			},
-		}
+			Tower::Fortress => Shot {
+				damages: 2,
+				fire: 0,
+				shield: 0,
+				additional_actions: 0,
+				cascade: ShotCascade::None,
+			},
+			Tower::Storm => Shot {
+				damages: 3,
+				fire: 0,
+				shield: 0,
+				additional_actions: 0,
+				cascade: ShotCascade::Chain { jumps_left: 2, radius: 3, already_hit: vec![] },
+			},
+			Tower::Support => return None,
+		})
 	}
 }
 
 #[derive(Clone)]
 enum Enemy {
 	Basic,
+	/// Heals back to its `max_hp` little by little when it is not hit for a few turns
+	/// (see `Balance::regeneration_delay`), so it has to be focused.
+	Regenerator {
+		max_hp: i32,
+		/// Counts the enemy phases since it was last hit (see `Map::inflict_damage_to_obj_at`).
+		turns_since_hit: i32,
+	},
 }
 
 /// An object that can be on a tile and maybe move or do stuff.
 #[derive(Clone)]
 enum Obj {
 	Caravan,
-	Tree,
+	/// Trees and crystals only have one sprite, their visual variant tells if it is mirrored.
+	Tree {
+		visual_variant: u32,
+	},
 	Rock {
 		visual_variant: u32,
 	},
-	Crystal,
+	Crystal {
+		visual_variant: u32,
+	},
 	Enemy {
 		actions: i32,
 		hp: i32,
 		fire: i32,
+		/// Absorbs damages before the hp, see `Map::inflict_damage_to_obj_at`.
+		shield: i32,
 		alive_animation: Option<AliveAnimation>,
 		colored_animation: Option<ColoredAnimation>,
-		#[allow(dead_code)] // It will be used pretty soon!
+		/// Persistent color that the enemy sprite is painted in (when not flashing
+		/// in the color of its `colored_animation`), to tell tougher enemies apart.
+		base_tint: Option<Color>,
 		variant: Enemy,
 	},
 	Tower {
 		actions: i32,
 		hp: i32,
 		fire: i32,
+		/// Absorbs damages before the hp, see `Map::inflict_damage_to_obj_at`.
+		shield: i32,
 		colored_animation: Option<ColoredAnimation>,
 		variant: Tower,
+		target_priority: TargetPriority,
+		/// Disabled towers hold their fire, they still spend their actions in the tower phase
+		/// (doing nothing) so that they don't pile up actions while disabled.
+		enabled: bool,
 	},
+	/// Objects that take more than one tile (see `Obj::footprint`) are on the top left tile
+	/// of their footprint, the other tiles they cover have one of these pointing to it.
+	/// It is on the same team as the object it is a part of, and it is not drawn.
+	Part {
+		to_anchor: CoordsDelta,
+		team: Option<Team>,
+	},
+}
+
+/// Who is on whose side, see `Obj::team`.
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Team {
+	/// The caravan and the towers.
+	Player,
+	Enemy,
+}
+
+/// The green of the flash of regenerating enemies.
+const REGENERATION_COLOR: Color = Color::rgb_u8(80, 255, 80);
+
+/// The color of support towers and of their aura ring.
+const SUPPORT_AURA_COLOR: Color = Color::rgba_u8(120, 255, 200, 130);
+
+/// The blueish color of shields (their ring, their counter and the flash of absorbed damages).
+const SHIELD_COLOR: Color = Color::rgb_u8(90, 170, 255);
+
+/// Makes the shield absorb as much of the damages as it can, returns what is left for the hp.
+/// Healing (negative damages) goes straight to the hp, it does not affect the shield.
+///
+/// Shields are temporary: they also wear off by 1 every time their holder plays
+/// (after the fire damages of that play, so fire eats into the shield first), see `phase_step`.
+fn absorb_damages(shield: &mut i32, damages: i32) -> i32 {
+	if damages <= 0 {
+		return damages;
+	}
+	let absorbed = damages.min(*shield).max(0);
+	*shield -= absorbed;
+	damages - absorbed
+}
+
+/// See `Obj::fighter_mut`.
+struct FighterMut<'a> {
+	hp: &'a mut i32,
+	fire: &'a mut i32,
+	shield: &'a mut i32,
+	actions: &'a mut i32,
+	colored_animation: &'a mut Option<ColoredAnimation>,
 }
 
 /// Small object animation: Squishes a little to appear more alive than rocks.
@@ -257,6 +646,71 @@ struct ColoredAnimation {
 }
 
 impl Obj {
+	fn name(&self) -> &'static str {
+		match self {
+			Obj::Caravan => "caravan",
+			Obj::Enemy { variant, .. } => match variant {
+				Enemy::Basic => "basic enemy",
+				Enemy::Regenerator { .. } => "regenerator",
+			},
+			Obj::Rock { .. } => "rock",
+			Obj::Tower { variant, .. } => variant.name(),
+			Obj::Tree { .. } => "tree",
+			Obj::Crystal { .. } => "crystal",
+			Obj::Part { .. } => "part of something",
+		}
+	}
+
+	/// How far (in tiles) the object lights up its surroundings at night, `None` if it gives
+	/// no light (see `LightSource`).
+	fn light_radius(&self) -> Option<f32> {
+		match self {
+			Obj::Caravan => Some(4.5),
+			Obj::Tower { variant: Tower::Fortress, .. } => Some(3.5),
+			Obj::Tower { .. } => Some(2.5),
+			_ => None,
+		}
+	}
+
+	/// The tiles taken by the object, from the tile it is on to the right and to the bottom.
+	fn footprint(&self) -> Dimensions {
+		match self {
+			Obj::Tower { variant: Tower::Fortress, .. } => Dimensions::square(2),
+			_ => Dimensions::square(1),
+		}
+	}
+
+	/// The objects that are not on any side (rocks, trees, etc.) get `None`.
+	fn team(&self) -> Option<Team> {
+		match self {
+			Obj::Caravan | Obj::Tower { .. } => Some(Team::Player),
+			Obj::Enemy { .. } => Some(Team::Enemy),
+			Obj::Tree { .. } | Obj::Rock { .. } | Obj::Crystal { .. } => None,
+			Obj::Part { team, .. } => *team,
+		}
+	}
+
+	/// Whether the shots of the given team affect this object.
+	fn is_hostile_to(&self, team: Team) -> bool {
+		self.team().is_some_and(|own_team| own_team != team)
+	}
+
+	/// Access to the stats of the objects that fight, `None` for the others.
+	/// New fighting objects only have to be added here for the damage rules to apply to them.
+	fn fighter_mut(&mut self) -> Option<FighterMut<'_>> {
+		match self {
+			Obj::Enemy { hp, fire, shield, actions, colored_animation, .. }
+			| Obj::Tower { hp, fire, shield, actions, colored_animation, .. } => {
+				Some(FighterMut { hp, fire, shield, actions, colored_animation })
+			},
+			Obj::Caravan
+			| Obj::Tree { .. }
+			| Obj::Rock { .. }
+			| Obj::Crystal { .. }
+			| Obj::Part { .. } => None,
+		}
+	}
+
 	fn hp(&self) -> Option<i32> {
 		match self {
 			Obj::Enemy { hp, .. } => Some(*hp),
@@ -279,22 +733,31 @@ impl Tile {
 	fn has_water(&self) -> bool {
 		self.ground.is_water()
 	}
-	fn has_caravan(&self) -> bool {
-		self
-			.obj
-			.as_ref()
-			.is_some_and(|obj| matches!(obj, Obj::Caravan))
-	}
-	fn has_enemy(&self) -> bool {
-		self
-			.obj
-			.as_ref()
-			.is_some_and(|obj| matches!(obj, Obj::Enemy { .. }))
+	/// Water or a bridge over water, nothing can be built there.
+	fn is_over_water(&self) -> bool {
+		self.ground.is_water() || self.ground.is_bridge()
 	}
 	fn is_empty_grass(&self) -> bool {
 		self.obj.is_none() && self.ground.is_grass()
 	}
 
+	/// Can an enemy move onto this tile?
+	/// An enemy never moves onto water or onto an other object, except for the caravan
+	/// and towers (that it destroys by moving onto them). Big towers are too big to be crushed.
+	fn is_passable_for_enemy(&self) -> bool {
+		!self.has_water()
+			&& (self.obj.is_none()
+				|| self.obj.as_ref().is_some_and(|obj| {
+					matches!(obj, Obj::Caravan | Obj::Tower { .. })
+						&& obj.footprint() == Dimensions::square(1)
+				}))
+	}
+	/// Can the caravan move onto this tile?
+	/// The caravan only moves along the path, and only onto empty tiles.
+	fn is_passable_for_caravan(&self) -> bool {
+		self.has_path() && self.obj.is_none()
+	}
+
 	fn path(&self) -> Option<&Path> {
 		self.ground.path()
 	}
@@ -302,56 +765,314 @@ impl Tile {
 
 struct Map {
 	grid: Grid<Tile>,
+	/// Always in sync with the objects of the `grid`, as long as objects are only added, removed
+	/// or replaced through the methods of `Map` (changing their fields directly is fine).
+	obj_index: ObjIndex,
+	/// The dimensions of the chunks that make the map, the map is as high as them.
+	chunk_dims: Dimensions,
+	/// How many chunks there are in a finite world (that ends after them, see `Phase::Victory`),
+	/// `None` if the world goes on forever to the right.
+	max_chunk_count: Option<i32>,
+}
+
+/// Where some kinds of objects are, so that finding them does not take a scan of the whole map.
+/// The coords are in no particular order.
+#[derive(Default)]
+struct ObjIndex {
+	caravan: Option<Coords>,
+	enemies: Vec<Coords>,
+	/// Big towers are only indexed by their top left tile (see `Obj::Part`).
+	towers: Vec<Coords>,
+}
+
+impl ObjIndex {
+	/// Scans the whole grid.
+	fn of_grid(grid: &Grid<Tile>) -> ObjIndex {
+		let mut index = ObjIndex::default();
+		for coords in grid.dims.iter() {
+			if let Some(obj) = &grid.at(coords).obj {
+				index.add(coords, obj);
+			}
+		}
+		index
+	}
+
+	fn add(&mut self, coords: Coords, obj: &Obj) {
+		match obj {
+			Obj::Caravan => self.caravan = Some(coords),
+			Obj::Enemy { .. } => self.enemies.push(coords),
+			Obj::Tower { .. } => self.towers.push(coords),
+			_ => {},
+		}
+	}
+
+	fn remove(&mut self, coords: Coords, obj: &Obj) {
+		let list = match obj {
+			Obj::Caravan => {
+				self.caravan = None;
+				return;
+			},
+			Obj::Enemy { .. } => &mut self.enemies,
+			Obj::Tower { .. } => &mut self.towers,
+			_ => return,
+		};
+		if let Some(index) = list.iter().position(|&listed| listed == coords) {
+			list.swap_remove(index);
+		}
+	}
 }
 
 impl Map {
+	fn new(chunk_dims: Dimensions, max_chunk_count: Option<i32>) -> Map {
+		Map {
+			grid: Grid::of_size_zero(),
+			obj_index: ObjIndex::default(),
+			chunk_dims,
+			max_chunk_count,
+		}
+	}
+
+	/// A map made of only grass and a straight path on the `path_y` row, without any object,
+	/// as a single chunk. Unlike the generated maps it is always the same, so that scenarios
+	/// can be set up precisely (with `Map::with_obj`) and played (with `simulate_turn`).
+	#[cfg(test)]
+	fn test_world(width: i32, height: i32, path_y: i32) -> Map {
+		let dims = Dimensions { w: width, h: height };
+		// A test world is a finite world of one chunk.
+		let mut map = Map::new(dims, Some(1));
+		map.grid = Chunk::straight_path_grid(dims, path_y, 0);
+		map
+	}
+
+	/// Places the given object (see `Map::place_obj`), handy to set up a `Map::test_world`.
+	#[cfg(test)]
+	fn with_obj(mut self, coords: Coords, obj: Obj) -> Map {
+		self.place_obj(coords, obj);
+		self
+	}
+
+	/// Replaces the whole grid by one of the same dimensions (such as one saved earlier
+	/// with `Grid::to_bytes`).
+	fn replace_grid(&mut self, grid: Grid<Tile>) -> Result<(), String> {
+		if grid.dims != self.grid.dims {
+			return Err(format!(
+				"the grid is {}x{} but the map is {}x{}",
+				grid.dims.w, grid.dims.h, self.grid.dims.w, self.grid.dims.h
+			));
+		}
+		self.obj_index = ObjIndex::of_grid(&grid);
+		self.grid = grid;
+		Ok(())
+	}
+
+	/// Whether the `obj_index` matches the objects of the grid (it takes a scan of the whole map).
+	#[cfg(test)]
+	fn obj_index_is_consistent(&self) -> bool {
+		let index_from_grid = ObjIndex::of_grid(&self.grid);
+		let same_coords = |a: &[Coords], b: &[Coords]| {
+			a.len() == b.len() && a.iter().all(|coords| b.contains(coords))
+		};
+		index_from_grid.caravan == self.obj_index.caravan
+			&& same_coords(&index_from_grid.enemies, &self.obj_index.enemies)
+			&& same_coords(&index_from_grid.towers, &self.obj_index.towers)
+	}
+
+	/// Removes the object from the tile and returns it,
+	/// `None` if there is no object there or if `coords` are out of the map.
+	/// The parts of a big object are removed with it.
+	fn take_obj(&mut self, coords: Coords) -> Option<Obj> {
+		let obj = self.grid.get_mut(coords).and_then(|tile| tile.obj.take())?;
+		self.obj_index.remove(coords, &obj);
+		for part_coords in (Rect { top_left: coords, dims: obj.footprint() }).iter() {
+			if let Some(Obj::Part { to_anchor, .. }) = self
+				.grid
+				.get(part_coords)
+				.and_then(|tile| tile.obj.as_ref())
+			{
+				if part_coords + *to_anchor == coords {
+					self.set_obj(part_coords, None);
+				}
+			}
+		}
+		Some(obj)
+	}
+
+	/// Puts the given object on the tile and its parts on the other tiles of its footprint
+	/// (replacing whatever was there), the parts that would be out of the map are skipped.
+	fn place_obj(&mut self, coords: Coords, obj: Obj) {
+		let team = obj.team();
+		for part_coords in (Rect { top_left: coords, dims: obj.footprint() }).iter() {
+			if part_coords != coords {
+				let to_anchor = coords - part_coords;
+				self.set_obj(part_coords, Some(Obj::Part { to_anchor, team }));
+			}
+		}
+		self.set_obj(coords, Some(obj));
+	}
+
+	/// The coords of the object that the tile at `coords` is a part of,
+	/// or `coords` if it is not a part of a big object.
+	fn anchor_of(&self, coords: Coords) -> Coords {
+		match self.grid.get(coords).and_then(|tile| tile.obj.as_ref()) {
+			Some(Obj::Part { to_anchor, .. }) => coords + *to_anchor,
+			_ => coords,
+		}
+	}
+
+	/// Can the given object be placed at `coords`? All the tiles of its footprint
+	/// have to be in the map, free and not over water.
+	/// Big objects can't be placed on the path, enemies can't crush them so they would be walls
+	/// that nothing gets through (see `Tile::is_passable_for_enemy`).
+	fn can_place_obj_at(&self, coords: Coords, obj: &Obj) -> bool {
+		let is_big = obj.footprint() != Dimensions::square(1);
+		Rect { top_left: coords, dims: obj.footprint() }
+			.iter()
+			.all(|coords| {
+				self.grid.get(coords).is_some_and(|tile| {
+					tile.obj.is_none() && !tile.is_over_water() && !(is_big && tile.has_path())
+				})
+			})
+	}
+
+	/// Puts the given object on the tile (replacing whatever was there),
+	/// does nothing if `coords` are out of the map.
+	fn set_obj(&mut self, coords: Coords, obj: Option<Obj>) {
+		if let Some(tile) = self.grid.get_mut(coords) {
+			if let Some(old_obj) = &tile.obj {
+				self.obj_index.remove(coords, old_obj);
+			}
+			if let Some(new_obj) = &obj {
+				self.obj_index.add(coords, new_obj);
+			}
+			tile.obj = obj;
+		}
+	}
+
+	/// The direction in which the tower at `coords` would shoot during the tower phase,
+	/// `None` if it sees no enemy, if it is disabled or if it doesn't shoot.
+	///
+	/// Among the enemies that it sees, towers shoot at the one that comes first according
+	/// to their `TargetPriority` (by default the one that is the closest to the caravan,
+	/// it seems like a nice default heuristic).
+	fn tower_target_direction(&self, coords: Coords) -> Option<CoordsDelta> {
+		let target_priority = match self.grid.get(coords).and_then(|tile| tile.obj.as_ref()) {
+			Some(Obj::Tower { enabled: false, .. }) => return None,
+			Some(Obj::Tower { variant, .. }) if variant.shot().is_none() => return None,
+			Some(Obj::Tower { target_priority, .. }) => *target_priority,
+			_ => TargetPriority::First,
+		};
+		let mut best_rank_and_dir: Option<((i32, i32), CoordsDelta)> = None;
+		for direction in CoordsDelta::iter_4_directions() {
+			let mut view_coords = coords + direction;
+			while let Some(tile) = self.grid.get(view_coords) {
+				if let (Some(Obj::Enemy { hp, .. }), Some(Path { distance, .. })) =
+					(&tile.obj, tile.path())
+				{
+					let rank = target_priority.rank(*hp, *distance);
+					if best_rank_and_dir.is_none()
+						|| best_rank_and_dir.is_some_and(|(best_rank, _)| rank < best_rank)
+					{
+						best_rank_and_dir = Some((rank, direction));
+						break;
+					}
+				}
+				// Shots go through friendly objects, so towers can see past them.
+				if tile
+					.obj
+					.as_ref()
+					.is_some_and(|obj| obj.team() != Some(Team::Player))
+				{
+					break;
+				}
+				view_coords += direction;
+			}
+		}
+		best_rank_and_dir.map(|(_, direction)| direction)
+	}
+
+	/// Is the tower at `coords` next to a support tower (on one of the 4 sides of any of the
+	/// tiles it takes)? Its shots then deal `Balance::support_damage_bonus` more damages.
+	///
+	/// The bonus doesn't stack, a tower next to several support towers gets it only once
+	/// (so that the best use of support towers is to spread them out among the other towers).
+	fn is_supported(&self, coords: Coords) -> bool {
+		let Some(obj) = self.grid.get(coords).and_then(|tile| tile.obj.as_ref()) else {
+			return false;
+		};
+		Rect { top_left: coords, dims: obj.footprint() }
+			.iter()
+			.any(|tile_coords| {
+				CoordsDelta::iter_4_directions().any(|direction| {
+					let neighbor_anchor = self.anchor_of(tile_coords + direction);
+					neighbor_anchor != coords
+						&& matches!(
+							self
+								.grid
+								.get(neighbor_anchor)
+								.and_then(|tile| tile.obj.as_ref()),
+							Some(Obj::Tower { variant: Tower::Support, .. })
+						)
+				})
+			})
+	}
+
+	/// The coords of the first object that a shot fired by `team` from `from` in the given
+	/// direction would hit (see `resolve_shot`), `None` if it would leave the map.
+	/// This does not account for what the hit would cascade into.
+	fn shot_first_hit(&self, from: Coords, direction: CoordsDelta, team: Team) -> Option<Coords> {
+		let mut coords = from + direction;
+		while let Some(tile) = self.grid.get(coords) {
+			if tile
+				.obj
+				.as_ref()
+				.is_some_and(|obj| obj.team() != Some(team))
+			{
+				return Some(coords);
+			}
+			coords += direction;
+		}
+		None
+	}
+
 	/// Draws the ground of the tile designated by the given `coords` to `dst` in the pixel buffer.
 	///
 	/// The drawing of some types of ground depends on the surrounding tiles, which is why
 	/// this is a method of `Map` instead of `Ground`.
-	fn draw_tile_ground_at(&self, renderer: &mut Renderer, coords: Coords, dst: Rect) {
-		let ground = self.grid.get(coords).unwrap().ground.clone();
+	/// Water is animated, `water_phase` (from 0.0 to 1.0) is where its animation loop is at.
+	fn draw_tile_ground_at(
+		&self,
+		renderer: &mut SoftwareRenderer,
+		coords: Coords,
+		dst: Rect,
+		water_phase: f32,
+	) {
+		let ground = self.grid.at(coords).ground.clone();
 		match ground {
 			Ground::Grass { visual_variant } => {
 				assert!(visual_variant < 4);
-				let sprite = Rect::tile((visual_variant as i32, 0).into(), 16);
+				let sprite = Rect::tile((visual_variant as i32, 0).into(), TILE_PX);
 				renderer.draw_sprite(dst, sprite, DrawSpriteEffects::none());
 			},
-			Ground::Path(Path { forward, backward, .. }) => {
+			Ground::Path(_) => {
 				// For now we just have a sprite of a streight path and of a L-turn.
-				// By flipping them around various axes we can draw all the cases.
+				// By flipping them around various axes we can draw the straight paths and
+				// the turns, the other cases (ends and junctions) are put together from parts.
 				let sprite_straight = (4, 0);
 				let sprite_turn = (5, 0);
-				/// Checks for one of the 4 possible L-turns.
-				fn is_turn(
-					forward: CoordsDelta,
-					backward: CoordsDelta,
-					a: CoordsDelta,
-					b: CoordsDelta,
-				) -> bool {
-					(forward == a && backward == b) || (backward == a && forward == b)
-				}
 				let (sprite_coords, flip_horizontally, flip_vertically, flip_diagonally_id) =
-					if forward.dy == 0 && backward.dy == 0 {
-						(sprite_straight, false, false, false) // Horizontal
-					} else if forward.dx == 0 && backward.dx == 0 {
-						(sprite_straight, false, false, true) // Vertical
-					} else if is_turn(forward, backward, CoordsDelta::UP, CoordsDelta::LEFT) {
-						(sprite_turn, false, false, false)
-					} else if is_turn(forward, backward, CoordsDelta::DOWN, CoordsDelta::LEFT) {
-						(sprite_turn, false, true, false)
-					} else if is_turn(forward, backward, CoordsDelta::UP, CoordsDelta::RIGHT) {
-						(sprite_turn, true, false, false)
-					} else if is_turn(forward, backward, CoordsDelta::DOWN, CoordsDelta::RIGHT) {
-						(sprite_turn, true, true, false)
-					} else {
-						panic!(
-							"A path may has both its backward ({backward:?}) \
-							and its forward ({forward:?}) directions be the same, \
-							which doesn't make sense."
-						);
+					match self.path_connections(coords) {
+						PATH_LEFT_RIGHT => (sprite_straight, false, false, false),
+						PATH_UP_DOWN => (sprite_straight, false, false, true),
+						PATH_UP_LEFT => (sprite_turn, false, false, false),
+						PATH_DOWN_LEFT => (sprite_turn, false, true, false),
+						PATH_UP_RIGHT => (sprite_turn, true, false, false),
+						PATH_DOWN_RIGHT => (sprite_turn, true, true, false),
+						connections => {
+							draw_path_from_parts(renderer, dst, connections);
+							return;
+						},
 					};
-				let sprite = Rect::tile(sprite_coords.into(), 16);
+				let sprite = Rect::tile(sprite_coords.into(), TILE_PX);
 				renderer.draw_sprite(
 					dst,
 					sprite,
@@ -360,213 +1081,606 @@ impl Map {
 						flip_vertically,
 						flip_diagonally_id,
 						paint: None,
+						scale_mode: ScaleMode::Nearest,
+						rotation: 0.0,
 					},
 				);
 			},
-			Ground::Water => {
-				// Depending on weather there are water on some adjacent tiles, we render
-				// different variants of the base water sprite.
-				// This is done to give a sense of depth (the water level is thus
-				// percieved as a bit below ground level).
-				let there_is_water_on_the_top =
-					if let Some(tile_on_the_top) = self.grid.get(coords + (0, -1).into()) {
-						tile_on_the_top.has_water()
-					} else {
-						false
-					};
-				let there_is_nothing_on_the_top = self.grid.get(coords + (0, -1).into()).is_none();
-				let there_is_ground_on_the_top_left_corner =
-					if let Some(tile_on_the_top_left_corner) = self.grid.get(coords + (-1, -1).into()) {
-						!tile_on_the_top_left_corner.has_water()
-					} else {
-						false
-					};
-				let there_is_water_on_the_left =
-					if let Some(tile_on_the_left) = self.grid.get(coords + (-1, 0).into()) {
-						tile_on_the_left.has_water()
+			Ground::Water => self.draw_water_at(renderer, coords, dst, water_phase),
+			Ground::Bridge(Path { forward, backward, .. }) => {
+				self.draw_water_at(renderer, coords, dst, water_phase);
+				// There is no bridge sprite (yet), so it is just some wooden planks.
+				let horizontal = forward.dy == 0 && backward.dy == 0;
+				let unit = dst.dims.w / TILE_PX;
+				let deck = if horizontal {
+					Rect::xywh(dst.left(), dst.top() + 3 * unit, dst.dims.w, 10 * unit)
+				} else {
+					Rect::xywh(dst.left() + 3 * unit, dst.top(), 10 * unit, dst.dims.h)
+				};
+				renderer.draw_rect(deck, Color::rgb_u8(150, 100, 50));
+				let dark_wood = Color::rgb_u8(100, 60, 30);
+				for i in 0..4 {
+					let plank_gap = if horizontal {
+						Rect::xywh(dst.left() + i * 4 * unit, deck.top(), unit, deck.dims.h)
 					} else {
-						true
+						Rect::xywh(deck.left(), dst.top() + i * 4 * unit, deck.dims.w, unit)
 					};
-				let sprite_coords_x = 6
-					+ if there_is_nothing_on_the_top {
-						2
-					} else if there_is_water_on_the_top && there_is_ground_on_the_top_left_corner {
-						6
-					} else if there_is_water_on_the_top {
-						4
-					} else {
-						0
-					} + if there_is_water_on_the_left { 0 } else { 1 };
-				let sprite = Rect::tile((sprite_coords_x, 0).into(), 16);
-				renderer.draw_sprite(dst, sprite, DrawSpriteEffects::none());
+					renderer.draw_rect(plank_gap, dark_wood);
+				}
+				let (rail_a, rail_b) = if horizontal {
+					(
+						Rect::xywh(deck.left(), deck.top(), deck.dims.w, unit),
+						Rect::xywh(
+							deck.left(),
+							deck.bottom_excluded() - unit,
+							deck.dims.w,
+							unit,
+						),
+					)
+				} else {
+					(
+						Rect::xywh(deck.left(), deck.top(), unit, deck.dims.h),
+						Rect::xywh(deck.right_excluded() - unit, deck.top(), unit, deck.dims.h),
+					)
+				};
+				renderer.draw_rect(rail_a, dark_wood);
+				renderer.draw_rect(rail_b, dark_wood);
 			},
 		}
 	}
 
-	fn draw_tile_obj_at(&self, renderer: &mut Renderer, coords: Coords, dst: Rect) {
-		match self.grid.get(coords).and_then(|tile| tile.obj.as_ref()) {
-			None => {},
-			Some(obj) => {
-				draw_obj(renderer, obj, dst, false);
-			},
+	/// The directions in which the path at `coords` connects to its neighbors, as a bitmask
+	/// of the `PATH_*` bits. A tile connects to a neighbor if one of them goes towards the other
+	/// (so a path that would branch is handled), and to the outside of the map if it goes there.
+	fn path_connections(&self, coords: Coords) -> u8 {
+		let Some(path) = self.grid.get(coords).and_then(Tile::path) else {
+			return 0;
+		};
+		let mut connections = 0;
+		for (bit, direction) in [PATH_UP, PATH_RIGHT, PATH_DOWN, PATH_LEFT]
+			.into_iter()
+			.zip(CoordsDelta::iter_4_directions())
+		{
+			let goes_there = path.forward == direction || path.backward == direction;
+			let comes_from_there = self
+				.grid
+				.get(coords + direction)
+				.and_then(Tile::path)
+				.is_some_and(|neighbor| {
+					neighbor.forward == -direction || neighbor.backward == -direction
+				});
+			if goes_there || comes_from_there {
+				connections |= bit;
+			}
 		}
+		connections
 	}
 
-	fn _draw(&self, _renderer: &mut Renderer, _config: MapDrawingConfig) {
-		todo!()
+	/// What is around the water tile at `coords` that matters to its sprite,
+	/// see `water_sprite_index`.
+	fn water_adjacency(&self, coords: Coords) -> WaterAdjacency {
+		let tile_on_the_top = self.grid.get(coords + CoordsDelta::UP);
+		let tile_on_the_top_left_corner = self
+			.grid
+			.get(coords + (CoordsDelta::UP + CoordsDelta::LEFT));
+		let tile_on_the_left = self.grid.get(coords + CoordsDelta::LEFT);
+		WaterAdjacency {
+			water_on_the_top: tile_on_the_top.is_some_and(Tile::is_over_water),
+			nothing_on_the_top: tile_on_the_top.is_none(),
+			ground_on_the_top_left_corner: tile_on_the_top_left_corner
+				.is_some_and(|tile| !tile.is_over_water()),
+			// Water is assumed to go on out of the left of the map.
+			water_on_the_left: tile_on_the_left.is_none_or(Tile::is_over_water),
+		}
 	}
 
-	fn shot_hits_obj_at(&mut self, coords: Coords, shot: &Shot) {
-		self.inflict_damage_to_obj_at(coords, shot.damages);
-		if shot.fire > 0 {
-			match self.grid.get_mut(coords).and_then(|tile| tile.obj.as_mut()) {
-				Some(Obj::Enemy { ref mut fire, ref mut colored_animation, .. }) => {
-					*fire += shot.fire;
-					*colored_animation = Some(ColoredAnimation {
-						tp: TimeProgression::new(Duration::from_secs_f32(0.075)),
-						color: Color::rgb_u8(255, 180, 0),
-					});
-				},
-				Some(Obj::Tower { ref mut fire, ref mut colored_animation, .. }) => {
-					*fire += shot.fire;
-					*colored_animation = Some(ColoredAnimation {
-						tp: TimeProgression::new(Duration::from_secs_f32(0.075)),
-						color: Color::rgb_u8(255, 180, 0),
-					});
-				},
-				_ => {},
-			};
+	fn draw_water_at(
+		&self,
+		renderer: &mut SoftwareRenderer,
+		coords: Coords,
+		dst: Rect,
+		water_phase: f32,
+	) {
+		let sprite_coords_x = water_sprite_index(self.water_adjacency(coords));
+		let sprite = Rect::tile((sprite_coords_x, 0).into(), TILE_PX);
+		renderer.draw_sprite(dst, sprite, DrawSpriteEffects::none());
+
+		// A little glint goes across the water to make it shimmer. It stays in the lower half
+		// of the tile so that it doesn't mess with the edges of the variants above.
+		// Tiles are not all in phase so that the water doesn't shimmer in lockstep.
+		let unit = dst.dims.w / TILE_PX;
+		let phase =
+			(water_phase + (coords.x * 3 + coords.y * 7).rem_euclid(11) as f32 / 11.0).fract();
+		let glint_x = (phase * TILE_PX as f32) as i32;
+		let glint_y = 9 + (coords.x + coords.y).rem_euclid(2) * 3;
+		let glint_w = 3.min(TILE_PX - glint_x);
+		let glint = Rect::xywh(
+			dst.left() + glint_x * unit,
+			dst.top() + glint_y * unit,
+			glint_w * unit,
+			unit,
+		);
+		renderer.draw_rect(glint, Color::rgb_u8(170, 220, 255));
+	}
+
+	fn draw_tile_obj_at(
+		&self,
+		renderer: &mut SoftwareRenderer,
+		coords: Coords,
+		dst: Rect,
+		scale_mode: ScaleMode,
+	) {
+		match self.grid.get(coords).and_then(|tile| tile.obj.as_ref()) {
+			None => {},
+			Some(obj) => {
+				draw_obj(renderer, obj, dst, false, scale_mode);
+			},
 		}
-		if shot.additional_actions > 0 {
-			match self.grid.get_mut(coords).and_then(|tile| tile.obj.as_mut()) {
-				Some(Obj::Enemy { ref mut actions, ref mut colored_animation, .. }) => {
-					*actions += shot.additional_actions;
-					*colored_animation = Some(ColoredAnimation {
-						tp: TimeProgression::new(Duration::from_secs_f32(0.075)),
-						color: Color::rgb_u8(255, 255, 0),
-					});
-				},
-				Some(Obj::Tower { ref mut actions, ref mut colored_animation, .. }) => {
-					*actions += shot.additional_actions;
-					*colored_animation = Some(ColoredAnimation {
-						tp: TimeProgression::new(Duration::from_secs_f32(0.075)),
-						color: Color::rgb_u8(255, 255, 0),
-					});
-				},
-				_ => {},
-			};
+	}
+
+	fn _draw(&self, _renderer: &mut SoftwareRenderer, _config: MapDrawingConfig) {
+		todo!()
+	}
+
+	/// The coords of the nearest tile (within `radius`, as the crow flies) with an object that is
+	/// hostile to `team`, not counting the objects whose anchors are in `excluded`.
+	fn nearest_hostile_within(
+		&self,
+		center: Coords,
+		radius: i32,
+		team: Team,
+		excluded: &[Coords],
+	) -> Option<Coords> {
+		Rect::from_center_size(center, Dimensions::square(radius * 2 + 1))
+			.iter()
+			.filter(|&coords| (coords - center).length_squared() <= radius * radius)
+			.filter(|&coords| {
+				self
+					.grid
+					.get(coords)
+					.and_then(|tile| tile.obj.as_ref())
+					.is_some_and(|obj| obj.is_hostile_to(team))
+			})
+			.filter(|&coords| !excluded.contains(&self.anchor_of(coords)))
+			.min_by_key(|&coords| (coords - center).length_squared())
+	}
+
+	/// Applies the effects of the shot (fired by someone of the given team) to the object there,
+	/// if it is hostile to that team.
+	fn shot_hits_obj_at(&mut self, coords: Coords, shot: &Shot, team: Team, stats: &mut RunStats) {
+		// A big object is hit as a whole.
+		let coords = self.anchor_of(coords);
+		let is_hostile = self
+			.grid
+			.get(coords)
+			.and_then(|tile| tile.obj.as_ref())
+			.is_some_and(|obj| obj.is_hostile_to(team));
+		if !is_hostile {
+			return;
+		}
+		self.inflict_damage_to_obj_at(coords, shot.damages, stats);
+		let fighter = self
+			.grid
+			.get_mut(coords)
+			.and_then(|tile| tile.obj.as_mut()?.fighter_mut());
+		if let Some(fighter) = fighter {
+			if shot.shield > 0 {
+				*fighter.shield += shot.shield;
+				*fighter.colored_animation = Some(ColoredAnimation {
+					tp: TimeProgression::gameplay(Duration::from_secs_f32(0.075)),
+					color: SHIELD_COLOR,
+				});
+			}
+			if shot.fire > 0 {
+				*fighter.fire += shot.fire;
+				*fighter.colored_animation = Some(ColoredAnimation {
+					tp: TimeProgression::gameplay(Duration::from_secs_f32(0.075)),
+					color: Color::rgb_u8(255, 180, 0),
+				});
+			}
+			if shot.additional_actions > 0 {
+				*fighter.actions += shot.additional_actions;
+				*fighter.colored_animation = Some(ColoredAnimation {
+					tp: TimeProgression::gameplay(Duration::from_secs_f32(0.075)),
+					color: Color::rgb_u8(255, 255, 0),
+				});
+			}
 		}
 	}
 
-	fn inflict_damage_to_obj_at(&mut self, coords: Coords, damages: i32) {
+	fn inflict_damage_to_obj_at(&mut self, coords: Coords, damages: i32, stats: &mut RunStats) {
+		let coords = self.anchor_of(coords);
 		let color = if damages < 0 {
 			Color::rgb_u8(255, 150, 150)
 		} else {
 			Color::rgb_u8(255, 0, 0)
 		};
-		let destroy = match self.grid.get_mut(coords).and_then(|tile| tile.obj.as_mut()) {
-			None => false,
-			Some(Obj::Enemy { ref mut hp, ref mut colored_animation, .. }) => {
-				*hp -= damages;
-				*colored_animation = Some(ColoredAnimation {
-					tp: TimeProgression::new(Duration::from_secs_f32(0.075)),
-					color,
-				});
-				*hp <= 0
-			},
-			Some(Obj::Tower { ref mut hp, ref mut colored_animation, .. }) => {
-				*hp -= damages;
-				*colored_animation = Some(ColoredAnimation {
-					tp: TimeProgression::new(Duration::from_secs_f32(0.075)),
+		let fighter = self
+			.grid
+			.get_mut(coords)
+			.and_then(|tile| tile.obj.as_mut()?.fighter_mut());
+		let (destroy, hp_damages) = match fighter {
+			None => (false, 0),
+			Some(fighter) => {
+				let hp_damages = absorb_damages(fighter.shield, damages);
+				*fighter.hp -= hp_damages;
+				// Damages that are fully absorbed make it flash in the color of the shield.
+				let color = if damages > 0 && hp_damages == 0 {
+					SHIELD_COLOR
+				} else {
+					color
+				};
+				*fighter.colored_animation = Some(ColoredAnimation {
+					tp: TimeProgression::gameplay(Duration::from_secs_f32(0.075)),
 					color,
 				});
-				*hp <= 0
+				(*fighter.hp <= 0, hp_damages)
 			},
-			Some(_) => false,
 		};
+		if let Some(Obj::Enemy { variant, .. }) =
+			self.grid.get_mut(coords).and_then(|tile| tile.obj.as_mut())
+		{
+			stats.damage_dealt += hp_damages.max(0);
+			// Being hit counts even if the shield takes it all, healing doesn't count.
+			if let Enemy::Regenerator { turns_since_hit, .. } = variant {
+				if damages > 0 {
+					*turns_since_hit = 0;
+				}
+			}
+		}
 		if destroy {
-			self.grid.get_mut(coords).unwrap().obj = None;
+			match self.take_obj(coords) {
+				Some(Obj::Enemy { .. }) => stats.enemies_killed += 1,
+				Some(Obj::Tower { .. }) => stats.towers_lost += 1,
+				_ => {},
+			}
 		}
 	}
 
+	fn caravan_coords(&self) -> Option<Coords> {
+		self.obj_index.caravan
+	}
+
 	fn caravan_coords_and_tile(&self) -> Option<(Coords, &Tile)> {
-		for coords in self.grid.dims.iter() {
-			let tile = self.grid.get(coords).unwrap();
-			if tile.has_caravan() {
-				return Some((coords, tile));
-			}
-		}
-		None
+		let coords = self.caravan_coords()?;
+		Some((coords, self.grid.at(coords)))
+	}
+
+	/// The enemies with their coords, in no particular order (see `ObjIndex`).
+	fn enemies(&self) -> impl Iterator<Item = (Coords, &Obj)> {
+		self.indexed_objs(&self.obj_index.enemies)
+	}
+
+	/// The towers with their coords (the top left tile for big ones),
+	/// in no particular order (see `ObjIndex`).
+	fn towers(&self) -> impl Iterator<Item = (Coords, &Obj)> {
+		self.indexed_objs(&self.obj_index.towers)
+	}
+
+	fn indexed_objs<'a>(
+		&'a self,
+		coords_list: &'a [Coords],
+	) -> impl Iterator<Item = (Coords, &'a Obj)> {
+		coords_list
+			.iter()
+			.filter_map(|&coords| Some((coords, self.grid.at(coords).obj.as_ref()?)))
 	}
 
 	fn caradan_path_dist(&self) -> Option<i32> {
 		self
 			.caravan_coords_and_tile()
-			.map(|(_coords, tile)| tile.path().unwrap().distance)
+			.and_then(|(_coords, tile)| tile.path())
+			.map(|path| path.distance)
 	}
 
-	fn path_coords(&self) -> Vec<Coords> {
-		let left_path_y = 'finding_left_path_y: {
-			for y in 0..self.grid.dims.h {
-				if self
-					.grid
-					.get((0, y).into())
-					.unwrap()
-					.path()
-					.is_some_and(|path| path.distance == 0)
-				{
-					break 'finding_left_path_y y;
-				}
-			}
-			panic!("No path tile with distance 0 on the left side found");
-		};
+	/// How many path tiles ahead of the caravan is the tile at `coords` (negative if behind),
+	/// `None` if that tile is not on the path or if there is no caravan (on the path).
+	fn path_dist_from_caravan(&self, coords: Coords) -> Option<i32> {
+		let distance = self.grid.get(coords)?.path()?.distance;
+		Some(distance - self.caradan_path_dist()?)
+	}
+
+	/// Returns the coords of all the path tiles, from the left-most one to the right-most one.
+	/// The path is followed from the path tile of the left column that comes from outside the grid,
+	/// so that it works even if the tile with distance 0 is not part of the grid anymore.
+	///
+	/// Returns `None` if no such path tile is found (which means the path is malformed).
+	fn path_coords(&self) -> Option<Vec<Coords>> {
+		let left_path_y = (0..self.grid.dims.h).find(|&y| {
+			let coords: Coords = (0, y).into();
+			self
+				.grid
+				.get(coords)
+				.and_then(|tile| tile.path())
+				.is_some_and(|path| !self.grid.dims.contains(coords + path.backward))
+		})?;
 		let mut path_coords = vec![];
 		let mut head: Coords = (0, left_path_y).into();
+		let mut expected_distance = self.grid.get(head)?.path()?.distance;
 		while let Some(path) = self.grid.get(head).and_then(|tile| tile.path()) {
+			// Following `forward` must visit the path tiles by increasing `distance` with no gaps,
+			// which is what the caravan moving code relies on.
+			debug_assert_eq!(
+				path.distance, expected_distance,
+				"path tile at {head:?} has an unexpected distance"
+			);
+			if path.distance != expected_distance {
+				// A malformed path (that loops for example) is cut there in release builds.
+				break;
+			}
+			expected_distance += 1;
 			path_coords.push(head);
 			head += path.forward;
 		}
-		path_coords
+		Some(path_coords)
 	}
 
+	/// Returns the y coordinate and the distance of the path tile that leaves the grid
+	/// on its right side, if any.
+	/// Returns `None` if the grid is empty or if the path is malformed and does not leave.
 	fn rightmost_path_y_and_dist(&self) -> Option<(i32, i32)> {
 		if self.grid.dims.w == 0 {
 			return None;
 		}
-		for y in 0..self.grid.dims.h {
+		(0..self.grid.dims.h).find_map(|y| {
 			let coords: Coords = (self.grid.dims.w - 1, y).into();
-			if let Ground::Path(Path { forward: CoordsDelta::RIGHT, distance, .. }) =
-				self.grid.get(coords).unwrap().ground
-			{
-				return Some((y, distance));
+			match self.grid.get(coords)?.path() {
+				Some(Path { forward: CoordsDelta::RIGHT, distance, .. }) => Some((y, *distance)),
+				_ => None,
 			}
+		})
+	}
+
+	/// Returns the coords of the enemy that should play next during the enemy phase, if any.
+	///
+	/// Enemies closer to the caravan play first so that they don't bump into each other too much,
+	/// closer to the caravan meaning on a path tile with the smallest distance.
+	/// Ties are broken explicitly (and not by whatever order the grid is iterated in)
+	/// by making the enemy with the smallest y play first, then the smallest x.
+	fn next_enemy_to_play(&self) -> Option<Coords> {
+		self
+			.enemies()
+			.filter_map(
+				|(coords, enemy)| match (enemy, self.grid.at(coords).path()) {
+					(Obj::Enemy { actions, .. }, Some(path)) if *actions >= 1 => {
+						Some((path.distance, coords))
+					},
+					_ => None,
+				},
+			)
+			.min_by_key(|(distance, coords)| (*distance, coords.y, coords.x))
+			.map(|(_distance, coords)| coords)
+	}
+
+	/// The path tile of the given column, where enemies spawn.
+	fn spawn_coords_on_column(&self, x: i32) -> Option<Coords> {
+		(0..self.grid.dims.h)
+			.map(|y| (x, y).into())
+			.find(|coords| self.grid.get(*coords).is_some_and(Tile::has_path))
+	}
+
+	/// Decides at random which enemy will spawn (see `Waves` for when).
+	fn roll_enemy() -> Obj {
+		let rand = rand_range(0.0..1.0);
+		// Tougher enemies are tinted so that they can be spotted from afar.
+		let (hp, base_tint, variant) = if rand < 0.1 {
+			(12, Some(Color::rgb_u8(200, 40, 40)), Enemy::Basic)
+		} else if rand < 0.2 {
+			let variant = Enemy::Regenerator { max_hp: 8, turns_since_hit: 0 };
+			(8, Some(Color::rgb_u8(120, 220, 120)), variant)
+		} else if rand < 0.35 {
+			(10, Some(Color::rgb_u8(230, 130, 110)), Enemy::Basic)
+		} else {
+			(8, None, Enemy::Basic)
+		};
+		Obj::Enemy {
+			actions: 0,
+			hp,
+			fire: 0,
+			shield: 0,
+			alive_animation: None,
+			colored_animation: None,
+			base_tint,
+			variant,
+		}
+	}
+
+	/// Where an enemy can spawn on the given column, if it can spawn there now.
+	/// To not flood the path, enemies don't spawn if there are already too many of them
+	/// or if one of them is still close to the spawn tile.
+	fn free_spawn_coords_on_column(&self, x: i32, balance: &Balance) -> Option<Coords> {
+		let spawn_coords = self.spawn_coords_on_column(x)?;
+		if self.grid.at(spawn_coords).obj.is_some() {
+			return None;
+		}
+		let too_close = |(coords, _enemy): (Coords, &Obj)| {
+			(coords - spawn_coords).manhattan() < balance.min_enemy_spawn_gap
+		};
+		if self.enemies().any(too_close) {
+			return None;
+		}
+		((self.enemies().count() as i32) < balance.max_enemy_count).then_some(spawn_coords)
+	}
+
+	/// Spawns the enemy on the path tile of the given column if it can spawn there now,
+	/// else the enemy is given back.
+	fn spawn_enemy_on_column(&mut self, x: i32, enemy: Obj, balance: &Balance) -> Option<Obj> {
+		if let Some(spawn_coords) = self.free_spawn_coords_on_column(x, balance) {
+			self.set_obj(spawn_coords, Some(enemy));
+			None
+		} else {
+			Some(enemy)
 		}
-		panic!("could not find a path on the rightmost column");
 	}
 
-	fn generate_chunk_on_the_right(&mut self) {
-		let chunk = Chunk::generate(self.rightmost_path_y_and_dist());
+	/// Whether the world is finite and all its chunks are there.
+	fn is_fully_generated(&self) -> bool {
+		self
+			.max_chunk_count
+			.is_some_and(|max_chunk_count| max_chunk_count * self.chunk_dims.w <= self.grid.dims.w)
+	}
+
+	/// Whether the caravan at `coords` made it to the end of a finite world.
+	fn is_end_of_the_world(&self, coords: Coords) -> bool {
+		self.is_fully_generated()
+			&& coords.x == self.grid.dims.w - 1
+			&& self.grid.get(coords).is_some_and(Tile::has_path)
+	}
+
+	/// Generates chunks on the right until the map is wider than `width` (in tiles),
+	/// or until there are no chunks left to generate in a finite world.
+	fn generate_chunks_until_wider_than(&mut self, width: i32) {
+		while self.grid.dims.w <= width && self.generate_chunk_on_the_right() {}
+	}
+
+	/// Returns `false` (without generating anything) if the world is finite
+	/// and there is no chunk left to generate.
+	fn generate_chunk_on_the_right(&mut self) -> bool {
+		if self.is_fully_generated() {
+			return false;
+		}
+		let last_path_y_and_dist = self.rightmost_path_y_and_dist();
+		if last_path_y_and_dist.is_none() && self.grid.dims.w != 0 {
+			// Better to start a disconnected path than to crash.
+			log::warn!("could not find a path on the rightmost column");
+		}
+		let chunk_index = self.grid.dims.w / self.chunk_dims.w;
+		let chunk = Chunk::generate(
+			self.chunk_dims,
+			last_path_y_and_dist,
+			Biome::random_for_chunk(chunk_index),
+		);
+		let chunk_left = self.grid.dims.w;
+		for coords in chunk.grid.dims.iter() {
+			if let Some(obj) = &chunk.grid.at(coords).obj {
+				self
+					.obj_index
+					.add(coords + CoordsDelta::from((chunk_left, 0)), obj);
+			}
+		}
 		let grid = std::mem::replace(&mut self.grid, Grid::of_size_zero());
 		let grid = grid.add_to_right(chunk.grid);
 		self.grid = grid;
+		true
+	}
+}
+
+/// See `Map::water_adjacency`.
+#[derive(Clone, Copy)]
+struct WaterAdjacency {
+	water_on_the_top: bool,
+	/// The tile is on the top row of the map.
+	nothing_on_the_top: bool,
+	ground_on_the_top_left_corner: bool,
+	water_on_the_left: bool,
+}
+
+/// The column (on the top row of the spritesheet) of the water sprite variant that fits
+/// what is around the water tile.
+///
+/// Depending on weather there are water on some adjacent tiles, we render
+/// different variants of the base water sprite.
+/// This is done to give a sense of depth (the water level is thus
+/// percieved as a bit below ground level).
+fn water_sprite_index(adjacency: WaterAdjacency) -> i32 {
+	let WaterAdjacency {
+		water_on_the_top,
+		nothing_on_the_top,
+		ground_on_the_top_left_corner,
+		water_on_the_left,
+	} = adjacency;
+	6 + if nothing_on_the_top {
+		2
+	} else if water_on_the_top && ground_on_the_top_left_corner {
+		6
+	} else if water_on_the_top {
+		4
+	} else {
+		0
+	} + if water_on_the_left { 0 } else { 1 }
+}
+
+const PATH_UP: u8 = 1 << 0;
+const PATH_RIGHT: u8 = 1 << 1;
+const PATH_DOWN: u8 = 1 << 2;
+const PATH_LEFT: u8 = 1 << 3;
+const PATH_LEFT_RIGHT: u8 = PATH_LEFT | PATH_RIGHT;
+const PATH_UP_DOWN: u8 = PATH_UP | PATH_DOWN;
+const PATH_UP_LEFT: u8 = PATH_UP | PATH_LEFT;
+const PATH_DOWN_LEFT: u8 = PATH_DOWN | PATH_LEFT;
+const PATH_UP_RIGHT: u8 = PATH_UP | PATH_RIGHT;
+const PATH_DOWN_RIGHT: u8 = PATH_DOWN | PATH_RIGHT;
+
+/// Draws a path tile that connects in the given directions (see `Map::path_connections`)
+/// for which there is no sprite (dead ends, T-junctions and crossings) from parts of
+/// the sprite of the straight path on top of grass.
+///
+/// In the straight path sprite (horizontal), the road is the rows 2 to 13, so the center
+/// of the tile is road and each connection adds a 2 pixels wide strip of road up to the edge.
+fn draw_path_from_parts(renderer: &mut SoftwareRenderer, dst: Rect, connections: u8) {
+	let grass = Rect::tile((0, 0).into(), TILE_PX);
+	renderer.draw_sprite(dst, grass, DrawSpriteEffects::none());
+	let straight = Rect::tile((4, 0).into(), TILE_PX);
+	let unit = dst.dims.w / TILE_PX;
+	// Copies the given rect (in sprite pixels) of the straight path sprite to the given rect
+	// (also in sprite pixels) of the tile.
+	let mut draw_part = |part: Rect, to: Rect| {
+		let sprite = Rect::xywh(
+			straight.left() + part.left(),
+			straight.top() + part.top(),
+			part.dims.w,
+			part.dims.h,
+		);
+		let part_dst = Rect::xywh(
+			dst.left() + to.left() * unit,
+			dst.top() + to.top() * unit,
+			to.dims.w * unit,
+			to.dims.h * unit,
+		);
+		renderer.draw_sprite(part_dst, sprite, DrawSpriteEffects::none());
+	};
+	draw_part(Rect::xywh(2, 2, 12, 12), Rect::xywh(2, 2, 12, 12));
+	if connections & PATH_LEFT != 0 {
+		draw_part(Rect::xywh(0, 2, 2, 12), Rect::xywh(0, 2, 2, 12));
+	}
+	if connections & PATH_RIGHT != 0 {
+		draw_part(Rect::xywh(14, 2, 2, 12), Rect::xywh(14, 2, 2, 12));
+	}
+	if connections & PATH_UP != 0 {
+		draw_part(Rect::xywh(2, 2, 12, 2), Rect::xywh(2, 0, 12, 2));
+	}
+	if connections & PATH_DOWN != 0 {
+		draw_part(Rect::xywh(2, 2, 12, 2), Rect::xywh(2, 14, 12, 2));
 	}
 }
 
-fn draw_obj(renderer: &mut Renderer, obj: &Obj, mut dst: Rect, disappearing: bool) {
-	let mut effects = DrawSpriteEffects::none();
+fn draw_obj(
+	renderer: &mut SoftwareRenderer,
+	obj: &Obj,
+	mut dst: Rect,
+	disappearing: bool,
+	scale_mode: ScaleMode,
+) {
+	let mut effects = DrawSpriteEffects { scale_mode, ..DrawSpriteEffects::none() };
+	// Big objects are drawn over their whole footprint.
+	let footprint = obj.footprint();
+	dst.dims.w *= footprint.w;
+	dst.dims.h *= footprint.h;
 	if disappearing {
 		effects.paint = Some(Color::rgb_u8(255, 0, 0));
 	}
 	match obj {
 		Obj::Caravan => {
-			let sprite = Rect::tile((7, 2).into(), 16);
+			let sprite = Rect::tile((7, 2).into(), TILE_PX);
 			dst.top_left.y -= dst.dims.h * 3 / 16;
 			renderer.draw_sprite(dst, sprite, effects);
 		},
-		Obj::Tree => {
-			let mut sprite = Rect::tile((4, 2).into(), 16);
-			sprite.top_left.y -= 16;
-			sprite.dims.h += 16;
+		Obj::Tree { visual_variant } => {
+			assert!(*visual_variant < 2);
+			effects.flip_horizontally = *visual_variant == 1;
+			let mut sprite = Rect::tile((4, 2).into(), TILE_PX);
+			sprite.top_left.y -= TILE_PX;
+			sprite.dims.h += TILE_PX;
 			dst.top_left.y -= dst.dims.h;
 			dst.dims.h += dst.dims.h;
 			dst.top_left.y -= dst.dims.h / 16;
@@ -574,22 +1688,33 @@ fn draw_obj(renderer: &mut Renderer, obj: &Obj, mut dst: Rect, disappearing: boo
 		},
 		Obj::Rock { visual_variant } => {
 			assert!(*visual_variant < 3);
-			let sprite = Rect::tile((*visual_variant as i32, 2).into(), 16);
+			let sprite = Rect::tile((*visual_variant as i32, 2).into(), TILE_PX);
 			dst.top_left.y -= dst.dims.h * 3 / 16;
 			renderer.draw_sprite(dst, sprite, effects);
 		},
-		Obj::Crystal => {
-			let mut sprite = Rect::tile((3, 2).into(), 16);
-			sprite.top_left.y -= 16;
-			sprite.dims.h += 16;
+		Obj::Crystal { visual_variant } => {
+			assert!(*visual_variant < 2);
+			effects.flip_horizontally = *visual_variant == 1;
+			let mut sprite = Rect::tile((3, 2).into(), TILE_PX);
+			sprite.top_left.y -= TILE_PX;
+			sprite.dims.h += TILE_PX;
 			dst.top_left.y -= dst.dims.h;
 			dst.dims.h += dst.dims.h;
 			dst.top_left.y -= dst.dims.h / 16;
 			renderer.draw_sprite(dst, sprite, effects);
 		},
-		Obj::Enemy { actions, hp, fire, alive_animation, colored_animation, .. } => {
+		Obj::Enemy {
+			actions,
+			hp,
+			fire,
+			shield,
+			alive_animation,
+			colored_animation,
+			base_tint,
+			..
+		} => {
 			let initial_dst = dst;
-			let sprite = Rect::tile((4, 8).into(), 16);
+			let sprite = Rect::tile((4, 8).into(), TILE_PX);
 			dst.top_left.y -= dst.dims.h * 3 / 16;
 			let unsquished_dst = dst;
 			if let Some(anim) = alive_animation {
@@ -609,10 +1734,10 @@ fn draw_obj(renderer: &mut Renderer, obj: &Obj, mut dst: Rect, disappearing: boo
 					let progress = anim.tp.progress();
 					if progress < 0.5 {
 						// Normal -> squihed.
-						dst = linear_interpolation_rect(progress * 2.0, dst, dst_squish);
+						dst = lerp_rect(progress * 2.0, dst, dst_squish);
 					} else {
 						// Squished -> normal.
-						dst = linear_interpolation_rect(progress * 2.0 - 1.0, dst_squish, dst);
+						dst = lerp_rect(progress * 2.0 - 1.0, dst_squish, dst);
 					}
 				}
 			}
@@ -626,22 +1751,32 @@ fn draw_obj(renderer: &mut Renderer, obj: &Obj, mut dst: Rect, disappearing: boo
 			} else {
 				None
 			};
-			if let Some(color) = color {
+			// The flashing color takes priority over the base tint while it lasts.
+			if let Some(color) = color.or(*base_tint) {
 				effects.paint = Some(color);
 			}
 			renderer.draw_sprite(dst, sprite, effects);
+			draw_shield(renderer, initial_dst, *shield);
 			// Now we render the hp counter of the enemy above it (centered),
 			// and we make it go up with the squishing during "alive" animations (because its cute!).
-			let mut top_center = unsquished_dst.top_left;
-			top_center.x += unsquished_dst.dims.w / 2;
-			top_center.y += unsquished_dst.dims.h / 10 + (unsquished_dst.dims.h - dst.dims.h);
+			let top_center = Coords::from((
+				unsquished_dst.center().x,
+				unsquished_dst.top()
+					+ unsquished_dst.dims.h / 10
+					+ (unsquished_dst.dims.h - dst.dims.h),
+			));
 			Font {
 				size_factor: 3,
 				horizontal_spacing: 2,
 				space_width: 7,
 				foreground: color.unwrap_or(Color::WHITE),
 				background: Some(Color::BLACK),
+				outline: None,
+				shadow: None,
 				margins: (3, 3).into(),
+				strict: false,
+				capital_size_factor: None,
+				monospace_width: None,
 			}
 			.draw_text_line(renderer, &format!("{hp}"), top_center, PinPoint::TOP_CENTER)
 			.unwrap();
@@ -651,7 +1786,7 @@ fn draw_obj(renderer: &mut Renderer, obj: &Obj, mut dst: Rect, disappearing: boo
 				let sprite = Rect::xywh(22, 17, 6, 6);
 				let fire_dst = Rect {
 					top_left: initial_dst.top_left + CoordsDelta::from((-4, 4)),
-					dims: sprite.dims * (initial_dst.dims.w / 16),
+					dims: sprite.dims * (initial_dst.dims.w / TILE_PX),
 				};
 				renderer.draw_sprite(fire_dst, sprite, DrawSpriteEffects::none());
 				if *fire >= 2 {
@@ -661,24 +1796,28 @@ fn draw_obj(renderer: &mut Renderer, obj: &Obj, mut dst: Rect, disappearing: boo
 						space_width: 7,
 						foreground: Color::rgb_u8(255, 0, 0),
 						background: Some(Color::BLACK),
+						outline: None,
+						shadow: None,
 						margins: (3, 3).into(),
+						strict: false,
+						capital_size_factor: None,
+						monospace_width: None,
 					}
-					.draw_text_line(
+					.draw_text_line_right_anchored(
 						renderer,
 						&format!("{fire}"),
 						(fire_dst.left(), fire_dst.top() + fire_dst.dims.h / 2).into(),
-						PinPoint::CENTER_RIGHT,
 					)
 					.unwrap();
 				}
 			}
 			if *actions >= 1 {
 				let sprite = Rect::xywh(1, 17, 6, 6);
-				let dims = sprite.dims * (initial_dst.dims.w / 16);
+				let dims = sprite.dims * (initial_dst.dims.w / TILE_PX);
 				let actions_dst = Rect {
 					top_left: initial_dst.top_left
 						+ CoordsDelta::from((-4, initial_dst.dims.h - 4 - dims.h)),
-					dims: sprite.dims * (initial_dst.dims.w / 16),
+					dims: sprite.dims * (initial_dst.dims.w / TILE_PX),
 				};
 				renderer.draw_sprite(actions_dst, sprite, DrawSpriteEffects::none());
 				if *actions >= 2 {
@@ -688,9 +1827,14 @@ fn draw_obj(renderer: &mut Renderer, obj: &Obj, mut dst: Rect, disappearing: boo
 						space_width: 7,
 						foreground: Color::rgb_u8(255, 255, 0),
 						background: Some(Color::BLACK),
+						outline: None,
+						shadow: None,
 						margins: (3, 3).into(),
+						strict: false,
+						capital_size_factor: None,
+						monospace_width: None,
 					}
-					.draw_text_line(
+					.draw_text_line_right_anchored(
 						renderer,
 						&format!("{actions}"),
 						(
@@ -698,28 +1842,45 @@ fn draw_obj(renderer: &mut Renderer, obj: &Obj, mut dst: Rect, disappearing: boo
 							actions_dst.top() + actions_dst.dims.h / 2,
 						)
 							.into(),
-						PinPoint::CENTER_RIGHT,
 					)
 					.unwrap();
 				}
 			}
 		},
-		Obj::Tower { actions, fire, variant, .. } => {
+		Obj::Tower { actions, fire, shield, variant, enabled, .. } => {
 			let sprite_x = match variant {
 				Tower::Basic => 8,
 				Tower::Pink => 9,
 				Tower::Blue => 10,
+				Tower::Fortress => 8,
+				Tower::Storm => 10,
+				Tower::Support => 8,
 			};
-			let sprite = Rect::tile((sprite_x, 4).into(), 16);
+			let sprite = Rect::tile((sprite_x, 4).into(), TILE_PX);
 			dst.top_left.y -= dst.dims.h * 2 / 16;
 			renderer.draw_sprite(dst, sprite, effects);
+			if matches!(variant, Tower::Storm) {
+				// The blue tower sprite, charged with electricity.
+				let charge = Some(Color::rgba_u8(255, 230, 60, 110));
+				renderer.draw_sprite(dst, sprite, DrawSpriteEffects { paint: charge, ..effects });
+			} else if matches!(variant, Tower::Support) {
+				// The basic tower sprite, in the color of its aura.
+				let aura = Some(SUPPORT_AURA_COLOR);
+				renderer.draw_sprite(dst, sprite, DrawSpriteEffects { paint: aura, ..effects });
+			}
+			if !*enabled && !disappearing {
+				// Dimmed by drawing it again in a translucent dark color.
+				let dimming = Some(Color::rgba_u8(20, 20, 30, 140));
+				renderer.draw_sprite(dst, sprite, DrawSpriteEffects { paint: dimming, ..effects });
+			}
+			draw_shield(renderer, dst, *shield);
 
 			// Draw fire and action counter in the front.
 			if *fire >= 1 {
 				let sprite = Rect::xywh(22, 17, 6, 6);
 				let fire_dst = Rect {
 					top_left: dst.top_left + CoordsDelta::from((-4, 4)),
-					dims: sprite.dims * (dst.dims.w / 16),
+					dims: sprite.dims * (dst.dims.w / TILE_PX),
 				};
 				renderer.draw_sprite(fire_dst, sprite, DrawSpriteEffects::none());
 				if *fire >= 2 {
@@ -729,23 +1890,27 @@ fn draw_obj(renderer: &mut Renderer, obj: &Obj, mut dst: Rect, disappearing: boo
 						space_width: 7,
 						foreground: Color::rgb_u8(255, 0, 0),
 						background: Some(Color::BLACK),
+						outline: None,
+						shadow: None,
 						margins: (3, 3).into(),
+						strict: false,
+						capital_size_factor: None,
+						monospace_width: None,
 					}
-					.draw_text_line(
+					.draw_text_line_right_anchored(
 						renderer,
 						&format!("{fire}"),
 						(fire_dst.left(), fire_dst.top() + fire_dst.dims.h / 2).into(),
-						PinPoint::CENTER_RIGHT,
 					)
 					.unwrap();
 				}
 			}
 			if *actions >= 1 {
 				let sprite = Rect::xywh(1, 17, 6, 6);
-				let dims = sprite.dims * (dst.dims.w / 16);
+				let dims = sprite.dims * (dst.dims.w / TILE_PX);
 				let actions_dst = Rect {
 					top_left: dst.top_left + CoordsDelta::from((-4, dst.dims.h - 4 - dims.h)),
-					dims: sprite.dims * (dst.dims.w / 16),
+					dims: sprite.dims * (dst.dims.w / TILE_PX),
 				};
 				renderer.draw_sprite(actions_dst, sprite, DrawSpriteEffects::none());
 				if *actions >= 2 {
@@ -755,9 +1920,14 @@ fn draw_obj(renderer: &mut Renderer, obj: &Obj, mut dst: Rect, disappearing: boo
 						space_width: 7,
 						foreground: Color::rgb_u8(255, 255, 0),
 						background: Some(Color::BLACK),
+						outline: None,
+						shadow: None,
 						margins: (3, 3).into(),
+						strict: false,
+						capital_size_factor: None,
+						monospace_width: None,
 					}
-					.draw_text_line(
+					.draw_text_line_right_anchored(
 						renderer,
 						&format!("{actions}"),
 						(
@@ -765,43 +1935,404 @@ fn draw_obj(renderer: &mut Renderer, obj: &Obj, mut dst: Rect, disappearing: boo
 							actions_dst.top() + actions_dst.dims.h / 2,
 						)
 							.into(),
-						PinPoint::CENTER_RIGHT,
 					)
 					.unwrap();
 				}
 			}
 		},
+		// The big object the part is a part of is drawn over it.
+		Obj::Part { .. } => {},
+	}
+}
+
+/// Draws a ring around the object (in `dst`) and the amount of shield at its top right,
+/// if it has any shield.
+fn draw_shield(renderer: &mut SoftwareRenderer, dst: Rect, shield: i32) {
+	if shield <= 0 {
+		return;
+	}
+	let center = dst.center();
+	let radius = dst.dims.w.min(dst.dims.h) as f32 / 2.0;
+	let point_on_ring = |i: i32| {
+		let angle = i as f32 / 24.0 * std::f32::consts::TAU;
+		let x = center.x as f32 + angle.cos() * radius;
+		let y = center.y as f32 + angle.sin() * radius;
+		Coords::from((x.round() as i32, y.round() as i32))
+	};
+	for i in 0..24 {
+		renderer.draw_line(point_on_ring(i), point_on_ring(i + 1), SHIELD_COLOR);
+	}
+	Font {
+		size_factor: 3,
+		horizontal_spacing: 2,
+		space_width: 7,
+		foreground: SHIELD_COLOR,
+		background: Some(Color::BLACK),
+		outline: None,
+		shadow: None,
+		margins: (3, 3).into(),
+		strict: false,
+		capital_size_factor: None,
+		monospace_width: None,
+	}
+	.draw_text_line(
+		renderer,
+		&format!("{shield}"),
+		(dst.right_excluded() + 4, dst.top() + 4).into(),
+		PinPoint::TOP_RIGHT,
+	)
+	.unwrap();
+}
+
+/// The lines of the tooltip about the object at `coords`, if it is something worth a tooltip.
+fn obj_tooltip_lines(map: &Map, coords: Coords, balance: &Balance) -> Option<Vec<String>> {
+	// The parts of a big object show the tooltip of the whole object.
+	let coords = map.anchor_of(coords);
+	let obj = map.grid.get(coords)?.obj.as_ref()?;
+	let mut lines = vec![obj.name().to_string()];
+	match obj {
+		Obj::Enemy { hp, fire, shield, variant, .. } => {
+			lines.push(format!("hp: {hp}"));
+			if let Enemy::Regenerator { max_hp, .. } = variant {
+				lines.push(format!(
+					"heals up to {max_hp} hp when not hit for {} turns",
+					balance.regeneration_delay
+				));
+			}
+			if *shield > 0 {
+				lines.push(format!("shield: {shield}"));
+			}
+			if *fire > 0 {
+				lines.push(format!("fire: {fire}"));
+			}
+			// Enemies walk on the path, but it doesn't hurt to check.
+			match map.path_dist_from_caravan(coords) {
+				Some(distance) => lines.push(format!("{distance} tiles from the caravan")),
+				None if map.grid.at(coords).path().is_none() => lines.push("off the path".to_string()),
+				None => {},
+			}
+		},
+		Obj::Tower { hp, fire, shield, variant, target_priority, enabled, .. } => {
+			lines.push(format!("hp: {hp}"));
+			if *shield > 0 {
+				lines.push(format!("shield: {shield}"));
+			}
+			if *fire > 0 {
+				lines.push(format!("fire: {fire}"));
+			}
+			match variant.shot() {
+				Some(shot) => lines.push(format!("damage: {}", shot.total_damages())),
+				None => lines.push(format!(
+					"adjacent towers deal +{} damage",
+					balance.support_damage_bonus
+				)),
+			}
+			if map.is_supported(coords) {
+				lines.push(format!(
+					"supported: +{} damage",
+					balance.support_damage_bonus
+				));
+			}
+			// Towers see in the 4 directions until something is in the way.
+			lines.push("range: line of sight".to_string());
+			lines.push(format!("targets: {}", target_priority.name()));
+			if !*enabled {
+				lines.push("holding fire".to_string());
+			}
+			lines.push(format!("price: {}", balance.tower_price(variant)));
+		},
+		Obj::Crystal { .. } => lines.push(format!("mining gives {}", balance.mining_yield)),
+		_ => return None,
+	}
+	Some(lines)
+}
+
+/// Draws a box with the given lines of text in it next to the anchor point
+/// (below and to the right of it, unless it would go out of the screen on that side).
+fn draw_tooltip(renderer: &mut SoftwareRenderer, anchor: Coords, lines: &[String]) {
+	let font = Font {
+		size_factor: 2,
+		horizontal_spacing: 2,
+		space_width: 7,
+		foreground: Color::WHITE,
+		background: None,
+		outline: None,
+		shadow: None,
+		margins: (0, 0).into(),
+		strict: false,
+		capital_size_factor: None,
+		monospace_width: None,
+	};
+	let padding = 6;
+	let line_spacing = 4;
+	let mut text_dims = Dimensions { w: 0, h: 0 };
+	for line in lines {
+		let line_dims = font.text_line_dims(line).unwrap();
+		text_dims.w = text_dims.w.max(line_dims.w);
+		text_dims.h += line_dims.h + line_spacing;
+	}
+	text_dims.h -= line_spacing;
+	let mut dims = text_dims + CoordsDelta::from((padding * 2, padding * 2));
+	// In a small window, the lines that are too long are cut at the right edge of the window,
+	// and the lines that don't fit are cut at the bottom edge.
+	dims.w = dims.w.min(renderer.dims().w);
+	dims.h = dims.h.min(renderer.dims().h);
+
+	// A bit away from the anchor so that the cursor does not hide the text.
+	let offset = 12;
+	let mut top_left = anchor + CoordsDelta::from((offset, offset));
+	if top_left.x + dims.w > renderer.dims().w {
+		top_left.x = (anchor.x - offset - dims.w).max(0);
 	}
+	if top_left.y + dims.h > renderer.dims().h {
+		top_left.y = (anchor.y - offset - dims.h).max(0);
+	}
+	let dst = Rect { top_left, dims };
+
+	renderer.draw_rect(dst, Color::BLACK);
+	renderer.draw_rect_edge(dst, Color::rgb_u8(120, 120, 120));
+	renderer.push_clip(dst.add_margin(-padding));
+	let mut head = top_left + CoordsDelta::from((padding, padding));
+	for line in lines {
+		let rect = font
+			.draw_text_line(renderer, line, head, PinPoint::TOP_LEFT)
+			.unwrap();
+		head.y += rect.dims.h + line_spacing;
+	}
+	renderer.pop_clip();
 }
 
-fn draw_shot(renderer: &mut Renderer, dst: Rect) {
-	let sprite = Rect::tile((8, 6).into(), 16);
+fn draw_shot(renderer: &mut SoftwareRenderer, dst: Rect) {
+	let sprite = Rect::tile((8, 6).into(), TILE_PX);
 	renderer.draw_sprite(dst, sprite, DrawSpriteEffects::none());
 }
 
+/// A zigzag line from `from` to `to` (in pixels), that flickers as `progress` goes from 0 to 1.
+fn draw_lightning(renderer: &mut SoftwareRenderer, from: Coords, to: Coords, progress: f32) {
+	let delta = to - from;
+	let length = (delta.length_squared() as f32).sqrt().max(1.0);
+	// Unit vector perpendicular to the arc, the zigzag goes back and forth along it.
+	let (perp_x, perp_y) = (-delta.dy as f32 / length, delta.dx as f32 / length);
+	let amplitude = length / 10.0;
+	let flicker = if (progress * 6.0) as i32 % 2 == 0 {
+		1.0
+	} else {
+		-1.0
+	};
+	let segment_count = 5;
+	let point = |i: i32| {
+		let t = i as f32 / segment_count as f32;
+		let offset = if i == 0 || i == segment_count {
+			0.0
+		} else if i % 2 == 0 {
+			amplitude * flicker
+		} else {
+			-amplitude * flicker
+		};
+		Coords::from((
+			(from.x as f32 + delta.dx as f32 * t + perp_x * offset).round() as i32,
+			(from.y as f32 + delta.dy as f32 * t + perp_y * offset).round() as i32,
+		))
+	};
+	for i in 0..segment_count {
+		let (a, b) = (point(i), point(i + 1));
+		renderer.draw_line(a, b, Color::rgb_u8(255, 250, 200));
+		renderer.draw_line(
+			a + CoordsDelta::from((1, 0)),
+			b + CoordsDelta::from((1, 0)),
+			SHIELD_COLOR,
+		);
+	}
+}
+
+/// An arrow from the enemy drawn at `tile_dst` towards the tile it will move to next,
+/// with the number of steps it will take in the next enemy phase if there are more than one.
+fn draw_enemy_move_arrow(
+	renderer: &mut SoftwareRenderer,
+	tile_dst: Rect,
+	direction: CoordsDelta,
+	steps: i32,
+) {
+	let color = Color::rgba_u8(255, 90, 90, 200);
+	let side = tile_dst.dims.w;
+	let perpendicular = direction.rotated_clockwise();
+	// From inside the enemy to the middle of the next tile, so that it is not hidden by it.
+	let tail = tile_dst.center() + direction * (side / 4);
+	let tip = tile_dst.center() + direction * side;
+	let head_length = side / 4;
+	for offset in -1..=1 {
+		renderer.draw_line(
+			tail + perpendicular * offset,
+			tip - direction * head_length + perpendicular * offset,
+			color,
+		);
+	}
+	// The head is a triangle, drawn like the spawn indicator.
+	for i in 0..head_length {
+		let base = tip - direction * i;
+		renderer.draw_line(
+			base + perpendicular * (i / 2),
+			base - perpendicular * (i / 2),
+			color,
+		);
+	}
+	if steps >= 2 {
+		Font {
+			size_factor: 2,
+			horizontal_spacing: 2,
+			space_width: 5,
+			foreground: Color::rgb_u8(255, 255, 0),
+			background: Some(Color::BLACK),
+			outline: None,
+			shadow: None,
+			margins: (2, 2).into(),
+			strict: false,
+			capital_size_factor: None,
+			monospace_width: None,
+		}
+		.draw_text_line(renderer, &format!("{steps}"), tip, PinPoint::CENTER_CENTER)
+		.unwrap();
+	}
+}
+
+/// The probabilities that drive the generation of a chunk.
+struct WorldGenParams {
+	/// Probability to add a water puddle, checked again after each added puddle.
+	water_puddle_probability: f32,
+	/// Probability for a grass tile to get a tree, on the top and bottom rows.
+	tree_probability_on_edges: f32,
+	/// Probability for a grass tile to get a tree, elsewhere.
+	tree_probability: f32,
+	rock_probability: f32,
+	/// Probability for a grass tile to get a crystal, on the second and second-to-last rows.
+	crystal_probability_near_edges: f32,
+	/// Probability for a grass tile to get a crystal, elsewhere.
+	crystal_probability: f32,
+	/// Probability for a path tile to get an enemy.
+	enemy_probability: f32,
+}
+
+/// Each chunk is of one biome, which changes how it looks (see `Biome::world_gen_params`).
+#[derive(Clone, Copy, Debug)]
+enum Biome {
+	Plains,
+	/// More trees.
+	Forest,
+	/// More rocks and fewer crystals.
+	Rocky,
+	/// More water.
+	Lake,
+}
+
+impl Biome {
+	fn world_gen_params(self) -> WorldGenParams {
+		let plains = WorldGenParams {
+			water_puddle_probability: 0.4,
+			tree_probability_on_edges: 0.3,
+			tree_probability: 0.05,
+			rock_probability: 0.05,
+			crystal_probability_near_edges: 0.03,
+			crystal_probability: 0.006,
+			enemy_probability: 0.4,
+		};
+		match self {
+			Biome::Plains => plains,
+			Biome::Forest => WorldGenParams {
+				tree_probability_on_edges: 0.6,
+				tree_probability: 0.25,
+				rock_probability: 0.02,
+				..plains
+			},
+			Biome::Rocky => WorldGenParams {
+				tree_probability_on_edges: 0.15,
+				tree_probability: 0.02,
+				rock_probability: 0.2,
+				crystal_probability_near_edges: 0.015,
+				crystal_probability: 0.003,
+				..plains
+			},
+			Biome::Lake => {
+				WorldGenParams { water_puddle_probability: 0.85, tree_probability: 0.03, ..plains }
+			},
+		}
+	}
+
+	/// The first chunks are plains, then the other biomes get more and more common
+	/// as the caravan gets further.
+	fn random_for_chunk(chunk_index: i32) -> Biome {
+		let other_than_plains_probability = (chunk_index as f32 * 0.1).min(0.7);
+		if rand_range(0.0..1.0) < other_than_plains_probability {
+			[Biome::Forest, Biome::Rocky, Biome::Lake][rand_range(0..3)]
+		} else {
+			Biome::Plains
+		}
+	}
+}
+
+/// How much the path generation constraints are relaxed, each variant dropping one more
+/// constraint than the previous one.
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Debug)]
+enum PathRelaxation {
+	None,
+	/// The path may do as many U-turns as it wants.
+	AnyUTurnCount,
+	/// The path doesn't have to go westward at all.
+	AnyWestwardCount,
+	/// The path may be as short as it wants (but the maximum length still holds).
+	AnyLength,
+}
+
+impl PathRelaxation {
+	fn after_tries(tries: u32) -> PathRelaxation {
+		match tries / Chunk::PATH_TRIES_PER_RELAXATION {
+			0 => PathRelaxation::None,
+			1 => PathRelaxation::AnyUTurnCount,
+			2 => PathRelaxation::AnyWestwardCount,
+			_ => PathRelaxation::AnyLength,
+		}
+	}
+}
+
 /// A pice of world that can be generated independently.
 struct Chunk {
-	/// A 10x10 grid.
+	/// A grid of the dimensions given to `Chunk::generate`.
 	grid: Grid<Tile>,
 }
 
 impl Chunk {
+	/// The path generation constraints are tuned for these, other dimensions get them scaled.
+	const DEFAULT_DIMS: Dimensions = Dimensions { w: 10, h: 10 };
+	/// Some dimensions (or bad luck) make the path constraints hard or impossible to satisfy,
+	/// so every that many failed tries one more constraint is dropped (see `PathRelaxation`).
+	const PATH_TRIES_PER_RELAXATION: u32 = 50_000;
+	/// After that many failed tries (with all the constraints dropped) the path is just
+	/// a straight line.
+	const MAX_PATH_TRIES: u32 = Chunk::PATH_TRIES_PER_RELAXATION * 4;
+
 	/// Generates a new random chunk of world.
 	/// The path must continue from where it stopped at the right side of the previous chunk,
 	/// so we must pass that information via `last_path_y_and_dist`.
-	fn generate(last_path_y_and_dist: Option<(i32, i32)>) -> Chunk {
+	fn generate(dims: Dimensions, last_path_y_and_dist: Option<(i32, i32)>, biome: Biome) -> Chunk {
+		let params = biome.world_gen_params();
+		// The path length in the chunk must be in this range, which is 14..30 for 10x10 chunks
+		// (scaled with the width for the minimum and with the area for the maximum).
+		let min_path_length = dims.w * 14 / 10;
+		let max_path_length = (dims.area() as i32 * 30 / 100).max(min_path_length + 1);
+		let mut tries = 0;
 		let mut grid = 'try_new_path: loop {
-			// Initialize with only grass.
-			let mut grid = Grid::new((10, 10).into(), |_coords: Coords| Tile {
-				ground: Ground::Grass {
-					visual_variant: if rand_range(0..4) == 0 {
-						rand_range(1..4)
-					} else {
-						0
-					},
-				},
-				obj: None,
-			});
+			if Chunk::MAX_PATH_TRIES <= tries {
+				log::warn!(
+					"failed to generate a path in a {dims} chunk in {tries} tries, it will be straight"
+				);
+				let (path_y, path_dist) = last_path_y_and_dist
+					.map(|(y, d)| (y, d + 1))
+					.unwrap_or((dims.h / 2, 0));
+				break Chunk::straight_path_grid(dims, path_y, path_dist);
+			}
+			let relaxation = PathRelaxation::after_tries(tries);
+			tries += 1;
+
+			let mut grid = Chunk::grass_grid(dims);
 
 			// We generate the path by moving `cur_head` around randomly and drawing the path.
 			// If it doesn't work then we just try again until it works >w<.
@@ -825,6 +2356,7 @@ impl Chunk {
 					})
 					.collect();
 				if possible_directions.is_empty() {
+					log::trace!("path try {tries} rejected: stuck at {cur_head}");
 					continue 'try_new_path;
 				} else {
 					let direction =
@@ -835,7 +2367,7 @@ impl Chunk {
 						};
 					let backward = prev_head - cur_head;
 					let forward = direction;
-					grid.get_mut(cur_head).unwrap().ground =
+					grid.at_mut(cur_head).ground =
 						Ground::Path(Path { forward, backward, distance: path_dist });
 					let it_turns_now =
 						!((backward.dx == 0 && forward.dx == 0) || (backward.dy == 0 && forward.dy == 0));
@@ -849,7 +2381,7 @@ impl Chunk {
 								let other_coords = cur_head + other_direction;
 								if let Some(other_tile) = grid.get_mut(other_coords) {
 									if other_tile.is_empty_grass() {
-										other_tile.obj = Some(Obj::Tree);
+										other_tile.obj = Some(Obj::Tree { visual_variant: 0 });
 									}
 								}
 							}
@@ -873,7 +2405,7 @@ impl Chunk {
 						let other_coords = cur_head + direction;
 						if let Some(other_tile) = grid.get_mut(other_coords) {
 							if other_tile.is_empty_grass() {
-								other_tile.obj = Some(Obj::Tree);
+								other_tile.obj = Some(Obj::Tree { visual_variant: 0 });
 							}
 						}
 					}
@@ -882,24 +2414,45 @@ impl Chunk {
 					}
 				}
 			}
-			if how_many_times_does_it_go_westward < 2
-				|| !(14..30).contains(&distance_in_chunk)
-				|| u_turn_count >= 2
+			let min_path_length = if relaxation >= PathRelaxation::AnyLength {
+				0
+			} else {
+				min_path_length
+			};
+			if (relaxation < PathRelaxation::AnyWestwardCount
+				&& how_many_times_does_it_go_westward < 2)
+				|| !(min_path_length..max_path_length).contains(&distance_in_chunk)
+				|| (relaxation < PathRelaxation::AnyUTurnCount && u_turn_count >= 2)
 			{
+				log::trace!(
+					"path try {tries} rejected: length {distance_in_chunk} \
+					(expected {min_path_length}..{max_path_length}), \
+					{how_many_times_does_it_go_westward} westward steps, {u_turn_count} u-turns"
+				);
 				continue 'try_new_path;
 			}
+			if relaxation == PathRelaxation::None {
+				log::debug!(
+					"generated a path of length {distance_in_chunk} in a {dims} chunk in {tries} tries"
+				);
+			} else {
+				log::info!(
+					"generated a path of length {distance_in_chunk} in a {dims} chunk in {tries} tries \
+					(relaxed: {relaxation:?})"
+				);
+			}
 			// Clean up the trees we planted just to help with path generation.
 			for coords in grid.dims.iter() {
-				grid.get_mut(coords).unwrap().obj = None;
+				grid.at_mut(coords).obj = None;
 			}
 			break grid;
 		};
 
 		// Generate some water.
-		while rand_range(0.0..1.0) < 0.4 {
+		while rand_range(0.0..1.0) < params.water_puddle_probability {
 			let mut coords = (rand_range(0..grid.dims.w), rand_range(0..grid.dims.h)).into();
 			loop {
-				let tile = grid.get_mut(coords).unwrap();
+				let tile = grid.at_mut(coords);
 				if tile.has_path() || tile.has_water() || rand_range(0..3) == 0 {
 					break;
 				}
@@ -909,31 +2462,37 @@ impl Chunk {
 					.unwrap();
 				if grid.get(coords + dxdy).is_some_and(|tile| !tile.has_path()) {
 					coords += dxdy;
+				} else if let Some(Ground::Path(path)) =
+					grid.get(coords + dxdy).map(|tile| &tile.ground)
+				{
+					// The water may flow under a straight part of the path that crosses its way,
+					// which then becomes a bridge (keeping its path info).
+					let path_crosses_the_way = path.forward == CoordsDelta::from((dxdy.dy, dxdy.dx))
+						|| path.forward == -CoordsDelta::from((dxdy.dy, dxdy.dx));
+					let is_straight = path.forward == -path.backward;
+					let beyond = coords + dxdy + dxdy;
+					if path_crosses_the_way
+						&& is_straight
+						&& grid
+							.get(beyond)
+							.is_some_and(|tile| !tile.has_path() && !tile.has_water())
+						&& rand_range(0.0..1.0) < 0.3
+					{
+						let path = path.clone();
+						grid.at_mut(coords + dxdy).ground = Ground::Bridge(path);
+						coords = beyond;
+					}
 				}
 			}
 		}
 
-		// Generate some trees.
-		let dims = grid.dims;
-		for coords in grid.dims.iter() {
-			let tile = grid.get_mut(coords).unwrap();
-			if tile.is_empty_grass() {
-				let tree_probability = if coords.y == 0 || coords.y == dims.h - 1 {
-					0.3
-				} else {
-					0.05
-				};
-				if rand_range(0.0..1.0) < tree_probability {
-					tile.obj = Some(Obj::Tree);
-				}
-			}
-		}
+		Chunk::generate_trees(&mut grid, &params);
 
 		// Generate some rocks.
 		for coords in grid.dims.iter() {
-			let tile = grid.get_mut(coords).unwrap();
+			let tile = grid.at_mut(coords);
 			if tile.is_empty_grass() {
-				let rock_probability = 0.05;
+				let rock_probability = params.rock_probability;
 				if rand_range(0.0..1.0) < rock_probability {
 					tile.obj = Some(Obj::Rock { visual_variant: rand_range(0..3) });
 				}
@@ -945,15 +2504,15 @@ impl Chunk {
 		let mut crystal_count = 0;
 		for _i in 0..30 {
 			for coords in grid.dims.iter() {
-				let tile = grid.get_mut(coords).unwrap();
+				let tile = grid.at_mut(coords);
 				if tile.is_empty_grass() {
 					let crystal_probability = if coords.y == 1 || coords.y == dims.h - 2 {
-						0.03
+						params.crystal_probability_near_edges
 					} else {
-						0.006
+						params.crystal_probability
 					};
 					if rand_range(0.0..1.0) < crystal_probability {
-						tile.obj = Some(Obj::Crystal);
+						tile.obj = Some(Obj::Crystal { visual_variant: rand_range(0..2) });
 						crystal_count += 1;
 					}
 				}
@@ -964,25 +2523,79 @@ impl Chunk {
 		}
 
 		// Generate some enemies.
+		let mut enemy_count = 0;
 		for coords in grid.dims.iter() {
-			let tile = grid.get_mut(coords).unwrap();
+			let tile = grid.at_mut(coords);
 			if tile.has_path() {
-				let enemy_probability = 0.4;
+				let enemy_probability = params.enemy_probability;
 				if rand_range(0.0..1.0) < enemy_probability {
+					enemy_count += 1;
 					tile.obj = Some(Obj::Enemy {
 						actions: 0,
 						hp: 8,
 						fire: 0,
+						shield: 0,
 						alive_animation: None,
 						colored_animation: None,
+						base_tint: None,
 						variant: Enemy::Basic,
 					});
 				}
 			}
 		}
 
+		log::debug!(
+			"generated a {biome:?} chunk with {crystal_count} crystals and {enemy_count} enemies"
+		);
 		Chunk { grid }
 	}
+
+	/// Grass with a few visual variants here and there.
+	fn grass_grid(dims: Dimensions) -> Grid<Tile> {
+		Grid::new(dims, |_coords: Coords| Tile {
+			ground: Ground::Grass {
+				visual_variant: if rand_range(0..4) == 0 {
+					rand_range(1..4)
+				} else {
+					0
+				},
+			},
+			obj: None,
+		})
+	}
+
+	/// Plants trees on the empty grass tiles, more of them on the top and bottom rows.
+	fn generate_trees(grid: &mut Grid<Tile>, params: &WorldGenParams) {
+		let dims = grid.dims;
+		for coords in dims.iter() {
+			let tile = grid.at_mut(coords);
+			if tile.is_empty_grass() {
+				let tree_probability = if coords.y == 0 || coords.y == dims.h - 1 {
+					params.tree_probability_on_edges
+				} else {
+					params.tree_probability
+				};
+				if rand_range(0.0..1.0) < tree_probability {
+					tile.obj = Some(Obj::Tree { visual_variant: rand_range(0..2) });
+				}
+			}
+		}
+	}
+
+	/// A path that just goes straight from the left side to the right side on the `path_y` row,
+	/// its leftmost tile being at distance `path_dist`.
+	/// It is the fallback for when no nice path could be generated.
+	fn straight_path_grid(dims: Dimensions, path_y: i32, path_dist: i32) -> Grid<Tile> {
+		let mut grid = Chunk::grass_grid(dims);
+		for x in 0..dims.w {
+			grid.at_mut((x, path_y).into()).ground = Ground::Path(Path {
+				forward: CoordsDelta::RIGHT,
+				backward: CoordsDelta::LEFT,
+				distance: path_dist + x,
+			});
+		}
+		grid
+	}
 }
 
 /// When a shot hits its target, it may (or may not) spawn new shots fro that target
@@ -993,27 +2606,101 @@ enum ShotCascade {
 	None,
 	Piercing(Box<Shot>),
 	SplitInTwo(Box<Shot>),
+	/// The shot arcs (see `AnimationAction::Arc`) from what it hits to the nearest hostile object
+	/// within `radius` (in tiles) that it did not hit yet, with one less damage, and so on
+	/// until there are no jumps left or no damages left to deal.
+	Chain {
+		jumps_left: i32,
+		radius: i32,
+		/// The anchors of the objects hit by the previous jumps, so that it never loops.
+		already_hit: Vec<Coords>,
+	},
 }
 
 #[derive(Clone)]
 struct Shot {
 	damages: i32,
 	fire: i32,
+	/// Shield granted to what is hit (after the damages of the shot), see `absorb_damages`.
+	shield: i32,
 	additional_actions: i32,
 	cascade: ShotCascade,
 }
 
+impl Shot {
+	/// The damages dealt if everything that can be hit by the shot and its cascade is hit
+	/// (negative damages heal).
+	fn total_damages(&self) -> i32 {
+		self.damages
+			+ match &self.cascade {
+				ShotCascade::None => 0,
+				ShotCascade::Piercing(shot) => shot.total_damages(),
+				ShotCascade::SplitInTwo(shot) => shot.total_damages() * 2,
+				ShotCascade::Chain { jumps_left, .. } => (1..=*jumps_left)
+					.map(|jump| (self.damages - jump).max(0))
+					.sum(),
+			}
+	}
+
+	/// The stats of the shot for the shot inspector (a debug tool), the shots of the cascade
+	/// follow (indented) so that the whole shot definition can be checked.
+	fn inspector_lines(&self) -> Vec<String> {
+		let mut lines = vec![
+			format!("damages: {}", self.damages),
+			format!("fire: {}", self.fire),
+			format!("shield: {}", self.shield),
+			format!("additional actions: {}", self.additional_actions),
+		];
+		let next_shot = match &self.cascade {
+			ShotCascade::None => {
+				lines.push("cascade: none".to_string());
+				None
+			},
+			ShotCascade::Piercing(shot) => {
+				lines.push("cascade: piercing".to_string());
+				Some(shot)
+			},
+			ShotCascade::SplitInTwo(shot) => {
+				lines.push("cascade: split in two".to_string());
+				Some(shot)
+			},
+			ShotCascade::Chain { jumps_left, radius, .. } => {
+				lines.push(format!(
+					"cascade: chain ({jumps_left} jumps left, radius {radius})"
+				));
+				None
+			},
+		};
+		if let Some(shot) = next_shot {
+			lines.extend(
+				shot
+					.inspector_lines()
+					.into_iter()
+					.map(|line| format!("  {line}")),
+			);
+		}
+		lines
+	}
+}
+
 /// An `AnimationAction` is some event that happens over a period (handled by an `Animation`).
 enum AnimationAction {
+	/// Always from a tile to one of its 4 neighbors (units move one tile at a time,
+	/// even during the multi-tile moves of the caravan). The path goes through the centers
+	/// of its tiles, so sliding straight from one tile to the next follows it, turns included.
 	Move {
 		obj: Obj,
 		from: Coords,
 		to: Coords,
 	},
-	/// The camera moves on the X axis (normally only to the right).
+	/// The camera moves on the X axis (normally to the right, but it can go back to the left
+	/// when recentering on the caravan).
 	CameraMoveX {
 		from: f32,
 		to: f32,
+		/// The zoom goes from the first one to the second one during the move (when zooming
+		/// to fit), `None` if it stays as it is.
+		zoom: Option<(f32, f32)>,
 	},
 	Appear {
 		obj: Obj,
@@ -1027,85 +2714,1509 @@ enum AnimationAction {
 		from: Coords,
 		direction: CoordsDelta,
 		shot: Shot,
+		/// The team of whoever fired the shot, it does not affect objects of that team.
+		team: Team,
 	},
-}
+	/// A shot that jumps straight from an object to another (like lightning),
+	/// see `ShotCascade::Chain`.
+	Arc {
+		from: Coords,
+		to: Coords,
+		shot: Shot,
+		team: Team,
+	},
+}
 
 struct Animation {
 	action: AnimationAction,
 	tp: TimeProgression,
 }
 
-/// When `progress` is 0.0 it returns `value_start`, 1.0 returns `value_end`
-/// and inbetween it does a linear interpolation (no way !!!).
-fn linear_interpolation(progress: f32, value_start: f32, value_end: f32) -> f32 {
-	value_start + progress * (value_end - value_start)
+/// Is the given tile the destination of an object that is currently moving (or appearing)?
+/// Such a tile is reserved for that object even though its `obj` stays `None` until the end
+/// of the animation, so nothing else should try to move there in the meantime.
+fn is_tile_reserved(animations: &[Animation], coords: Coords) -> bool {
+	animations.iter().any(|anim| match anim.action {
+		AnimationAction::Move { to, .. } | AnimationAction::Appear { to, .. } => to == coords,
+		_ => false,
+	})
 }
 
-fn linear_interpolation_rect(progress: f32, value_start: Rect, value_end: Rect) -> Rect {
-	Rect::xywh(
-		linear_interpolation(
-			progress,
-			value_start.top_left.x as f32,
-			value_end.top_left.x as f32,
-		)
-		.round() as i32,
-		linear_interpolation(
-			progress,
-			value_start.top_left.y as f32,
-			value_end.top_left.y as f32,
+/// Starts moving the caravan one tile forward along the path, if the tile there is free.
+/// Returns the path distance of the tile the caravan is moving to, or `None` if it can't move.
+fn start_caravan_step_forward(map: &mut Map, animations: &mut Vec<Animation>) -> Option<i32> {
+	let (coords, tile) = map.caravan_coords_and_tile()?;
+	let Path { forward, distance, .. } = tile.path()?.clone();
+	let dst_coords = coords + forward;
+	let dst_is_free = map
+		.grid
+		.get(dst_coords)
+		.is_some_and(Tile::is_passable_for_caravan)
+		&& !is_tile_reserved(animations, dst_coords);
+	if !dst_is_free {
+		return None;
+	}
+	animations.push(Animation {
+		action: AnimationAction::Move {
+			obj: map.take_obj(coords).unwrap(),
+			from: coords,
+			to: dst_coords,
+		},
+		tp: TimeProgression::gameplay(Duration::from_secs_f32(0.05)),
+	});
+	Some(distance + 1)
+}
+
+/// Can a new tower of the given type be placed with its top left tile at `coords`
+/// (on free ground, and with enough crystals to pay for it)?
+fn can_place_tower(map: &Map, game: &GameState, tower: &Tower, coords: Coords) -> bool {
+	map.can_place_obj_at(coords, &tower.new_obj())
+		&& game.crystal_amount >= game.balance.tower_price(tower)
+}
+
+/// Starts placing a new tower with its top left tile at `coords` and pays for it, if it can be
+/// placed there (see `can_place_tower`). Returns whether it is placed, placing a tower ends
+/// the player phase (after the animation).
+/// Both the mouse and the build mode place towers through here, so they follow the same rules.
+fn start_placing_tower(
+	map: &Map,
+	game: &mut GameState,
+	tower: &Tower,
+	coords: Coords,
+	animations: &mut Vec<Animation>,
+) -> bool {
+	if !can_place_tower(map, game, tower, coords) {
+		return false;
+	}
+	animations.push(Animation {
+		action: AnimationAction::Appear { obj: tower.new_obj(), to: coords },
+		tp: TimeProgression::gameplay(Duration::from_secs_f32(0.05)),
+	});
+	game.crystal_amount -= game.balance.tower_price(tower);
+	game.stats.towers_placed += 1;
+	true
+}
+
+/// A player action, asked for by a click or a key press. Actions are not done right away but
+/// queued, and the queued action is done as soon as the player can act: in the player phase,
+/// with no animation going on (and no caravan move in progress), and before the player phase
+/// is ending. This way an input made during an animation (or during the enemy and tower phases)
+/// is not lost, it is done when the animation is over.
+///
+/// There is at most one queued action: a new one replaces the one that is waiting (so only the
+/// most recent input is done, there are no chains of actions). Right click and Escape (and
+/// leaving the build mode or the caravan destination choice, or starting a new run) drop
+/// the queued action. An action that can't be done when its turn comes (such as placing a tower
+/// without enough crystals) does nothing.
+#[derive(Clone, Copy)]
+enum QueuedAction {
+	/// A left click on the tile that was already selected (place a tower, mine a crystal or
+	/// start choosing where to move the caravan).
+	ClickTile(Coords),
+	/// Placing the ghost of the build mode there.
+	PlaceGhost(Coords),
+	StepForward,
+	ScrollForward,
+	SkipTurn,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Phase {
+	Player,
+	Enemy,
+	Tower,
+	GameOver,
+	/// The caravan made it to the end of a finite world (see `Map::max_chunk_count`).
+	Victory,
+}
+
+impl Phase {
+	/// Lost or won, either way there is nothing left to play.
+	fn is_over(self) -> bool {
+		matches!(self, Phase::GameOver | Phase::Victory)
+	}
+}
+
+/// The numbers of the game economy, gathered here so that they can be tweaked in one place.
+struct Balance {
+	starting_crystal_amount: i32,
+	basic_tower_price: i32,
+	pink_tower_price: i32,
+	blue_tower_price: i32,
+	fortress_price: i32,
+	storm_tower_price: i32,
+	support_tower_price: i32,
+	/// Damages added to the shots fired by the towers next to a support tower
+	/// (see `Map::is_supported`).
+	support_damage_bonus: i32,
+	/// How many crystals are gained by mining a crystal.
+	mining_yield: i32,
+	/// No enemy spawns while there are that many enemies on the map.
+	max_enemy_count: i32,
+	/// No enemy spawns while another enemy is closer than that (in tiles) to the spawn tile.
+	min_enemy_spawn_gap: i32,
+	/// Turns without any enemy spawning before the first wave and between two waves.
+	turns_between_waves: i32,
+	/// How many enemies the first wave has, each next wave has `wave_growth` more.
+	first_wave_size: i32,
+	wave_growth: i32,
+	/// Regenerators heal once they have not been hit for that many enemy phases,
+	/// by `regeneration` hp at the start of each enemy phase.
+	regeneration_delay: i32,
+	regeneration: i32,
+}
+
+impl Default for Balance {
+	fn default() -> Balance {
+		Balance {
+			starting_crystal_amount: 20,
+			basic_tower_price: 10,
+			pink_tower_price: 10,
+			blue_tower_price: 10,
+			fortress_price: 30,
+			storm_tower_price: 15,
+			support_tower_price: 12,
+			support_damage_bonus: 1,
+			mining_yield: 30,
+			max_enemy_count: 12,
+			min_enemy_spawn_gap: 2,
+			turns_between_waves: 4,
+			first_wave_size: 3,
+			wave_growth: 1,
+			regeneration_delay: 2,
+			regeneration: 1,
+		}
+	}
+}
+
+impl Balance {
+	/// The default balance with the waves adjusted to the difficulty.
+	fn with_difficulty(difficulty: Difficulty) -> Balance {
+		let balance = Balance::default();
+		match difficulty {
+			Difficulty::Easy => Balance {
+				turns_between_waves: 6,
+				first_wave_size: 2,
+				max_enemy_count: 10,
+				..balance
+			},
+			Difficulty::Normal => balance,
+			Difficulty::Hard => Balance {
+				turns_between_waves: 3,
+				wave_growth: 2,
+				max_enemy_count: 16,
+				..balance
+			},
+		}
+	}
+
+	fn tower_price(&self, tower: &Tower) -> i32 {
+		match tower {
+			Tower::Basic => self.basic_tower_price,
+			Tower::Pink => self.pink_tower_price,
+			Tower::Blue => self.blue_tower_price,
+			Tower::Fortress => self.fortress_price,
+			Tower::Storm => self.storm_tower_price,
+			Tower::Support => self.support_tower_price,
+		}
+	}
+}
+
+/// Enemies come in waves: After a countdown of turns during which no enemy spawns,
+/// the enemies of the wave spawn one per enemy phase, then the countdown to the next wave starts.
+struct Waves {
+	/// Enemy phases without spawns left before the next wave.
+	turns_before_next_wave: i32,
+	/// Enemies of the current wave left to be rolled (0 between waves).
+	enemies_left_in_wave: i32,
+	/// How many waves have started.
+	wave_count: i32,
+}
+
+impl Waves {
+	fn new(balance: &Balance) -> Waves {
+		Waves {
+			turns_before_next_wave: balance.turns_between_waves,
+			enemies_left_in_wave: 0,
+			wave_count: 0,
+		}
+	}
+
+	/// Decides what will spawn at the end of the next enemy phase (see
+	/// `GameState::next_enemy_spawn`), to be called once per enemy phase.
+	fn roll_next_spawn(&mut self, balance: &Balance) -> Option<Obj> {
+		if self.enemies_left_in_wave == 0 {
+			if self.turns_before_next_wave > 0 {
+				self.turns_before_next_wave -= 1;
+				return None;
+			}
+			self.enemies_left_in_wave =
+				balance.first_wave_size + self.wave_count * balance.wave_growth;
+			self.wave_count += 1;
+			self.turns_before_next_wave = balance.turns_between_waves;
+		}
+		self.enemies_left_in_wave -= 1;
+		Some(Map::roll_enemy())
+	}
+}
+
+/// The state of a game that is not in the map.
+struct GameState {
+	phase: Phase,
+	turn_counter: i32,
+	distance_traveled: i32,
+	crystal_amount: i32,
+	balance: Balance,
+	stats: RunStats,
+	/// The enemy that will spawn at the end of the next enemy phase (decided in advance
+	/// so that the player can be warned).
+	next_enemy_spawn: Option<Obj>,
+	waves: Waves,
+	/// Rule that makes the caravan mine the crystals next to the tiles it moves to.
+	auto_mining: bool,
+	/// The run is won when the caravan has traveled that far (see `GameState::goal_is_reached`).
+	distance_goal: Option<i32>,
+}
+
+impl GameState {
+	/// Whether the caravan that just moved to `caravan_coords` won the run, by reaching the end
+	/// of a finite world (see `Map::max_chunk_count`) or the `distance_goal`.
+	fn goal_is_reached(&self, map: &Map, caravan_coords: Coords) -> bool {
+		let distance = map
+			.grid
+			.get(caravan_coords)
+			.and_then(Tile::path)
+			.map(|path| path.distance);
+		map.is_end_of_the_world(caravan_coords)
+			|| self
+				.distance_goal
+				.is_some_and(|goal| distance.is_some_and(|distance| goal <= distance))
+	}
+}
+
+/// A new map with the caravan at its start (and no enemies close to it),
+/// and the state of a new run on that map.
+fn new_run(
+	chunk_dims: Dimensions,
+	max_chunk_count: Option<i32>,
+	distance_goal: Option<i32>,
+	gameplay_config: &GameplayConfig,
+	visible_width: i32,
+) -> (Map, GameState) {
+	let mut map = Map::new(chunk_dims, max_chunk_count);
+
+	map.generate_chunks_until_wider_than(visible_width);
+
+	let enemies_near_the_start: Vec<Coords> = map
+		.enemies()
+		.map(|(coords, _)| coords)
+		.filter(|coords| coords.x < 15)
+		.collect();
+	for coords in enemies_near_the_start {
+		map.set_obj(coords, None);
+	}
+
+	for y in 0..map.grid.dims.h {
+		let coords = (0, y).into();
+		if let Some(Path { distance: 0, .. }) = map.grid.at(coords).path() {
+			map.set_obj(coords, Some(Obj::Caravan));
+		}
+	}
+
+	let balance = Balance {
+		starting_crystal_amount: gameplay_config.starting_crystals,
+		..Balance::with_difficulty(gameplay_config.difficulty)
+	};
+	let mut waves = Waves::new(&balance);
+	let next_enemy_spawn = waves.roll_next_spawn(&balance);
+	let game = GameState {
+		phase: Phase::Player,
+		turn_counter: 0,
+		distance_traveled: 0,
+		crystal_amount: balance.starting_crystal_amount,
+		balance,
+		stats: RunStats::default(),
+		next_enemy_spawn,
+		waves,
+		auto_mining: false,
+		distance_goal,
+	};
+	(map, game)
+}
+
+/// What happened during the run, shown at game over.
+#[derive(Default)]
+struct RunStats {
+	enemies_killed: i32,
+	crystals_mined: i32,
+	towers_placed: i32,
+	towers_lost: i32,
+	/// Damage inflicted to enemies (healing does not count).
+	damage_dealt: i32,
+}
+
+/// Ends the player phase, enemies get to play (and the regenerators that were left alone heal).
+/// A run that is over stays over (the last move of the caravan may have won it).
+fn end_player_phase(map: &mut Map, game: &mut GameState) {
+	if game.phase.is_over() {
+		return;
+	}
+	game.phase = Phase::Enemy;
+	for &coords in map.obj_index.enemies.iter() {
+		if let Some(Obj::Enemy {
+			ref mut actions,
+			ref mut hp,
+			ref mut variant,
+			ref mut colored_animation,
+			..
+		}) = map.grid.at_mut(coords).obj
+		{
+			*actions += 1;
+			if let Enemy::Regenerator { max_hp, turns_since_hit } = variant {
+				*turns_since_hit += 1;
+				if game.balance.regeneration_delay <= *turns_since_hit && *hp < *max_hp {
+					*hp = (*hp + game.balance.regeneration).min(*max_hp);
+					*colored_animation = Some(ColoredAnimation {
+						tp: TimeProgression::gameplay(Duration::from_secs_f32(0.075)),
+						color: REGENERATION_COLOR,
+					});
+				}
+			}
+		}
+	}
+}
+
+/// Makes the current enemy or tower phase progress by one step, which means making
+/// one enemy or one tower play, or getting to the next phase if no one is left to play.
+/// Does nothing during the player phase.
+/// Returns the coords of the enemy or tower that played (where it was before playing), if any.
+fn phase_step(
+	map: &mut Map,
+	game: &mut GameState,
+	animations: &mut Vec<Animation>,
+	sounds: &mut Vec<SoundEffect>,
+) -> Option<Coords> {
+	if game.phase == Phase::Enemy {
+		// The enemies shall play now, one at a time (see `Map::next_enemy_to_play`).
+		let enemy_to_play = map.next_enemy_to_play();
+		if let Some(coords) = enemy_to_play {
+			// Found the closest enemy that hasn't played yet. This enemy plays now.
+			// But before playing, we handle fire effect (if any).
+			if let Obj::Enemy { actions, ref mut fire, .. } =
+				map.grid.at_mut(coords).obj.as_mut().unwrap()
+			{
+				if *actions >= 1 && *fire >= 1 {
+					*fire -= 1;
+					map.inflict_damage_to_obj_at(coords, 1, &mut game.stats);
+					sounds.push(SoundEffect::Hit);
+				}
+			}
+			// Then the shield wears off a little (if it is still alive to play).
+			if let Some(Obj::Enemy { actions, ref mut shield, .. }) = map.grid.at_mut(coords).obj {
+				if actions >= 1 && *shield >= 1 {
+					*shield -= 1;
+				}
+			}
+			let tile = map.grid.at_mut(coords);
+			if let Some(Obj::Enemy { ref mut actions, .. }) = tile.obj {
+				if *actions >= 1 {
+					// Now the enemy really plays.
+					*actions -= 1;
+					let backward = if let Some(Path { backward, .. }) = tile.path() {
+						*backward
+					} else {
+						panic!("enemy not on a path")
+					};
+					let dst_coords = coords + backward;
+					// An enemy never moves onto an other enemy. A moving object is off the grid
+					// until its move is over, so the tile it is moving to looks free. The game
+					// waits for the animations to be over before the next step so this does not
+					// happen for now, the reservation check keeps it that way if steps overlap.
+					let dst_is_free = map
+						.grid
+						.get(dst_coords)
+						.is_some_and(Tile::is_passable_for_enemy)
+						&& !is_tile_reserved(animations, dst_coords);
+					if dst_is_free {
+						// The enemy is about to crush the caravan, that deserves some slow motion.
+						let mut duration = Duration::from_secs_f32(0.05);
+						if AnimationClock::slow_motion() && map.caravan_coords() == Some(dst_coords) {
+							duration = duration.mul_f32(AnimationClock::SLOW_MOTION_FACTOR);
+						}
+						animations.push(Animation {
+							action: AnimationAction::Move {
+								obj: map.take_obj(coords).unwrap(),
+								from: coords,
+								to: dst_coords,
+							},
+							tp: TimeProgression::gameplay(duration),
+						});
+						sounds.push(SoundEffect::Step);
+					}
+				}
+			}
+		} else {
+			// No enemies left to play.
+			// We get to next phase, chunk generation and enemy spawn are up to the caller.
+
+			// Get to next phase
+			game.phase = Phase::Tower;
+			for &coords in map.obj_index.towers.iter() {
+				if let Some(Obj::Tower { ref mut actions, .. }) = map.grid.at_mut(coords).obj {
+					*actions += 1;
+				}
+			}
+		}
+		enemy_to_play
+	} else if game.phase == Phase::Tower {
+		// Towers gonna shoot!
+		let mut tower_to_play = None;
+		// From left to right (then top to bottom).
+		let mut tower_coords = map.obj_index.towers.clone();
+		tower_coords.sort_by_key(|coords| (coords.x, coords.y));
+		for coords in tower_coords {
+			// Before playing, we handle fire effect (if any).
+			if let Some(Obj::Tower { actions, ref mut fire, .. }) =
+				map.grid.at_mut(coords).obj.as_mut()
+			{
+				if *actions >= 1 && *fire >= 1 {
+					*fire -= 1;
+					map.inflict_damage_to_obj_at(coords, 1, &mut game.stats);
+					sounds.push(SoundEffect::Hit);
+				}
+			}
+			// Then the shield wears off a little (if it is still alive to play).
+			if let Some(Obj::Tower { actions, ref mut shield, .. }) = map.grid.at_mut(coords).obj {
+				if actions >= 1 && *shield >= 1 {
+					*shield -= 1;
+				}
+			}
+			let tile = map.grid.at_mut(coords);
+			if let Some(Obj::Tower { ref mut actions, ref variant, .. }) = tile.obj {
+				if *actions >= 1 {
+					*actions -= 1;
+					let mut shot = variant.shot();
+					if let Some(shot) = &mut shot {
+						if map.is_supported(coords) {
+							shot.damages += game.balance.support_damage_bonus;
+						}
+					}
+
+					// Disabled towers and towers that don't shoot have no target,
+					// they just spend their action.
+					if let (Some(shot), Some(direction)) = (shot, map.tower_target_direction(coords)) {
+						// Shoot!
+						// The shot here is a test for now,
+						// the basic tower isn't supposed to shoot shots like these.
+						animations.push(Animation {
+							action: AnimationAction::Shoot {
+								from: coords,
+								direction,
+								shot,
+								team: Team::Player,
+							},
+							tp: TimeProgression::gameplay(Duration::from_secs_f32(0.05)),
+						});
+						sounds.push(SoundEffect::Pew);
+					}
+
+					tower_to_play = Some(coords);
+					break;
+				}
+			}
+		}
+		if tower_to_play.is_none() {
+			if map.caravan_coords().is_some() {
+				game.phase = Phase::Player;
+				game.turn_counter += 1;
+			} else {
+				game.phase = Phase::GameOver;
+			}
+		}
+		tower_to_play
+	} else {
+		None
+	}
+}
+
+/// Applies the effects of an animation that just finished to the map,
+/// and returns the animations that follow from it (such as shots that keep flying).
+/// Camera movements are not handled here as they are not part of the game logic.
+fn resolve_animation(
+	map: &mut Map,
+	anim: &Animation,
+	game: &mut GameState,
+	sounds: &mut Vec<SoundEffect>,
+) -> Vec<Animation> {
+	let mut new_anims: Vec<Animation> = vec![];
+	match &anim.action {
+		AnimationAction::Move { obj, from, to } => {
+			debug_assert_eq!((*to - *from).manhattan(), 1, "a move from {from} to {to}");
+			// The destination is supposed to have been reserved (see `is_tile_reserved`), but if
+			// something got there anyway then the moving object goes back to where it was
+			// rather than overwriting it. Enemies crush the caravan and towers on purpose though.
+			let is_free = |coords: Coords| map.grid.get(coords).is_some_and(|tile| tile.obj.is_none());
+			let enemy_crushes = matches!(obj, Obj::Enemy { .. })
+				&& map.grid.get(*to).is_some_and(Tile::is_passable_for_enemy);
+			if is_free(*to) {
+				map.set_obj(*to, Some(obj.clone()));
+				if matches!(obj, Obj::Caravan) && game.auto_mining {
+					new_anims.extend(auto_mine_around(map, *to, game, sounds));
+				}
+				if matches!(obj, Obj::Caravan) && game.goal_is_reached(map, *to) {
+					game.phase = Phase::Victory;
+				}
+			} else if enemy_crushes {
+				if let Some(Obj::Tower { .. }) = map.take_obj(*to) {
+					game.stats.towers_lost += 1;
+				}
+				map.set_obj(*to, Some(obj.clone()));
+			} else if is_free(*from) {
+				log::warn!("the destination {to} of a move is occupied, going back to {from}");
+				map.set_obj(*from, Some(obj.clone()));
+			} else {
+				log::error!(
+					"a {} got lost, both ends of its move are occupied",
+					obj.name()
+				);
+			}
+		},
+		AnimationAction::CameraMoveX { .. } => {},
+		AnimationAction::Appear { obj, to } => map.place_obj(*to, obj.clone()),
+		AnimationAction::Disappear { .. } => {},
+		AnimationAction::Shoot { from, direction, shot, team } => {
+			new_anims = resolve_shot(
+				map,
+				*from,
+				*direction,
+				shot,
+				*team,
+				anim.tp.duration,
+				&mut game.stats,
+				sounds,
+			);
+		},
+		AnimationAction::Arc { from, to, shot, team } => {
+			// The target may have died or moved away in the meantime, then the arc is lost.
+			let hits_something = map
+				.grid
+				.get(*to)
+				.and_then(|tile| tile.obj.as_ref())
+				.is_some_and(|obj| obj.is_hostile_to(*team));
+			if hits_something {
+				// Cascades that care about the direction get the main axis of the arc.
+				let delta = *to - *from;
+				let direction = if delta.dx.abs() >= delta.dy.abs() {
+					CoordsDelta::from((delta.dx.signum(), 0))
+				} else {
+					CoordsDelta::from((0, delta.dy.signum()))
+				};
+				new_anims = resolve_shot_hit(map, *to, direction, shot, *team, &mut game.stats, sounds);
+			}
+		},
+	}
+	new_anims
+}
+
+/// The caravan at `coords` mines the crystals on the tiles next to it (see
+/// `GameState::auto_mining`), and the animations of their disappearance are returned.
+/// Crystals are removed from the map right away, so they can't be mined twice.
+fn auto_mine_around(
+	map: &mut Map,
+	coords: Coords,
+	game: &mut GameState,
+	sounds: &mut Vec<SoundEffect>,
+) -> Vec<Animation> {
+	let mut anims = vec![];
+	for direction in CoordsDelta::iter_4_directions() {
+		let crystal_coords = coords + direction;
+		if map
+			.grid
+			.get(crystal_coords)
+			.is_some_and(|tile| matches!(tile.obj, Some(Obj::Crystal { .. })))
+		{
+			anims.push(Animation {
+				action: AnimationAction::Disappear {
+					obj: map.take_obj(crystal_coords).unwrap(),
+					from: crystal_coords,
+				},
+				tp: TimeProgression::gameplay(Duration::from_secs_f32(0.05)),
+			});
+			game.crystal_amount += game.balance.mining_yield;
+			game.stats.crystals_mined += 1;
+		}
+	}
+	if !anims.is_empty() {
+		sounds.push(SoundEffect::Mine);
+	}
+	anims
+}
+
+/// Resolves a shot that goes one tile from `from` in the given direction: It hits whatever
+/// object is there (and cascades, see `ShotCascade`) or flies on over empty tiles.
+/// Returns the shots that follow (a shot flying on keeps taking `step_duration` per tile).
+/// A shot that would leave the map is simply lost, and so are the parts of a cascade that
+/// would start out of the map (they get lost when they are resolved in turn).
+/// There is no friendly fire: A shot flies through the objects of the `team` that fired it
+/// as if their tiles were empty.
+#[allow(clippy::too_many_arguments)] // It is the fields of a `Shoot` animation plus context.
+fn resolve_shot(
+	map: &mut Map,
+	from: Coords,
+	direction: CoordsDelta,
+	shot: &Shot,
+	team: Team,
+	step_duration: Duration,
+	stats: &mut RunStats,
+	sounds: &mut Vec<SoundEffect>,
+) -> Vec<Animation> {
+	let mut new_anims: Vec<Animation> = vec![];
+	let to = from + direction;
+	if !map.grid.dims.contains(to) {
+		// The shot leaves the map and is lost.
+		return vec![];
+	}
+	let hits_something = map
+		.grid
+		.at(to)
+		.obj
+		.as_ref()
+		.is_some_and(|obj| obj.team() != Some(team));
+	if hits_something {
+		new_anims = resolve_shot_hit(map, to, direction, shot, team, stats, sounds);
+	} else {
+		new_anims.push(Animation {
+			action: AnimationAction::Shoot { from: to, direction, shot: shot.clone(), team },
+			tp: TimeProgression::new(step_duration),
+		});
+	}
+	new_anims
+}
+
+/// The shot (going in the given direction) hits the object at `to`,
+/// returns the shots that follow from its cascade.
+fn resolve_shot_hit(
+	map: &mut Map,
+	to: Coords,
+	direction: CoordsDelta,
+	shot: &Shot,
+	team: Team,
+	stats: &mut RunStats,
+	sounds: &mut Vec<SoundEffect>,
+) -> Vec<Animation> {
+	let mut new_anims: Vec<Animation> = vec![];
+	// Before the hit, as it may destroy the object (and its parts).
+	let hit_anchor = map.anchor_of(to);
+	map.shot_hits_obj_at(to, shot, team, stats);
+	sounds.push(SoundEffect::Hit);
+	match &shot.cascade {
+		ShotCascade::None => {},
+		ShotCascade::Piercing(piercing_shot) => {
+			new_anims.push(Animation {
+				action: AnimationAction::Shoot {
+					from: to,
+					direction,
+					shot: *(*piercing_shot).clone(),
+					team,
+				},
+				tp: TimeProgression::gameplay(Duration::from_secs_f32(0.05)),
+			});
+			sounds.push(SoundEffect::Pew);
+		},
+		ShotCascade::SplitInTwo(side_shots) => {
+			let one_side = CoordsDelta::from((direction.dy, direction.dx));
+			new_anims.push(Animation {
+				action: AnimationAction::Shoot {
+					from: to,
+					direction: one_side,
+					shot: *(*side_shots).clone(),
+					team,
+				},
+				tp: TimeProgression::gameplay(Duration::from_secs_f32(0.05)),
+			});
+			new_anims.push(Animation {
+				action: AnimationAction::Shoot {
+					from: to,
+					direction: -one_side,
+					shot: *(*side_shots).clone(),
+					team,
+				},
+				tp: TimeProgression::gameplay(Duration::from_secs_f32(0.05)),
+			});
+			sounds.push(SoundEffect::Pew);
+		},
+		ShotCascade::Chain { jumps_left, radius, already_hit } => {
+			let mut already_hit = already_hit.clone();
+			already_hit.push(hit_anchor);
+			let damages = shot.damages - 1;
+			let next_to = map.nearest_hostile_within(to, *radius, team, &already_hit);
+			if let (true, Some(next_to)) = (*jumps_left >= 1 && damages >= 1, next_to) {
+				let cascade =
+					ShotCascade::Chain { jumps_left: jumps_left - 1, radius: *radius, already_hit };
+				new_anims.push(Animation {
+					action: AnimationAction::Arc {
+						from: to,
+						to: next_to,
+						shot: Shot { damages, cascade, ..shot.clone() },
+						team,
+					},
+					tp: TimeProgression::gameplay(Duration::from_secs_f32(0.05)),
+				});
+				sounds.push(SoundEffect::Pew);
+			}
+		},
+	}
+	new_anims
+}
+
+/// Plays a whole turn without any window, animation delays or sounds,
+/// starting from the player phase (the player doing nothing) and up to the next player phase
+/// (or game over). No new chunks are generated and no enemies spawn.
+/// This is meant for checking the turn logic headlessly.
+#[cfg(test)]
+fn simulate_turn(map: &mut Map, game: &mut GameState) {
+	if game.phase == Phase::Player {
+		end_player_phase(map, game);
+	}
+	let mut animations: Vec<Animation> = vec![];
+	let mut sounds: Vec<SoundEffect> = vec![];
+	while game.phase == Phase::Enemy || game.phase == Phase::Tower || !animations.is_empty() {
+		if animations.is_empty() {
+			phase_step(map, game, &mut animations, &mut sounds);
+		} else {
+			// Animations end instantly, in the order they were started.
+			let anim = animations.remove(0);
+			let new_anims = resolve_animation(map, &anim, game, &mut sounds);
+			animations.extend(new_anims);
+		}
+	}
+}
+
+/// The number of turns it takes to go from day to night and back to day.
+const DAY_NIGHT_CYCLE_TURNS: i32 = 24;
+
+/// The color that the map is multiplied by at the given turn, white during the day
+/// and darker and bluer during the night.
+fn day_night_tint(turn_counter: i32) -> Color {
+	let cycle_progress =
+		turn_counter.rem_euclid(DAY_NIGHT_CYCLE_TURNS) as f32 / DAY_NIGHT_CYCLE_TURNS as f32;
+	let darkness = (1.0 - (cycle_progress * std::f32::consts::TAU).cos()) / 2.0;
+	lerp_color(darkness, Color::WHITE, Color::rgb_u8(90, 100, 170))
+}
+
+/// Something that lights up the tiles around it at night (see `Obj::light_radius`).
+struct LightSource {
+	/// In tiles, where (0.0, 0.0) is the top left corner of the tile (0, 0).
+	center: (f32, f32),
+	radius: f32,
+}
+
+impl LightSource {
+	/// The light source of the object (if it gives light) with its top left tile at `coords`,
+	/// or `(x, y)` for an object between tiles (such as one that is moving).
+	fn of_obj(obj: &Obj, (x, y): (f32, f32)) -> Option<LightSource> {
+		let footprint = obj.footprint();
+		Some(LightSource {
+			center: (x + footprint.w as f32 / 2.0, y + footprint.h as f32 / 2.0),
+			radius: obj.light_radius()?,
+		})
+	}
+}
+
+/// How much the tile at `coords` is lit, from 0.0 (not at all) to 1.0 (as if in daylight).
+/// The light fades linearly with the distance to the source, the brightest source wins.
+fn light_level(lights: &[LightSource], coords: Coords) -> f32 {
+	let (x, y) = (coords.x as f32 + 0.5, coords.y as f32 + 0.5);
+	lights
+		.iter()
+		.map(|light| {
+			let distance = ((x - light.center.0).powi(2) + (y - light.center.1).powi(2)).sqrt();
+			1.0 - distance / light.radius
+		})
+		.fold(0.0, f32::max)
+}
+
+/// Purely cosmetic weather, made of particles falling over the screen.
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Weather {
+	Clear,
+	Rain,
+	Snow,
+}
+
+impl Weather {
+	fn from_name(name: &str) -> Option<Weather> {
+		match name {
+			"clear" => Some(Weather::Clear),
+			"rain" => Some(Weather::Rain),
+			"snow" => Some(Weather::Snow),
+			_ => None,
+		}
+	}
+
+	fn next(self) -> Weather {
+		match self {
+			Weather::Clear => Weather::Rain,
+			Weather::Rain => Weather::Snow,
+			Weather::Snow => Weather::Clear,
+		}
+	}
+}
+
+/// A weather particle, in screen pixels (and pixels per second).
+struct Particle {
+	x: f32,
+	y: f32,
+	vx: f32,
+	vy: f32,
+	/// Seconds before the particle disappears (if it didn't leave the screen before that).
+	lifetime: f32,
+}
+
+struct WeatherParticles {
+	weather: Weather,
+	particles: Vec<Particle>,
+	/// How many particles should have spawned but didn't yet (as a frame rarely lasts long
+	/// enough to spawn a whole number of particles).
+	spawn_accumulator: f32,
+}
+
+impl WeatherParticles {
+	/// There are never more particles than that, to keep it cheap.
+	const MAX_PARTICLES: usize = 600;
+
+	fn new() -> WeatherParticles {
+		WeatherParticles {
+			weather: Weather::Clear,
+			particles: vec![],
+			spawn_accumulator: 0.0,
+		}
+	}
+
+	fn set_weather(&mut self, weather: Weather) {
+		self.weather = weather;
+		self.particles.clear();
+		self.spawn_accumulator = 0.0;
+	}
+
+	fn update(&mut self, dt: f32, screen_dims: Dimensions) {
+		for particle in self.particles.iter_mut() {
+			particle.x += particle.vx * dt;
+			particle.y += particle.vy * dt;
+			particle.lifetime -= dt;
+		}
+		self.particles.retain(|particle| {
+			0.0 < particle.lifetime
+				&& particle.y < screen_dims.h as f32
+				&& -10.0 < particle.x
+				&& particle.x < screen_dims.w as f32 + 10.0
+		});
+
+		let spawn_rate_per_pixel_of_width = match self.weather {
+			Weather::Clear => return,
+			Weather::Rain => 0.2,
+			Weather::Snow => 0.03,
+		};
+		self.spawn_accumulator += spawn_rate_per_pixel_of_width * screen_dims.w as f32 * dt;
+		while 1.0 <= self.spawn_accumulator {
+			self.spawn_accumulator -= 1.0;
+			if WeatherParticles::MAX_PARTICLES <= self.particles.len() {
+				self.spawn_accumulator = 0.0;
+				break;
+			}
+			// Particles spawn a bit further than the right side as the wind blows to the left.
+			let x = cosmetic_rand_range(0.0..(screen_dims.w as f32 * 1.2));
+			self.particles.push(match self.weather {
+				Weather::Rain => Particle {
+					x,
+					y: 0.0,
+					vx: -120.0,
+					vy: cosmetic_rand_range(800.0..1000.0),
+					lifetime: 3.0,
+				},
+				Weather::Snow => Particle {
+					x,
+					y: 0.0,
+					vx: cosmetic_rand_range(-40.0..10.0),
+					vy: cosmetic_rand_range(50.0..110.0),
+					lifetime: 30.0,
+				},
+				Weather::Clear => unreachable!(),
+			});
+		}
+	}
+
+	fn draw(&self, renderer: &mut SoftwareRenderer) {
+		let (dims, color) = match self.weather {
+			Weather::Clear => return,
+			Weather::Rain => (Dimensions { w: 2, h: 12 }, Color::rgb_u8(150, 170, 255)),
+			Weather::Snow => (Dimensions { w: 4, h: 4 }, Color::WHITE),
+		};
+		for particle in self.particles.iter() {
+			let dst = Rect { top_left: (particle.x as i32, particle.y as i32).into(), dims };
+			renderer.draw_rect(dst, color);
+		}
+	}
+}
+
+/// A milestone of a run, celebrated with a toast the first time it is reached.
+struct Milestone {
+	text: &'static str,
+	is_reached: fn(&GameState, &Map) -> bool,
+}
+
+const MILESTONES: &[Milestone] = &[
+	Milestone {
+		text: "first tower placed!",
+		is_reached: |game, _| 1 <= game.stats.towers_placed,
+	},
+	Milestone {
+		text: "first blue tower!",
+		is_reached: |_, map| {
+			map.towers()
+				.any(|(_, tower)| matches!(tower, Obj::Tower { variant: Tower::Blue, .. }))
+		},
+	},
+	Milestone {
+		text: "first crystal mined!",
+		is_reached: |game, _| 1 <= game.stats.crystals_mined,
+	},
+	Milestone {
+		text: "10 enemies killed!",
+		is_reached: |game, _| 10 <= game.stats.enemies_killed,
+	},
+	Milestone {
+		text: "50 enemies killed!",
+		is_reached: |game, _| 50 <= game.stats.enemies_killed,
+	},
+	Milestone {
+		text: "100 tiles traveled!",
+		is_reached: |game, _| 100 <= game.distance_traveled,
+	},
+	Milestone {
+		text: "500 tiles traveled!",
+		is_reached: |game, _| 500 <= game.distance_traveled,
+	},
+];
+
+/// A short notification that slides in at the top of the screen and fades out.
+struct Toast {
+	text: String,
+	tp: TimeProgression,
+}
+
+/// The toasts are shown one at a time, the others wait in the queue.
+/// They are only cosmetic and do not block anything (unlike the `Animation`s).
+struct Toasts {
+	current: Option<Toast>,
+	queue: std::collections::VecDeque<String>,
+	/// Which of the `MILESTONES` were already celebrated.
+	reached_milestones: Vec<bool>,
+}
+
+impl Toasts {
+	const DURATION: Duration = Duration::from_millis(2500);
+	/// The part of the duration spent sliding in (and also the part spent fading out).
+	const TRANSITION: f32 = 0.15;
+
+	fn new() -> Toasts {
+		Toasts {
+			current: None,
+			queue: std::collections::VecDeque::new(),
+			reached_milestones: vec![false; MILESTONES.len()],
+		}
+	}
+
+	fn push(&mut self, text: String) {
+		self.queue.push_back(text);
+	}
+
+	fn check_milestones(&mut self, game: &GameState, map: &Map) {
+		for (i, milestone) in MILESTONES.iter().enumerate() {
+			if !self.reached_milestones[i] && (milestone.is_reached)(game, map) {
+				self.reached_milestones[i] = true;
+				self.push(milestone.text.to_string());
+			}
+		}
+	}
+
+	fn update(&mut self) {
+		if self
+			.current
+			.as_ref()
+			.is_some_and(|toast| toast.tp.is_done())
+		{
+			self.current = None;
+		}
+		if self.current.is_none() {
+			self.current = self
+				.queue
+				.pop_front()
+				.map(|text| Toast { text, tp: TimeProgression::new(Toasts::DURATION) });
+		}
+	}
+
+	fn draw(&self, renderer: &mut SoftwareRenderer, hud: &HudLayout) {
+		let Some(toast) = &self.current else {
+			return;
+		};
+		let progress = toast.tp.progress().min(1.0);
+		let slide_in = (progress / Toasts::TRANSITION).min(1.0);
+		let fade_out = ((1.0 - progress) / Toasts::TRANSITION).min(1.0);
+		let alpha = (slide_in.min(fade_out) * 255.0) as u8;
+		let font = Font {
+			size_factor: 3,
+			horizontal_spacing: 2,
+			space_width: 7,
+			foreground: Color::rgba_u8(255, 255, 120, alpha),
+			background: Some(Color::rgba_u8(0, 0, 0, alpha / 2 + alpha / 4)),
+			outline: None,
+			shadow: None,
+			margins: (8, 8).into(),
+			strict: false,
+			capital_size_factor: None,
+			monospace_width: None,
+		};
+		let height = font.text_line_dims(&toast.text).unwrap().h;
+		let y = lerp_i32(quad_out(slide_in), -height, 0);
+		font
+			.draw_text_line(
+				renderer,
+				&toast.text,
+				hud.point(PinPoint::TOP_CENTER, (0, y).into()),
+				PinPoint::TOP_CENTER,
+			)
+			.unwrap();
+	}
+}
+
+/// Some text being typed by the player. While there is one, it gets all the keyboard input
+/// (and the gameplay keys do nothing).
+struct TextInput {
+	/// Shown before the text, it tells what the text is for.
+	prompt: &'static str,
+	text: String,
+}
+
+impl TextInput {
+	fn new(prompt: &'static str) -> TextInput {
+		TextInput { prompt, text: String::new() }
+	}
+
+	/// Control characters (such as the ones sent along backspace or return) are ignored,
+	/// these keys are handled as keys.
+	fn type_char(&mut self, ch: char) {
+		if !ch.is_control() {
+			self.text.push(ch);
+		}
+	}
+
+	fn backspace(&mut self) {
+		self.text.pop();
+	}
+
+	fn draw(&self, renderer: &mut SoftwareRenderer, hud: &HudLayout) {
+		let font = Font {
+			size_factor: 3,
+			horizontal_spacing: 2,
+			space_width: 7,
+			foreground: Color::WHITE,
+			background: Some(Color::rgba_u8(0, 0, 0, 200)),
+			outline: None,
+			shadow: None,
+			margins: (6, 6).into(),
+			strict: false,
+			capital_size_factor: None,
+			monospace_width: None,
+		};
+		// An underscore as a cursor at the end.
+		font
+			.draw_text_line(
+				renderer,
+				&format!("{}{}_", self.prompt, self.text),
+				hud.point(PinPoint::BOTTOM_LEFT, (0, 0).into()),
+				PinPoint::BOTTOM_LEFT,
+			)
+			.unwrap();
+	}
+}
+
+/// Runs a debug console command, returns what to tell the player about it.
+fn run_console_command(
+	command: &str,
+	map: &mut Map,
+	game: &mut GameState,
+	weather_particles: &mut WeatherParticles,
+) -> Result<String, String> {
+	let mut words = command.split_whitespace();
+	match (words.next(), words.next(), words.next()) {
+		(None, ..) => Err("no command".to_string()),
+		(Some("crystals"), Some(amount), None) => {
+			let amount: i32 = amount
+				.parse()
+				.map_err(|_| format!("bad amount \"{amount}\""))?;
+			game.crystal_amount = amount;
+			Ok(format!("crystals set to {amount}"))
+		},
+		(Some("weather"), Some(name), None) => {
+			let weather = Weather::from_name(name).ok_or(format!("unknown weather \"{name}\""))?;
+			weather_particles.set_weather(weather);
+			Ok(format!("weather set to {name}"))
+		},
+		(Some("save_grid"), Some(path), None) => {
+			std::fs::write(path, map.grid.to_bytes())
+				.map_err(|error| format!("can't write \"{path}\": {error}"))?;
+			Ok(format!("grid saved to {path}"))
+		},
+		(Some("load_grid"), Some(path), None) => {
+			let bytes =
+				std::fs::read(path).map_err(|error| format!("can't read \"{path}\": {error}"))?;
+			let grid =
+				Grid::from_bytes(&bytes).map_err(|error| format!("can't load \"{path}\": {error}"))?;
+			map.replace_grid(grid)?;
+			Ok(format!("grid loaded from {path}"))
+		},
+		(Some(name), ..) => Err(format!("unknown command \"{name}\"")),
+	}
+}
+
+/// Keeps the durations of the last few frames, to see stutters in a graph.
+struct FrameTimeHistory {
+	frame_times: std::collections::VecDeque<Duration>,
+}
+
+impl FrameTimeHistory {
+	const LENGTH: usize = 120;
+
+	fn new() -> FrameTimeHistory {
+		FrameTimeHistory {
+			frame_times: std::collections::VecDeque::with_capacity(Self::LENGTH),
+		}
+	}
+
+	fn push(&mut self, dt: Duration) {
+		// A spike is a frame that takes way longer than the recent ones (and that is noticeable).
+		if let Some((_min, avg, _max)) = self.min_avg_max() {
+			if avg * 3 < dt && Duration::from_secs_f32(1.0 / 30.0) < dt {
+				log::debug!("frame time spike: {dt:?} (the average is {avg:?})");
+			}
+		}
+		if self.frame_times.len() == Self::LENGTH {
+			self.frame_times.pop_front();
+		}
+		self.frame_times.push_back(dt);
+	}
+
+	/// Returns the min, average and max frame durations, if any.
+	fn min_avg_max(&self) -> Option<(Duration, Duration, Duration)> {
+		let min = *self.frame_times.iter().min()?;
+		let max = *self.frame_times.iter().max()?;
+		let avg = self.frame_times.iter().sum::<Duration>() / self.frame_times.len() as u32;
+		Some((min, avg, max))
+	}
+
+	/// Draws the frame durations as a graph (the oldest on the left) in the given rect,
+	/// with the min/avg/max frame durations written under it.
+	fn draw(&self, renderer: &mut SoftwareRenderer, dst: Rect) {
+		renderer.draw_rect(dst, Color::BLACK);
+		let Some((min, avg, max)) = self.min_avg_max() else {
+			return;
+		};
+		// The top of the graph is at least at the duration of a 30 fps frame,
+		// so that a smooth run looks flat instead of amplifying the noise.
+		let top_duration = max.max(Duration::from_secs_f32(1.0 / 30.0)).as_secs_f32();
+		let point = |index: usize, dt: &Duration| -> Coords {
+			let x = dst.left() + (index as i32 * (dst.dims.w - 1)) / (Self::LENGTH as i32 - 1);
+			let height = ((dt.as_secs_f32() / top_duration) * (dst.dims.h - 1) as f32) as i32;
+			(x, dst.bottom_excluded() - 1 - height).into()
+		};
+		// The duration of a 60 fps frame as a reference.
+		let reference_y = point(0, &Duration::from_secs_f32(1.0 / 60.0)).y;
+		renderer.draw_line(
+			(dst.left(), reference_y).into(),
+			(dst.right_excluded() - 1, reference_y).into(),
+			Color::rgb_u8(0, 100, 0),
+		);
+		for (index, (dt_a, dt_b)) in self
+			.frame_times
+			.iter()
+			.zip(self.frame_times.iter().skip(1))
+			.enumerate()
+		{
+			renderer.draw_line(
+				point(index, dt_a),
+				point(index + 1, dt_b),
+				Color::rgb_u8(80, 255, 80),
+			);
+		}
+		Font {
+			size_factor: 2,
+			horizontal_spacing: 2,
+			space_width: 7,
+			foreground: Color::WHITE,
+			background: Some(Color::BLACK),
+			outline: None,
+			shadow: None,
+			margins: (3, 3).into(),
+			strict: false,
+			capital_size_factor: None,
+			monospace_width: None,
+		}
+		.draw_text_line(
+			renderer,
+			&format!(
+				"min {:.1} avg {:.1} max {:.1} ms",
+				min.as_secs_f32() * 1000.0,
+				avg.as_secs_f32() * 1000.0,
+				max.as_secs_f32() * 1000.0,
+			),
+			(dst.left(), dst.bottom_excluded()).into(),
+			PinPoint::TOP_LEFT,
 		)
-		.round() as i32,
-		linear_interpolation(progress, value_start.dims.w as f32, value_end.dims.w as f32).round()
-			as i32,
-		linear_interpolation(progress, value_start.dims.h as f32, value_end.dims.h as f32).round()
-			as i32,
-	)
+		.unwrap();
+	}
 }
 
 struct MapDrawingConfig {
 	top_left: Coords,
-	/// A square tile will be drawn to a square area of side 16 * zoom.
-	zoom: i32,
+	/// A square tile will be drawn to a square area of side `TILE_PX * zoom` (rounded).
+	/// It is a whole number, except in the middle of a zoom animation (see
+	/// `AnimationAction::CameraMoveX`).
+	zoom: f32,
 	/// The x coordinate (in the map's grid coordinate system) of the left side of the screen.
 	camera_x: f32,
 }
 
 impl MapDrawingConfig {
+	const MIN_ZOOM: f32 = 1.0;
+	const DEFAULT_ZOOM: f32 = 4.0;
+
 	fn tile_side(&self) -> i32 {
-		self.zoom * 16
+		(self.zoom * TILE_PX as f32).round() as i32
+	}
+
+	/// The screen x coordinate of the left side of the tile column 0 (relative to `top_left`).
+	fn map_left(&self) -> i32 {
+		-(self.camera_x * self.tile_side() as f32) as i32
+	}
+
+	/// The `camera_x` that puts the center of the given tile column in the middle of the screen
+	/// (but without going left of the map).
+	fn camera_x_centered_on(&self, tile_x: i32, screen_w: i32) -> f32 {
+		self.camera_x_centered_on_columns(tile_x, tile_x, screen_w)
+	}
+
+	/// Same as `camera_x_centered_on` but for the columns from `left` to `right` (included).
+	fn camera_x_centered_on_columns(&self, left: i32, right: i32, screen_w: i32) -> f32 {
+		let side = self.tile_side() as f32;
+		let span_center = self.top_left.x as f32 + side * (left + right + 1) as f32 / 2.0;
+		((span_center - screen_w as f32 / 2.0) / side).max(0.0)
+	}
+
+	/// Keeps the camera from showing what is past the end of a finite world
+	/// (and from going left of the map).
+	fn clamp_camera_x(&mut self, map: &Map, screen_w: i32) {
+		if map.is_fully_generated() {
+			let side = self.tile_side() as f32;
+			let map_right = self.top_left.x as f32 + map.grid.dims.w as f32 * side;
+			self.camera_x = self.camera_x.min((map_right - screen_w as f32) / side);
+		}
+		self.camera_x = self.camera_x.max(0.0);
+	}
+
+	/// Sets the zoom to the biggest one (but not bigger than the default one) with which
+	/// the columns from `left` to `right` (included) fit in the screen, or to the minimum zoom
+	/// if they can't fit. Returns the `camera_x` that centers these columns.
+	fn zoom_to_fit_columns(
+		&mut self,
+		left: i32,
+		right: i32,
+		map_height: i32,
+		screen_dims: Dimensions,
+	) -> f32 {
+		let fitting_zoom_w = screen_dims.w / ((right - left + 1) * TILE_PX);
+		let fitting_zoom_h = (screen_dims.h - self.top_left.y) / (map_height * TILE_PX);
+		self.zoom = (fitting_zoom_w.min(fitting_zoom_h) as f32)
+			.clamp(MapDrawingConfig::MIN_ZOOM, MapDrawingConfig::DEFAULT_ZOOM);
+		self.camera_x_centered_on_columns(left, right, screen_dims.w)
 	}
 
-	fn tile_coords_to_screen_rect(&self, tile_coords: Coords) -> Rect {
-		let dst_side = self.zoom * 16;
-		let left = -(self.camera_x * dst_side as f32) as i32;
+	fn tile_coords_to_screen_rect(&self, TileCoords(tile_coords): TileCoords) -> Rect {
+		let dst_side = self.tile_side();
 		Rect::xywh(
-			self.top_left.x + left + dst_side * tile_coords.x,
+			self.top_left.x + self.map_left() + dst_side * tile_coords.x,
 			self.top_left.y + dst_side * tile_coords.y,
 			dst_side,
 			dst_side,
 		)
 	}
 
-	fn screen_coords_to_tile_coords(&self, screen_coords: Coords) -> Coords {
-		let dst_side = (self.zoom * 16) as f32;
-		let left = -self.camera_x * dst_side;
-		(
-			((screen_coords.x as f32 - left - self.top_left.x as f32) / dst_side).floor() as i32,
-			((screen_coords.y as f32 - self.top_left.y as f32) / dst_side).floor() as i32,
+	/// Inverse of `tile_coords_to_screen_rect` (the pixel is in the returned tile's rect).
+	/// Pixels left of or above the map origin give negative tile coords, hence the flooring
+	/// `div_euclid` (plain `/` would round them toward zero, into the tile next to it).
+	fn screen_coords_to_tile_coords(&self, PixelCoords(screen_coords): PixelCoords) -> TileCoords {
+		let dst_side = self.tile_side();
+		TileCoords(
+			(
+				(screen_coords.x - self.top_left.x - self.map_left()).div_euclid(dst_side),
+				(screen_coords.y - self.top_left.y).div_euclid(dst_side),
+			)
+				.into(),
 		)
-			.into()
+	}
+}
+
+/// Places the HUD elements relative to the borders of the window (instead of at fixed pixel
+/// coords) so that the HUD follows the window when it is resized.
+struct HudLayout {
+	/// The window area minus the padding on all sides.
+	area: Rect,
+}
+
+impl HudLayout {
+	fn new(window_dims: Dimensions, padding: i32) -> HudLayout {
+		HudLayout {
+			area: Rect { top_left: (0, 0).into(), dims: window_dims }.add_margin(-padding),
+		}
+	}
+
+	/// The point that, when used with the same `anchor` pin point to draw something, places that
+	/// thing in the corresponding corner (or side) of the layout area, moved by `offset`.
+	fn point(&self, anchor: PinPoint, offset: CoordsDelta) -> Coords {
+		anchor.point_in_rect(self.area) + offset
+	}
+
+	/// A rect of the given dimensions placed as `point` would place it.
+	fn rect(&self, anchor: PinPoint, offset: CoordsDelta, dims: Dimensions) -> Rect {
+		let top_left = anchor.actual_top_left_coords(self.point(anchor, offset), dims);
+		Rect { top_left, dims }
 	}
 }
 
 fn main() {
 	env_logger::init();
+	let args: Vec<String> = std::env::args().collect();
+	let arg_value = |name: &str| -> Option<&String> {
+		args
+			.iter()
+			.position(|arg| arg == name)
+			.and_then(|index| args.get(index + 1))
+	};
+
+	// A session can be recorded with `--record path/to/file`
+	// and played back later with `--replay path/to/file`.
+	let record_path: Option<std::path::PathBuf> =
+		arg_value("--record").map(std::path::PathBuf::from);
+	let replay: Option<(RecordingHeader, Player)> =
+		arg_value("--replay").and_then(|path| match Player::load(std::path::Path::new(path)) {
+			Ok(replay) => Some(replay),
+			Err(error) => {
+				log::warn!("failed to load the replay \"{path}\" ({error}), playing normally");
+				None
+			},
+		});
+
+	// The best runs are kept in `leaderboard.json` (or in `--leaderboard path/to/file`).
+	let leaderboard_path = std::path::PathBuf::from(
+		arg_value("--leaderboard").map_or("leaderboard.json", |path| path.as_str()),
+	);
+	let mut leaderboard = Leaderboard::load_or_empty(&leaderboard_path);
+
+	// The settings are in `td.toml` (or in `--config path/to/file`).
+	let config_path =
+		std::path::PathBuf::from(arg_value("--config").map_or("td.toml", |path| path.as_str()));
+	let config = Config::load_or_default(&config_path);
+	// A replayed run is not a new run, it does not go in the leaderboard.
+	let is_replay = replay.is_some();
+	let mut run_is_in_leaderboard = is_replay;
+	// Where the current run ranks in the leaderboard, if it made it there.
+	let mut leaderboard_rank: Option<usize> = None;
+
+	// The chunks of the world can be made bigger or smaller with `--chunk-dims 16x12`.
+	let chunk_dims = arg_value("--chunk-dims").map_or(Chunk::DEFAULT_DIMS, |dims| {
+		let parsed = dims
+			.split_once('x')
+			.and_then(|(w, h)| Some((w.parse().ok()?, h.parse().ok()?)));
+		match parsed {
+			// The path needs a bit of room to wander around.
+			Some((w, h)) if 3 <= w && 3 <= h => Dimensions { w, h },
+			_ => {
+				log::warn!("invalid chunk dimensions \"{dims}\", using the default ones");
+				Chunk::DEFAULT_DIMS
+			},
+		}
+	});
+
+	// The world can be made finite with `--chunk-count 8`, the run is won when the caravan
+	// reaches its end (see `GameState::goal_is_reached`).
+	let max_chunk_count: Option<i32> =
+		arg_value("--chunk-count").and_then(|count| match count.parse::<i32>() {
+			Ok(count) if count >= 1 => Some(count),
+			_ => {
+				log::warn!("invalid chunk count \"{count}\", playing in an infinite world");
+				None
+			},
+		});
+
+	// A run can also be won by traveling some distance, with `--distance-goal 200`.
+	let distance_goal: Option<i32> =
+		arg_value("--distance-goal").and_then(|distance| match distance.parse::<i32>() {
+			Ok(distance) if distance >= 1 => Some(distance),
+			_ => {
+				log::warn!("invalid distance goal \"{distance}\", playing without a distance goal");
+				None
+			},
+		});
+
+	// The game RNG can be seeded with `--seed 1234` to play the same map again.
+	let seed: u64 = if let Some((header, _)) = &replay {
+		header.seed
+	} else if let Some(seed) = arg_value("--seed") {
+		seed.parse().unwrap_or_else(|_| {
+			log::warn!("invalid seed \"{seed}\", using a random seed instead");
+			random_seed()
+		})
+	} else {
+		random_seed()
+	};
+	log::info!("seed {seed}");
+	seed_rng(seed);
+
+	// Running with `--fixed-step-animations` makes the animations advance by steps
+	// (see `AnimationClock`) instead of following the wall clock.
+	AnimationClock::set_fixed_step(args.iter().any(|arg| arg == "--fixed-step-animations"));
+	AnimationClock::set_slow_motion(config.gameplay.slow_motion);
+
+	// A challenge mode can be enabled with `--turn-timer 10` so that each player phase
+	// ends by itself after that many seconds.
+	let turn_time_limit: Option<Duration> = arg_value("--turn-timer").and_then(|seconds| {
+		// `try_from_secs_f32` rules out `inf` and numbers too big for a `Duration`.
+		let positive_seconds = seconds.parse::<f32>().ok().filter(|seconds| *seconds > 0.0);
+		match positive_seconds.map(Duration::try_from_secs_f32) {
+			Some(Ok(duration)) => Some(duration),
+			_ => {
+				log::warn!("invalid turn timer \"{seconds}\", playing without a turn timer");
+				None
+			},
+		}
+	});
+
 	let event_loop = winit::event_loop::EventLoop::new();
-	let window = winit::window::WindowBuilder::new()
-		.with_title("Defend the caravan")
-		.with_inner_size(winit::dpi::PhysicalSize::new(800, 800))
-		.with_maximized(true)
-		.build(&event_loop)
-		.unwrap();
+	let window = if let Some((header, _)) = &replay {
+		// The window starts with the size it had in the recording as the map depends on it.
+		winit::window::WindowBuilder::new()
+			.with_title("Defend the caravan (replay)")
+			.with_inner_size(header.window_size)
+			.build(&event_loop)
+			.unwrap()
+	} else {
+		winit::window::WindowBuilder::new()
+			.with_title("Defend the caravan")
+			.with_inner_size(winit::dpi::PhysicalSize::new(800, 800))
+			.with_maximized(true)
+			.build(&event_loop)
+			.unwrap()
+	};
 
 	// Center the window.
 	let screen_size = window.available_monitors().next().unwrap().size();
@@ -1115,61 +4226,60 @@ fn main() {
 		screen_size.height / 2 - window_outer_size.height / 2,
 	));
 
-	let mut renderer = Renderer::new(&window, Color::rgb_u8(80, 80, 200));
-
-	let audio_player = AudioPlayer::new();
-
-	let mut map = Map { grid: Grid::of_size_zero() };
+	// A spritesheet other than the embedded one can be used (for modding or theming)
+	// by running with `--spritesheet path/to/spritesheet.png`.
+	let spritesheet_path: Option<std::path::PathBuf> =
+		arg_value("--spritesheet").map(std::path::PathBuf::from);
 
-	while map.grid.dims.w * 8 * 8 < renderer.dims().w {
-		map.generate_chunk_on_the_right();
-	}
+	let clear_color = Color::rgb_u8(80, 80, 200);
+	let mut renderer = if let Some(path) = &spritesheet_path {
+		Renderer::with_spritesheet_path(&window, clear_color, path)
+	} else {
+		Renderer::new(&window, clear_color)
+	};
 
-	for x in 0..15 {
-		for y in 0..map.grid.dims.h {
-			let coords = (x, y).into();
-			if map.grid.get(coords).unwrap().has_enemy() {
-				map.grid.get_mut(coords).unwrap().obj = None;
-			}
-		}
-	}
+	let audio_player = AudioPlayer::new(config.audio.master_volume);
+	let caravan_step_cadence = config.audio.caravan_step_cadence;
 
-	for y in 0..map.grid.dims.h {
-		let coords = (0, y).into();
-		if let Ground::Path(Path { distance: 0, .. }) = map.grid.get(coords).unwrap().ground {
-			map.grid.get_mut(coords).unwrap().obj = Some(Obj::Caravan);
-		}
-	}
+	let mut map_drawing_config = MapDrawingConfig {
+		top_left: (0, 180).into(),
+		zoom: MapDrawingConfig::DEFAULT_ZOOM,
+		camera_x: 0.0,
+	};
 
-	#[derive(PartialEq, Eq)]
-	enum Phase {
-		Player,
-		Enemy,
-		Tower,
-		GameOver,
-	}
-	let mut phase = Phase::Player;
+	let gameplay_config = config.gameplay;
+	let (mut map, mut game) = new_run(
+		chunk_dims,
+		max_chunk_count,
+		distance_goal,
+		&gameplay_config,
+		(renderer.dims().w - 1) / map_drawing_config.tile_side(),
+	);
 
 	#[derive(PartialEq, Eq)]
 	enum InterfaceMode {
 		Normal,
 		MovingCaravanChoosingDst,
-		MovingCaravanAnimation { remaining_moves: i32 },
+		MovingCaravanAnimation {
+			remaining_moves: i32,
+			/// Counts the steps taken so far, for the rhythm of the step sounds.
+			steps_done: i32,
+		},
+		/// The tower to place follows a keyboard cursor as a ghost. The facing does nothing yet,
+		/// it is there for the towers with directional shots to come.
+		Building {
+			cursor: Coords,
+			facing: CoordsDelta,
+		},
 	}
 	let mut interface_mode = InterfaceMode::Normal;
 
-	let mut turn_counter = 0;
-	let mut distance_traveled = 0;
-	let mut crystal_amount = 20;
-
 	let mut current_animations: Vec<Animation> = vec![];
 	let mut end_player_phase_after_animation = false;
 	let mut end_player_phase_right_now = false;
 
-	let mut map_drawing_config =
-		MapDrawingConfig { top_left: (0, 180).into(), zoom: 4, camera_x: 0.0 };
-
-	let mut cursor_position = Coords::from((0, 0));
+	let mut cursor_position = PixelCoords((0, 0).into());
+	let mut cursor_in_window = false;
 	let mut hovered_tile_coords: Option<Coords> = None;
 	let mut selected_tile_coords: Option<Coords> = None;
 
@@ -1177,13 +4287,230 @@ fn main() {
 
 	let mut tower_type_to_place = Tower::Basic;
 
+	let mut queued_action: Option<QueuedAction> = None;
+
+	// In auto-advance mode, the caravan moves forward by itself once at the beginning
+	// of every player phase (if it can), and that ends the player phase.
+	let mut auto_advance = false;
+	let mut auto_advance_done_this_phase = false;
+
+	// The countdown of the current player phase in turn timer mode (see `turn_time_limit`),
+	// it is paused while the console is open.
+	let mut turn_timer: Option<TimeProgression> = turn_time_limit.map(TimeProgression::new);
+	// The countdown to the end of the player phase when the player does nothing (see
+	// `GameplayConfig::idle_turn_end_seconds`), it starts over on any input and it only runs
+	// while the player could act.
+	let idle_seconds = gameplay_config.idle_turn_end_seconds;
+	let idle_time_limit = if idle_seconds > 0.0 {
+		match Duration::try_from_secs_f32(idle_seconds) {
+			Ok(duration) => Some(duration),
+			Err(error) => {
+				log::warn!("invalid idle turn end of {idle_seconds} seconds ({error}), ignored");
+				None
+			},
+		}
+	} else {
+		None
+	};
+	let mut idle_timer: Option<TimeProgression> = idle_time_limit.map(TimeProgression::new);
+	// Set by the event loop on every input (recorded or replayed), for the idle timer.
+	let input_happened = std::rc::Rc::new(std::cell::Cell::new(false));
+	let input_happened_in_event_loop = std::rc::Rc::clone(&input_happened);
+	// Held to skip the enemy and tower phases animations.
+	let mut fast_forward = false;
+	// Held to pan the camera continuously.
+	let mut camera_pan_left = false;
+	let mut camera_pan_right = false;
+	// Pan the camera when the cursor is close to the left or right side of the window.
+	let mut edge_scroll = false;
+	// While the middle mouse button is held, the cursor position and the camera x
+	// when the drag started, the map follows the cursor.
+	let mut camera_drag: Option<(PixelCoords, f32)> = None;
+	// Warn about the enemy that will spawn with a marker on the right side of the window.
+	let mut display_spawn_indicator = true;
+	// Tint the path tiles that the caravan already went over.
+	let mut display_caravan_trail = true;
+	// Show where each enemy will go in the next enemy phase, during the player phase.
+	let mut display_enemy_moves = false;
+	// The furthest path distance the caravan has been at. Path distances only increase
+	// along the path, so the trail is all the path tiles up to that distance.
+	let mut caravan_max_path_dist = 0;
+
+	// The enemy or tower that is playing in the enemy or tower phase (see `phase_step`),
+	// highlighted so that the automated phases are easier to follow.
+	let mut acting_unit: Option<(Coords, TimeProgression)> = None;
+	// Zoomed out to see from the caravan to the rightmost enemy (see `zoom_to_fit_columns`).
+	let mut zoomed_to_fit = false;
+
 	let mut display_path_dist = false;
+	let mut display_debug_grid = false;
+	// Hovering a shot in flight shows its stats instead of the tile tooltip (toggled with F7),
+	// slowing the game down (see `GameSpeed`) makes the shots easier to catch.
+	let mut shot_inspector = false;
+	let mut display_frame_time_graph = false;
+	let mut frame_time_history = FrameTimeHistory::new();
+
+	// Toggled with W.
+	let mut weather_particles = WeatherParticles::new();
+	let mut toasts = Toasts::new();
+	// The debug console, opened with Tab (see `run_console_command`).
+	let mut console_input: Option<TextInput> = None;
+
+	// The water shimmer loops over this period.
+	let water_animation = TimeProgression::new(Duration::from_secs_f32(2.5));
 
 	let mut last_time = std::time::Instant::now();
 
+	let mut recorder = record_path.and_then(|path| {
+		let header = RecordingHeader { seed, window_size: window.inner_size() };
+		match Recorder::create(&path, &header) {
+			Ok(recorder) => Some(recorder),
+			Err(error) => {
+				log::warn!(
+					"failed to create the recording \"{}\" ({error})",
+					path.display()
+				);
+				None
+			},
+		}
+	});
+	let mut replay_player = replay.map(|(_header, mut player)| {
+		player.start_now();
+		player
+	});
+
+	let keys = config.keys;
+
+	let main_window_id = window.id();
+	// The replay resizes the window like in the recording from outside of `handle_event`.
+	let window = std::rc::Rc::new(window);
+	let window_in_event_loop = std::rc::Rc::clone(&window);
 	use winit::event::*;
-	event_loop.run(move |event, _, control_flow| match event {
+	use winit::event_loop::ControlFlow;
+	let mut handle_event = move |event: Event<()>, control_flow: &mut ControlFlow| match event {
 		Event::WindowEvent { ref event, window_id } if window_id == window.id() => match event {
+			WindowEvent::ReceivedCharacter(ch) => {
+				if let Some(console_input) = &mut console_input {
+					console_input.type_char(*ch);
+				}
+			},
+			WindowEvent::KeyboardInput {
+				input: KeyboardInput { state: ElementState::Pressed, virtual_keycode, .. },
+				..
+			} if console_input.is_some() => match virtual_keycode {
+				Some(VirtualKeyCode::Back) => console_input.as_mut().unwrap().backspace(),
+				Some(VirtualKeyCode::Escape) => console_input = None,
+				Some(VirtualKeyCode::Return | VirtualKeyCode::NumpadEnter) => {
+					let command = console_input.take().unwrap().text;
+					match run_console_command(&command, &mut map, &mut game, &mut weather_particles) {
+						Ok(message) => toasts.push(message),
+						Err(message) => {
+							log::warn!("console: {message}");
+							toasts.push(message);
+						},
+					}
+				},
+				_ => {},
+			},
+			WindowEvent::KeyboardInput { .. } if console_input.is_some() => {},
+			WindowEvent::KeyboardInput {
+				input: KeyboardInput { state: ElementState::Pressed, virtual_keycode: Some(key), .. },
+				..
+			} if *key == keys.console => {
+				console_input = Some(TextInput::new("> "));
+				// The key releases will go to the console, so the held keys are let go now.
+				fast_forward = false;
+				camera_pan_left = false;
+				camera_pan_right = false;
+			},
+
+			WindowEvent::KeyboardInput {
+				input: KeyboardInput { state: ElementState::Pressed, virtual_keycode: Some(key), .. },
+				..
+			} if *key == keys.build_mode
+				&& interface_mode == InterfaceMode::Normal
+				&& !game.phase.is_over() =>
+			{
+				// The ghost starts on the tile the player is looking at.
+				let screen_center = PixelCoords((renderer.dims().w / 2, renderer.dims().h / 2).into());
+				let cursor = selected_tile_coords
+					.or(hovered_tile_coords)
+					.unwrap_or_else(|| {
+						let TileCoords(coords) =
+							map_drawing_config.screen_coords_to_tile_coords(screen_center);
+						(
+							coords.x.clamp(0, map.grid.dims.w - 1),
+							coords.y.clamp(0, map.grid.dims.h - 1),
+						)
+							.into()
+					});
+				interface_mode = InterfaceMode::Building { cursor, facing: CoordsDelta::RIGHT };
+			},
+			WindowEvent::KeyboardInput {
+				input: KeyboardInput { state: ElementState::Pressed, virtual_keycode: Some(key), .. },
+				..
+			} if matches!(interface_mode, InterfaceMode::Building { .. })
+				&& (*key == VirtualKeyCode::Escape
+					|| [
+						keys.build_mode,
+						keys.place_tower,
+						keys.rotate_tower,
+						keys.cursor_up,
+						keys.cursor_down,
+						keys.cursor_left,
+						keys.cursor_right,
+					]
+					.contains(key)) =>
+			{
+				let InterfaceMode::Building { cursor, facing } = interface_mode else {
+					unreachable!()
+				};
+				if *key == VirtualKeyCode::Escape || *key == keys.build_mode {
+					interface_mode = InterfaceMode::Normal;
+					queued_action = None;
+				} else if *key == keys.place_tower {
+					queued_action = Some(QueuedAction::PlaceGhost(cursor));
+				} else if *key == keys.rotate_tower {
+					interface_mode =
+						InterfaceMode::Building { cursor, facing: facing.rotated_clockwise() };
+				} else {
+					let direction = if *key == keys.cursor_up {
+						CoordsDelta::UP
+					} else if *key == keys.cursor_down {
+						CoordsDelta::DOWN
+					} else if *key == keys.cursor_left {
+						CoordsDelta::LEFT
+					} else {
+						CoordsDelta::RIGHT
+					};
+					let cursor = cursor + direction;
+					if map.grid.dims.contains(cursor) {
+						interface_mode = InterfaceMode::Building { cursor, facing };
+						// The camera follows the ghost when it goes off screen.
+						let dst = map_drawing_config.tile_coords_to_screen_rect(TileCoords(cursor));
+						if dst.left() < 0 || renderer.dims().w < dst.right_excluded() {
+							let side = map_drawing_config.tile_side();
+							map_drawing_config.camera_x =
+								map_drawing_config.camera_x_centered_on(cursor.x, renderer.dims().w);
+							map.generate_chunks_until_wider_than(
+								(map_drawing_config.camera_x + 1.0) as i32 + (renderer.dims().w + 1) / side,
+							);
+						}
+					}
+				}
+			},
+
+			WindowEvent::KeyboardInput {
+				input:
+					KeyboardInput {
+						state: ElementState::Pressed,
+						virtual_keycode: Some(VirtualKeyCode::Escape),
+						..
+					},
+				..
+			} if queued_action.is_some() => {
+				queued_action = None;
+			},
 			WindowEvent::CloseRequested
 			| WindowEvent::KeyboardInput {
 				input:
@@ -1201,16 +4528,26 @@ fn main() {
 				renderer.resized((*new_size).into());
 				window.request_redraw();
 
-				while map.grid.dims.w * 8 * 8
-					<= (map_drawing_config.camera_x + 1.0) as i32 * 8 * 8 + renderer.dims().w + 1
-				{
-					map.generate_chunk_on_the_right();
-				}
+				let side = map_drawing_config.tile_side();
+				map.generate_chunks_until_wider_than(
+					(map_drawing_config.camera_x + 1.0) as i32 + (renderer.dims().w + 1) / side,
+				);
 			},
 
 			WindowEvent::CursorMoved { position, .. } => {
-				cursor_position = (position.x.floor() as i32, position.y.floor() as i32).into();
-				let coords = map_drawing_config.screen_coords_to_tile_coords(cursor_position);
+				cursor_position =
+					PixelCoords((position.x.floor() as i32, position.y.floor() as i32).into());
+				cursor_in_window = true;
+				if let Some((PixelCoords(drag_start), camera_x_start)) = camera_drag {
+					let side = map_drawing_config.tile_side();
+					let dx = cursor_position.0.x - drag_start.x;
+					map_drawing_config.camera_x = (camera_x_start - dx as f32 / side as f32).max(0.0);
+					map.generate_chunks_until_wider_than(
+						(map_drawing_config.camera_x + 1.0) as i32 + (renderer.dims().w + 1) / side,
+					);
+				}
+				let TileCoords(coords) =
+					map_drawing_config.screen_coords_to_tile_coords(cursor_position);
 				if map.grid.dims.contains(coords) {
 					hovered_tile_coords = Some(coords);
 				} else {
@@ -1220,6 +4557,7 @@ fn main() {
 
 			WindowEvent::CursorLeft { .. } => {
 				hovered_tile_coords = None;
+				cursor_in_window = false;
 			},
 
 			WindowEvent::MouseInput {
@@ -1227,98 +4565,25 @@ fn main() {
 				button: MouseButton::Left,
 				..
 			} => {
-				#[allow(clippy::unnecessary_unwrap)] // `if let &&` is not stable yet you nincompoop
 				if selected_tile_coords.is_some()
 					&& selected_tile_coords == hovered_tile_coords
-					&& current_animations.is_empty()
-					&& phase == Phase::Player
+					&& interface_mode == InterfaceMode::Normal
 				{
-					let tile = map.grid.get(selected_tile_coords.unwrap()).unwrap().clone();
-					let tower_price = 10;
-					if tile.obj.is_none()
-						&& !tile.has_water()
-						&& crystal_amount >= tower_price
-						&& interface_mode == InterfaceMode::Normal
-					{
-						// Place a tower on empty ground.
-						current_animations.push(Animation {
-							action: AnimationAction::Appear {
-								obj: Obj::Tower {
-									actions: 0,
-									hp: tower_type_to_place.initial_hp(),
-									fire: 0,
-									colored_animation: None,
-									variant: tower_type_to_place.clone(),
-								},
-								to: selected_tile_coords.unwrap(),
-							},
-							tp: TimeProgression::new(Duration::from_secs_f32(0.05)),
-						});
-						audio_player.play_sound_effect(SoundEffect::Place);
-						crystal_amount -= tower_price;
-						end_player_phase_after_animation = true;
-					} else if matches!(tile.obj, Some(Obj::Crystal))
-						&& current_animations.is_empty()
-						&& interface_mode == InterfaceMode::Normal
-					{
-						// Mine the crystal.
-						current_animations.push(Animation {
-							action: AnimationAction::Disappear {
-								obj: map
-									.grid
-									.get_mut(selected_tile_coords.unwrap())
-									.unwrap()
-									.obj
-									.take()
-									.unwrap(),
-								from: selected_tile_coords.unwrap(),
-							},
-							tp: TimeProgression::new(Duration::from_secs_f32(0.05)),
-						});
-						audio_player.play_sound_effect(SoundEffect::Mine);
-						crystal_amount += 30;
-						end_player_phase_after_animation = true;
-					} else if matches!(tile.obj, Some(Obj::Caravan))
-						&& current_animations.is_empty()
-						&& interface_mode == InterfaceMode::Normal
-					{
-						interface_mode = InterfaceMode::MovingCaravanChoosingDst;
-						// Make selectable the tiles on which the caravan can move.
-						let path_coords = map.path_coords();
-						let caravan_path_dist = map.caradan_path_dist().unwrap();
-						for coords in path_coords {
-							let path = map.grid.get(coords).unwrap().path().unwrap();
-							if path.distance <= caravan_path_dist {
-								continue;
-							}
-							if map
-								.grid
-								.get(coords)
-								.unwrap()
-								.obj
-								.as_ref()
-								.is_some_and(|obj| !matches!(obj, Obj::Caravan))
-							{
-								break;
-							}
-							selectable_tile_coords.push(coords);
-						}
-					}
+					queued_action = selected_tile_coords.map(QueuedAction::ClickTile);
 				} else if interface_mode == InterfaceMode::MovingCaravanChoosingDst
 					&& hovered_tile_coords.is_some_and(|coords| selectable_tile_coords.contains(&coords))
 				{
-					let dst_tile = map.grid.get(hovered_tile_coords.unwrap()).unwrap().clone();
+					let dst_tile = map.grid.at(hovered_tile_coords.unwrap()).clone();
 					let dst_dist = dst_tile.path().unwrap().distance;
-					let src_dist = map
-						.caravan_coords_and_tile()
-						.unwrap()
-						.1
-						.path()
-						.unwrap()
-						.distance;
-					let move_dist = dst_dist - src_dist;
-					interface_mode =
-						InterfaceMode::MovingCaravanAnimation { remaining_moves: move_dist };
+					interface_mode = if let Some(src_dist) = map.caradan_path_dist() {
+						let move_dist = dst_dist - src_dist;
+						InterfaceMode::MovingCaravanAnimation {
+							remaining_moves: move_dist,
+							steps_done: 0,
+						}
+					} else {
+						InterfaceMode::Normal
+					};
 					selectable_tile_coords.clear();
 				} else if interface_mode == InterfaceMode::Normal {
 					selected_tile_coords = hovered_tile_coords;
@@ -1326,6 +4591,7 @@ fn main() {
 				} else {
 					interface_mode = InterfaceMode::Normal;
 					selectable_tile_coords.clear();
+					queued_action = None;
 				}
 			},
 
@@ -1336,38 +4602,245 @@ fn main() {
 			} => {
 				selected_tile_coords = None;
 				selectable_tile_coords.clear();
+				queued_action = None;
+			},
+
+			WindowEvent::MouseInput { state, button: MouseButton::Middle, .. } => {
+				camera_drag = (*state == ElementState::Pressed)
+					.then_some((cursor_position, map_drawing_config.camera_x));
+			},
+
+			WindowEvent::KeyboardInput {
+				input: KeyboardInput { state: ElementState::Pressed, virtual_keycode: Some(key), .. },
+				..
+			} if *key == keys.step_forward => {
+				queued_action = Some(QueuedAction::StepForward);
+			},
+
+			WindowEvent::KeyboardInput {
+				input: KeyboardInput { state: ElementState::Pressed, virtual_keycode: Some(key), .. },
+				..
+			} if *key == keys.auto_advance => {
+				auto_advance = !auto_advance;
+			},
+
+			WindowEvent::KeyboardInput {
+				input: KeyboardInput { state: ElementState::Pressed, virtual_keycode: Some(key), .. },
+				..
+			} if *key == keys.new_run && game.phase.is_over() => {
+				// Start over on a new map, the settings and the toggles stay as they are.
+				let auto_mining = game.auto_mining;
+				map_drawing_config.zoom = MapDrawingConfig::DEFAULT_ZOOM;
+				map_drawing_config.camera_x = 0.0;
+				(map, game) = new_run(
+					chunk_dims,
+					max_chunk_count,
+					distance_goal,
+					&gameplay_config,
+					(renderer.dims().w - 1) / map_drawing_config.tile_side(),
+				);
+				game.auto_mining = auto_mining;
+				interface_mode = InterfaceMode::Normal;
+				current_animations.clear();
+				end_player_phase_after_animation = false;
+				end_player_phase_right_now = false;
+				selected_tile_coords = None;
+				selectable_tile_coords.clear();
+				queued_action = None;
+				auto_advance_done_this_phase = false;
+				turn_timer = turn_time_limit.map(TimeProgression::new);
+				caravan_max_path_dist = 0;
+				acting_unit = None;
+				zoomed_to_fit = false;
+				toasts = Toasts::new();
+				run_is_in_leaderboard = is_replay;
+				leaderboard_rank = None;
+			},
+
+			WindowEvent::KeyboardInput {
+				input: KeyboardInput { state: ElementState::Pressed, virtual_keycode: Some(key), .. },
+				..
+			} if *key == keys.auto_mining => {
+				game.auto_mining = !game.auto_mining;
+			},
+
+			WindowEvent::KeyboardInput {
+				input: KeyboardInput { state: ElementState::Pressed, virtual_keycode: Some(key), .. },
+				..
+			} if *key == keys.scroll_forward => {
+				queued_action = Some(QueuedAction::ScrollForward);
+			},
+
+			WindowEvent::KeyboardInput {
+				input: KeyboardInput { state: ElementState::Pressed, virtual_keycode: Some(key), .. },
+				..
+			} if *key == keys.recenter_camera
+				&& current_animations.is_empty()
+				&& game.phase == Phase::Player =>
+			{
+				// Recenter the camera on the caravan (without ending the turn).
+				if let Some(caravan_coords) = map.caravan_coords() {
+					let to =
+						map_drawing_config.camera_x_centered_on(caravan_coords.x, renderer.dims().w);
+					current_animations.push(Animation {
+						action: AnimationAction::CameraMoveX {
+							from: map_drawing_config.camera_x,
+							to,
+							zoom: None,
+						},
+						tp: TimeProgression::new(Duration::from_secs_f32(0.2)),
+					});
+					let side = map_drawing_config.tile_side();
+					map.generate_chunks_until_wider_than(to as i32 + (renderer.dims().w + 1) / side);
+				}
+			},
+
+			WindowEvent::KeyboardInput {
+				input: KeyboardInput { state: ElementState::Pressed, virtual_keycode: Some(key), .. },
+				..
+			} if *key == keys.zoom_to_fit && current_animations.is_empty() => {
+				// Toggle between the default zoom and a zoom that shows everything
+				// from the caravan to the rightmost enemy (handy for screenshots).
+				let caravan_x = map.caravan_coords().map(|coords| coords.x);
+				let from_zoom = map_drawing_config.zoom;
+				let to = if zoomed_to_fit {
+					map_drawing_config.zoom = MapDrawingConfig::DEFAULT_ZOOM;
+					caravan_x
+						.map(|x| map_drawing_config.camera_x_centered_on(x, renderer.dims().w))
+						.unwrap_or(map_drawing_config.camera_x)
+				} else {
+					let left = caravan_x.unwrap_or(map_drawing_config.camera_x as i32);
+					let rightmost_enemy_x = map.enemies().map(|(coords, _)| coords.x).max();
+					let right = rightmost_enemy_x.unwrap_or(map.grid.dims.w - 1).max(left);
+					map_drawing_config.zoom_to_fit_columns(left, right, map.grid.dims.h, renderer.dims())
+				};
+				zoomed_to_fit = !zoomed_to_fit;
+				let side = map_drawing_config.tile_side();
+				map.generate_chunks_until_wider_than(to as i32 + (renderer.dims().w + 1) / side);
+				// The zoom was set to where it is going, it gets there along with the camera.
+				let to_zoom = map_drawing_config.zoom;
+				map_drawing_config.zoom = from_zoom;
+				current_animations.push(Animation {
+					action: AnimationAction::CameraMoveX {
+						from: map_drawing_config.camera_x,
+						to,
+						zoom: Some((from_zoom, to_zoom)),
+					},
+					tp: TimeProgression::new(Duration::from_secs_f32(0.3)),
+				});
+			},
+
+			WindowEvent::KeyboardInput {
+				input: KeyboardInput { state: ElementState::Pressed, virtual_keycode: Some(key), .. },
+				..
+			} if *key == keys.skip_turn => {
+				queued_action = Some(QueuedAction::SkipTurn);
+			},
+
+			WindowEvent::KeyboardInput {
+				input: KeyboardInput { state: ElementState::Pressed, virtual_keycode: Some(key), .. },
+				..
+			} if *key == keys.cycle_tower => {
+				tower_type_to_place = match tower_type_to_place {
+					Tower::Basic => Tower::Pink,
+					Tower::Pink => Tower::Blue,
+					Tower::Blue => Tower::Fortress,
+					Tower::Fortress => Tower::Storm,
+					Tower::Storm => Tower::Support,
+					Tower::Support => Tower::Basic,
+				};
+			},
+
+			WindowEvent::KeyboardInput {
+				input: KeyboardInput { state: ElementState::Pressed, virtual_keycode: Some(key), .. },
+				..
+			} if *key == keys.cycle_target_priority => {
+				// Cycle the target priority of the selected tower.
+				if let Some(coords) = selected_tile_coords.map(|coords| map.anchor_of(coords)) {
+					if let Some(Obj::Tower { target_priority, .. }) = &mut map.grid.at_mut(coords).obj {
+						*target_priority = target_priority.next();
+					}
+				}
+			},
+
+			WindowEvent::KeyboardInput {
+				input: KeyboardInput { state: ElementState::Pressed, virtual_keycode: Some(key), .. },
+				..
+			} if *key == keys.hold_fire => {
+				// Make the selected tower hold its fire, or fire again.
+				if let Some(coords) = selected_tile_coords.map(|coords| map.anchor_of(coords)) {
+					if let Some(Obj::Tower { enabled, .. }) = &mut map.grid.at_mut(coords).obj {
+						*enabled = !*enabled;
+					}
+				}
 			},
 
 			WindowEvent::KeyboardInput {
 				input:
 					KeyboardInput {
 						state: ElementState::Pressed,
-						virtual_keycode: Some(VirtualKeyCode::Space),
+						virtual_keycode: Some(VirtualKeyCode::F3),
 						..
 					},
 				..
-			} if current_animations.is_empty() && phase == Phase::Player => {
-				for coords in map.grid.dims.iter() {
-					if map.grid.get(coords).is_some_and(|tile| tile.has_caravan()) {
-						if let Some(Path { forward, distance, .. }) =
-							map.grid.get(coords).unwrap().path().cloned()
-						{
-							let dst_coords = coords + forward;
-							if map.grid.get(dst_coords).unwrap().obj.is_none() {
-								current_animations.push(Animation {
-									action: AnimationAction::Move {
-										obj: map.grid.get_mut(coords).unwrap().obj.take().unwrap(),
-										from: coords,
-										to: dst_coords,
-									},
-									tp: TimeProgression::new(Duration::from_secs_f32(0.05)),
-								});
-								distance_traveled = distance + 1;
-								end_player_phase_after_animation = true;
-							}
-							break;
+			} => {
+				display_debug_grid = !display_debug_grid;
+			},
+
+			WindowEvent::KeyboardInput {
+				input:
+					KeyboardInput {
+						state: ElementState::Pressed,
+						virtual_keycode: Some(VirtualKeyCode::F4),
+						..
+					},
+				..
+			} => {
+				display_frame_time_graph = !display_frame_time_graph;
+			},
+
+			WindowEvent::KeyboardInput {
+				input:
+					KeyboardInput {
+						state: ElementState::Pressed,
+						virtual_keycode: Some(VirtualKeyCode::F7),
+						..
+					},
+				..
+			} => {
+				shot_inspector = !shot_inspector;
+			},
+
+			WindowEvent::KeyboardInput {
+				input:
+					KeyboardInput {
+						state: ElementState::Pressed,
+						virtual_keycode: Some(VirtualKeyCode::F8),
+						..
+					},
+				..
+			} => {
+				// Safety valve in case an animation ever gets stuck: All the animations (and the
+				// ones that follow from them) end right now, with their effects applied as if they
+				// ended normally so that no object is lost. Things may visually snap into place,
+				// and the sounds are skipped.
+				let mut sounds: Vec<SoundEffect> = vec![];
+				while !current_animations.is_empty() {
+					let anim = current_animations.remove(0);
+					if let AnimationAction::CameraMoveX { to, zoom, .. } = anim.action {
+						map_drawing_config.camera_x = to;
+						if let Some((_, to_zoom)) = zoom {
+							map_drawing_config.zoom = to_zoom;
 						}
 					}
+					let new_anims = resolve_animation(&mut map, &anim, &mut game, &mut sounds);
+					current_animations.extend(new_anims);
+				}
+				if end_player_phase_after_animation {
+					end_player_phase_after_animation = false;
+					end_player_phase_right_now = false;
+					selectable_tile_coords.clear();
+					end_player_phase(&mut map, &mut game);
 				}
 			},
 
@@ -1375,65 +4848,100 @@ fn main() {
 				input:
 					KeyboardInput {
 						state: ElementState::Pressed,
-						virtual_keycode: Some(VirtualKeyCode::Return),
+						virtual_keycode: Some(VirtualKeyCode::F6),
 						..
 					},
 				..
-			} if current_animations.is_empty() && phase == Phase::Player => {
-				current_animations.push(Animation {
-					action: AnimationAction::CameraMoveX {
-						from: map_drawing_config.camera_x,
-						to: map_drawing_config.camera_x + 1.0,
-					},
-					tp: TimeProgression::new(Duration::from_secs_f32(0.05)),
-				});
-				let side = map_drawing_config.tile_side();
-				while map.grid.dims.w * side
-					<= (map_drawing_config.camera_x + 1.0) as i32 * side + renderer.dims().w + 1
-				{
-					map.generate_chunk_on_the_right();
+			} => {
+				// Hot-reload the spritesheet from disk, so that sprites can be tweaked live.
+				if let Some(path) = &spritesheet_path {
+					match load_spritesheet(path) {
+						Ok(spritesheet) => {
+							renderer.set_spritesheet(spritesheet);
+							log::info!("reloaded the spritesheet from \"{}\"", path.display());
+						},
+						Err(error) => log::warn!(
+							"could not reload the spritesheet from \"{}\", keeping the old one: {error}",
+							path.display()
+						),
+					}
+				} else {
+					log::warn!("no spritesheet to reload, run with `--spritesheet path` to use one");
 				}
-				end_player_phase_after_animation = true;
 			},
 
 			WindowEvent::KeyboardInput {
 				input:
 					KeyboardInput {
-						state: ElementState::Pressed,
-						virtual_keycode: Some(VirtualKeyCode::S),
+						state,
+						virtual_keycode: Some(VirtualKeyCode::RShift | VirtualKeyCode::LShift),
 						..
 					},
 				..
-			} if current_animations.is_empty() && phase == Phase::Player => {
-				end_player_phase_right_now = true;
+			} => {
+				display_path_dist = *state == ElementState::Pressed;
+			},
+
+			WindowEvent::KeyboardInput {
+				input: KeyboardInput { state: ElementState::Pressed, virtual_keycode: Some(key), .. },
+				..
+			} if *key == keys.weather => {
+				weather_particles.set_weather(weather_particles.weather.next());
+			},
+
+			WindowEvent::KeyboardInput {
+				input: KeyboardInput { state, virtual_keycode: Some(key), .. },
+				..
+			} if *key == keys.fast_forward => {
+				fast_forward = *state == ElementState::Pressed;
+			},
+
+			WindowEvent::KeyboardInput {
+				input: KeyboardInput { state, virtual_keycode: Some(key), .. },
+				..
+			} if *key == keys.pan_left => {
+				camera_pan_left = *state == ElementState::Pressed;
+			},
+			WindowEvent::KeyboardInput {
+				input: KeyboardInput { state, virtual_keycode: Some(key), .. },
+				..
+			} if *key == keys.pan_right => {
+				camera_pan_right = *state == ElementState::Pressed;
+			},
+
+			WindowEvent::KeyboardInput {
+				input: KeyboardInput { state: ElementState::Pressed, virtual_keycode: Some(key), .. },
+				..
+			} if *key == keys.edge_scroll => {
+				edge_scroll = !edge_scroll;
+			},
+
+			WindowEvent::KeyboardInput {
+				input: KeyboardInput { state: ElementState::Pressed, virtual_keycode: Some(key), .. },
+				..
+			} if *key == keys.spawn_indicator => {
+				display_spawn_indicator = !display_spawn_indicator;
+			},
+
+			WindowEvent::KeyboardInput {
+				input: KeyboardInput { state: ElementState::Pressed, virtual_keycode: Some(key), .. },
+				..
+			} if *key == keys.caravan_trail => {
+				display_caravan_trail = !display_caravan_trail;
 			},
 
 			WindowEvent::KeyboardInput {
-				input:
-					KeyboardInput {
-						state: ElementState::Pressed,
-						virtual_keycode: Some(VirtualKeyCode::T),
-						..
-					},
+				input: KeyboardInput { state: ElementState::Pressed, virtual_keycode: Some(key), .. },
 				..
-			} => {
-				tower_type_to_place = match tower_type_to_place {
-					Tower::Basic => Tower::Pink,
-					Tower::Pink => Tower::Blue,
-					Tower::Blue => Tower::Basic,
-				};
+			} if *key == keys.enemy_moves => {
+				display_enemy_moves = !display_enemy_moves;
 			},
 
 			WindowEvent::KeyboardInput {
-				input:
-					KeyboardInput {
-						state,
-						virtual_keycode: Some(VirtualKeyCode::RShift | VirtualKeyCode::LShift),
-						..
-					},
+				input: KeyboardInput { state: ElementState::Pressed, virtual_keycode: Some(key), .. },
 				..
-			} => {
-				display_path_dist = *state == ElementState::Pressed;
+			} if *key == keys.game_speed => {
+				AnimationClock::set_game_speed(AnimationClock::game_speed().next());
 			},
 
 			_ => {},
@@ -1444,14 +4952,70 @@ fn main() {
 			let dt = now.duration_since(last_time);
 			last_time = now;
 			let fps = 1.0 / dt.as_secs_f32();
+			frame_time_history.push(dt);
+			let clock_before = AnimationClock::now();
+			AnimationClock::advance(dt);
+			let clock_dt = AnimationClock::now().saturating_sub(clock_before);
+			if console_input.is_some() {
+				if let Some(turn_timer) = &mut turn_timer {
+					turn_timer.delay(clock_dt);
+				}
+			}
+			weather_particles.update(dt.as_secs_f32(), renderer.dims());
+			toasts.check_milestones(&game, &map);
+
+			if game.phase.is_over() && !run_is_in_leaderboard {
+				run_is_in_leaderboard = true;
+				let entry = LeaderboardEntry::now(game.distance_traveled, game.turn_counter);
+				leaderboard_rank = leaderboard.add(entry);
+				if leaderboard_rank.is_some() {
+					if let Err(error) = leaderboard.save(&leaderboard_path) {
+						log::warn!(
+							"failed to save the leaderboard \"{}\" ({error})",
+							leaderboard_path.display()
+						);
+					}
+				}
+			}
+			toasts.update();
+
+			// Continuous camera panning (unless a camera animation is already moving it).
+			// The keyboard has priority over the edge scrolling so that they don't fight.
+			let camera_is_animated = current_animations
+				.iter()
+				.any(|anim| matches!(anim.action, AnimationAction::CameraMoveX { .. }));
+			let mut pan_direction = camera_pan_right as i32 - camera_pan_left as i32;
+			if pan_direction == 0 && edge_scroll && cursor_in_window {
+				let edge_width = 20;
+				let PixelCoords(cursor) = cursor_position;
+				if cursor.x < edge_width {
+					pan_direction = -1;
+				} else if renderer.dims().w - edge_width <= cursor.x {
+					pan_direction = 1;
+				}
+			}
+			if pan_direction != 0 && !camera_is_animated && camera_drag.is_none() {
+				// In tiles per second.
+				let pan_speed = 8.0;
+				map_drawing_config.camera_x = (map_drawing_config.camera_x
+					+ pan_direction as f32 * pan_speed * dt.as_secs_f32())
+				.max(0.0);
+				let side = map_drawing_config.tile_side();
+				map.generate_chunks_until_wider_than(
+					(map_drawing_config.camera_x + 1.0) as i32 + (renderer.dims().w + 1) / side,
+				);
+				if cursor_in_window {
+					let TileCoords(coords) =
+						map_drawing_config.screen_coords_to_tile_coords(cursor_position);
+					hovered_tile_coords = map.grid.dims.contains(coords).then_some(coords);
+				}
+			}
 
 			//std::thread::sleep(Duration::from_secs_f32(0.003));
 
 			// Trigger some enemy alive animations at random.
-			for coords in map.grid.dims.iter() {
-				if let Some(Obj::Enemy { alive_animation, .. }) =
-					&mut map.grid.get_mut(coords).unwrap().obj
-				{
+			for &coords in map.obj_index.enemies.iter() {
+				if let Some(Obj::Enemy { alive_animation, .. }) = &mut map.grid.at_mut(coords).obj {
 					if let Some(anim) = alive_animation {
 						let progress = anim.tp.progress();
 						if progress > 10.0 {
@@ -1459,7 +5023,7 @@ fn main() {
 							// so that there is a kind of cooldown for the animation per enemy.
 							*alive_animation = None;
 						}
-					} else if rand_range(0.0..0.1) < 0.001 {
+					} else if cosmetic_rand_range(0.0..0.1) < 0.001 {
 						*alive_animation = Some(AliveAnimation {
 							tp: TimeProgression::new(Duration::from_secs_f32(0.3)),
 						});
@@ -1469,31 +5033,48 @@ fn main() {
 
 			// Here comes the rendering of the map and interface.
 			renderer.clear();
+			let water_phase = water_animation.looping_progress();
+
+			// The caravan is not on the map while it moves, the trail then stays as it was.
+			if let Some(distance) = map.caradan_path_dist() {
+				caravan_max_path_dist = caravan_max_path_dist.max(distance);
+			}
+
+			map_drawing_config.clamp_camera_x(&map, renderer.dims().w);
 
 			// Drawing the ground of the tiles first so that objects can't ever appear behind ground.
 			for coords in map.grid.dims.iter() {
-				let dst = map_drawing_config.tile_coords_to_screen_rect(coords);
+				let dst = map_drawing_config.tile_coords_to_screen_rect(TileCoords(coords));
 				if dst.right_excluded() < 0 || renderer.dims().w < dst.left() {
 					continue;
 				}
-				map.draw_tile_ground_at(&mut renderer, coords, dst);
+				map.draw_tile_ground_at(&mut renderer, coords, dst, water_phase);
+				if display_caravan_trail
+					&& map
+						.grid
+						.at(coords)
+						.path()
+						.is_some_and(|path| path.distance <= caravan_max_path_dist)
+				{
+					renderer.multiply_rect(dst, Color::rgb_u8(255, 225, 190));
+				}
 			}
 
 			// Draw the selection/hover/selectable rectangles and related stuff.
 			if let Some(coords) = hovered_tile_coords {
 				if selectable_tile_coords.contains(&coords) {
 					let dst = map_drawing_config
-						.tile_coords_to_screen_rect(coords)
+						.tile_coords_to_screen_rect(TileCoords(coords))
 						.add_margin(1);
 					renderer.draw_rect_edge(dst, Color::rgb_u8(0, 100, 255));
 				} else {
-					let dst = map_drawing_config.tile_coords_to_screen_rect(coords);
+					let dst = map_drawing_config.tile_coords_to_screen_rect(TileCoords(coords));
 					renderer.draw_rect_edge(dst, Color::rgb_u8(255, 60, 0));
 				}
 			}
 			if let Some(coords) = selected_tile_coords {
 				let dst = map_drawing_config
-					.tile_coords_to_screen_rect(coords)
+					.tile_coords_to_screen_rect(TileCoords(coords))
 					.add_margin(2);
 				renderer.draw_rect_edge(dst, Color::rgb_u8(255, 255, 80));
 			}
@@ -1502,51 +5083,238 @@ fn main() {
 					continue;
 				}
 				let dst = map_drawing_config
-					.tile_coords_to_screen_rect(*coords)
+					.tile_coords_to_screen_rect(TileCoords(*coords))
 					.add_margin(-1);
 				renderer.draw_rect_edge(dst, Color::rgb_u8(0, 100, 255));
 			}
 
 			// Now the objects that are not in animations.
 			for coords in map.grid.dims.iter() {
-				let dst = map_drawing_config.tile_coords_to_screen_rect(coords);
+				let dst = map_drawing_config.tile_coords_to_screen_rect(TileCoords(coords));
 				if dst.right_excluded() < 0 || renderer.dims().w < dst.left() {
 					continue;
 				}
-				map.draw_tile_obj_at(&mut renderer, coords, dst);
+				map.draw_tile_obj_at(&mut renderer, coords, dst, ScaleMode::Nearest);
+			}
+
+			// The ghost of the tower to place in build mode, tinted green if it can be placed
+			// there and red if it can't, with a line showing its facing.
+			if let InterfaceMode::Building { cursor, facing } = interface_mode {
+				let ghost = tower_type_to_place.new_obj();
+				let dst = map_drawing_config.tile_coords_to_screen_rect(TileCoords(cursor));
+				draw_obj(&mut renderer, &ghost, dst, false, ScaleMode::Nearest);
+				let footprint = ghost.footprint();
+				let footprint_dst = Rect {
+					top_left: dst.top_left,
+					dims: (dst.dims.w * footprint.w, dst.dims.h * footprint.h).into(),
+				};
+				let tint = if can_place_tower(&map, &game, &tower_type_to_place, cursor) {
+					Color::rgba_u8(80, 255, 80, 90)
+				} else {
+					Color::rgba_u8(255, 40, 40, 110)
+				};
+				renderer.draw_rect(footprint_dst, tint);
+				renderer.draw_rect_edge(footprint_dst.add_margin(2), tint);
+				let center = footprint_dst.center();
+				renderer.draw_line(
+					center,
+					center + facing * (footprint_dst.dims.w / 2),
+					Color::WHITE,
+				);
+			}
+
+			// Outline the enemy or tower that is playing, pulsing from white to yellow.
+			if let Some((coords, tp)) = &acting_unit {
+				if matches!(game.phase, Phase::Enemy | Phase::Tower) {
+					let footprint = map
+						.grid
+						.get(*coords)
+						.and_then(|tile| tile.obj.as_ref())
+						.map_or(Dimensions::square(1), Obj::footprint);
+					let dst = map_drawing_config.tile_coords_to_screen_rect(TileCoords(*coords));
+					let dst = Rect {
+						top_left: dst.top_left,
+						dims: (dst.dims.w * footprint.w, dst.dims.h * footprint.h).into(),
+					};
+					let pulse = (tp.looping_progress() * std::f32::consts::TAU).cos() * 0.5 + 0.5;
+					let color = Color::rgb_u8(255, 255, (80.0 + 175.0 * pulse) as u8);
+					renderer.draw_rect_edge(dst.add_margin(2), color);
+				}
+			}
+
+			let player_could_act = game.phase == Phase::Player
+				&& current_animations.is_empty()
+				&& console_input.is_none()
+				&& interface_mode == InterfaceMode::Normal
+				&& queued_action.is_none()
+				&& !end_player_phase_after_animation
+				&& !end_player_phase_right_now;
+			if input_happened.take() || !player_could_act {
+				idle_timer = idle_time_limit.map(TimeProgression::new);
+			}
+			// Idling for too long has the same effect as pressing S.
+			if player_could_act && idle_timer.as_ref().is_some_and(TimeProgression::is_done) {
+				end_player_phase_right_now = true;
+			}
+
+			// Running out of time has the same effect as pressing S.
+			if turn_timer.as_ref().is_some_and(TimeProgression::is_done)
+				&& game.phase == Phase::Player
+				&& current_animations.is_empty()
+				&& !end_player_phase_after_animation
+			{
+				end_player_phase_right_now = true;
+			}
+
+			if game.phase == Phase::Player
+				&& current_animations.is_empty()
+				&& !matches!(interface_mode, InterfaceMode::MovingCaravanAnimation { .. })
+				&& !end_player_phase_after_animation
+				&& !end_player_phase_right_now
+			{
+				match queued_action.take() {
+					None => {},
+					Some(QueuedAction::ClickTile(coords)) => {
+						let tile = map.grid.at(coords).clone();
+						// Place a tower on empty ground (the clicked tile is the top left one
+						// of its footprint).
+						if interface_mode == InterfaceMode::Normal
+							&& start_placing_tower(
+								&map,
+								&mut game,
+								&tower_type_to_place,
+								coords,
+								&mut current_animations,
+							) {
+							audio_player.play_sound_effect(SoundEffect::Place);
+							end_player_phase_after_animation = true;
+						} else if matches!(tile.obj, Some(Obj::Crystal { .. }))
+							&& interface_mode == InterfaceMode::Normal
+						{
+							// Mine the crystal.
+							current_animations.push(Animation {
+								action: AnimationAction::Disappear {
+									obj: map.take_obj(coords).unwrap(),
+									from: coords,
+								},
+								tp: TimeProgression::gameplay(Duration::from_secs_f32(0.05)),
+							});
+							audio_player.play_sound_effect(SoundEffect::Mine);
+							game.crystal_amount += game.balance.mining_yield;
+							game.stats.crystals_mined += 1;
+							end_player_phase_after_animation = true;
+						} else if matches!(tile.obj, Some(Obj::Caravan))
+							&& interface_mode == InterfaceMode::Normal
+						{
+							// Make selectable the tiles on which the caravan can move.
+							// If the path is malformed, then the caravan just can't be moved that way.
+							if let (Some(path_coords), Some(caravan_path_dist)) =
+								(map.path_coords(), map.caradan_path_dist())
+							{
+								interface_mode = InterfaceMode::MovingCaravanChoosingDst;
+								for coords in path_coords {
+									let path = map.grid.at(coords).path().unwrap();
+									if path.distance <= caravan_path_dist {
+										continue;
+									}
+									if !map.grid.at(coords).is_passable_for_caravan() {
+										break;
+									}
+									selectable_tile_coords.push(coords);
+								}
+							}
+						}
+					},
+					Some(QueuedAction::PlaceGhost(coords)) => {
+						// Same as placing with the mouse (the build mode is still on, as leaving it
+						// drops the queued action).
+						let placed = start_placing_tower(
+							&map,
+							&mut game,
+							&tower_type_to_place,
+							coords,
+							&mut current_animations,
+						);
+						if placed {
+							audio_player.play_sound_effect(SoundEffect::Place);
+							end_player_phase_after_animation = true;
+							interface_mode = InterfaceMode::Normal;
+						}
+					},
+					Some(QueuedAction::StepForward) => {
+						if let Some(distance) =
+							start_caravan_step_forward(&mut map, &mut current_animations)
+						{
+							game.distance_traveled = distance;
+							end_player_phase_after_animation = true;
+						}
+					},
+					Some(QueuedAction::ScrollForward) => {
+						current_animations.push(Animation {
+							action: AnimationAction::CameraMoveX {
+								from: map_drawing_config.camera_x,
+								to: map_drawing_config.camera_x + 1.0,
+								zoom: None,
+							},
+							tp: TimeProgression::gameplay(Duration::from_secs_f32(0.05)),
+						});
+						let side = map_drawing_config.tile_side();
+						map.generate_chunks_until_wider_than(
+							(map_drawing_config.camera_x + 1.0) as i32 + (renderer.dims().w + 1) / side,
+						);
+						end_player_phase_after_animation = true;
+					},
+					Some(QueuedAction::SkipTurn) => end_player_phase_right_now = true,
+				}
+			}
+
+			if auto_advance
+				&& !auto_advance_done_this_phase
+				&& game.phase == Phase::Player
+				&& current_animations.is_empty()
+				&& interface_mode == InterfaceMode::Normal
+				&& !end_player_phase_after_animation
+				&& !end_player_phase_right_now
+			{
+				// If the caravan is blocked then the player phase goes on as usual,
+				// so that the player can react.
+				auto_advance_done_this_phase = true;
+				if let Some(distance) = start_caravan_step_forward(&mut map, &mut current_animations) {
+					game.distance_traveled = distance;
+					end_player_phase_after_animation = true;
+				}
 			}
 
-			if let InterfaceMode::MovingCaravanAnimation { remaining_moves } = interface_mode {
+			if let InterfaceMode::MovingCaravanAnimation { remaining_moves, steps_done } =
+				interface_mode
+			{
 				if current_animations.is_empty() {
-					if remaining_moves <= 0 {
+					// A step may have reached the distance goal, then the run is won and the
+					// caravan stops there (with the distance that the victory shows).
+					if remaining_moves <= 0 || game.phase.is_over() {
 						interface_mode = InterfaceMode::Normal;
 					} else {
 						let (caravan_coords, caravan_tile) = map.caravan_coords_and_tile().unwrap();
 						let distance = caravan_tile.path().unwrap().distance;
-						let forward = map
-							.grid
-							.get(caravan_coords)
-							.unwrap()
-							.path()
-							.unwrap()
-							.forward;
+						let forward = map.grid.at(caravan_coords).path().unwrap().forward;
 						current_animations.push(Animation {
 							action: AnimationAction::Move {
-								obj: map
-									.grid
-									.get_mut(caravan_coords)
-									.unwrap()
-									.obj
-									.take()
-									.unwrap(),
+								obj: map.take_obj(caravan_coords).unwrap(),
 								from: caravan_coords,
 								to: caravan_coords + forward,
 							},
-							tp: TimeProgression::new(Duration::from_secs_f32(0.05)),
+							tp: TimeProgression::gameplay(Duration::from_secs_f32(0.05)),
 						});
-						distance_traveled = distance + 1;
-						interface_mode =
-							InterfaceMode::MovingCaravanAnimation { remaining_moves: remaining_moves - 1 };
+						game.distance_traveled = distance + 1;
+						// One step sound every few steps sounds like a rhythm, one per step
+						// would be a buzz (the steps are that quick).
+						if 0 < caravan_step_cadence && steps_done % caravan_step_cadence == 0 {
+							audio_player.play_sound_effect(SoundEffect::CaravanStep);
+						}
+						interface_mode = InterfaceMode::MovingCaravanAnimation {
+							remaining_moves: remaining_moves - 1,
+							steps_done: steps_done + 1,
+						};
 						if remaining_moves == 1 {
 							end_player_phase_after_animation = true;
 						}
@@ -1554,102 +5322,54 @@ fn main() {
 				}
 			}
 
+			// Sounds triggered by the game logic, played once the logic is done for this frame.
+			let mut sounds: Vec<SoundEffect> = vec![];
+			// For the shot inspector, the hovered shot in flight that is the closest to the cursor
+			// (with its squared distance to the cursor).
+			let mut inspected_shot: Option<(i32, Shot)> = None;
+
 			if !current_animations.is_empty() {
 				let mut anim_indices_to_remove: Vec<usize> = vec![];
 				let mut new_anims: Vec<Animation> = vec![];
 				for (anim_index, anim) in current_animations.iter().enumerate() {
 					if anim.tp.is_done() {
-						let duration = anim.tp.duration;
-
 						// The current animation is finished.
 						anim_indices_to_remove.push(anim_index);
-						match &anim.action {
-							AnimationAction::Move { obj, to, .. } => {
-								map.grid.get_mut(*to).unwrap().obj = Some(obj.clone())
-							},
-							AnimationAction::CameraMoveX { to, .. } => map_drawing_config.camera_x = *to,
-							AnimationAction::Appear { obj, to } => {
-								map.grid.get_mut(*to).unwrap().obj = Some(obj.clone())
-							},
-							AnimationAction::Disappear { .. } => {},
-							AnimationAction::Shoot { from, direction, shot } => {
-								let to = *from + *direction;
-								if map.grid.dims.contains(to) {
-									if map.grid.get(to).unwrap().obj.is_some() {
-										map.shot_hits_obj_at(to, shot);
-										audio_player.play_sound_effect(SoundEffect::Hit);
-										match &shot.cascade {
-											ShotCascade::None => {},
-											ShotCascade::Piercing(piercing_shot) => {
-												new_anims.push(Animation {
-													action: AnimationAction::Shoot {
-														from: to,
-														direction: *direction,
-														shot: *(*piercing_shot).clone(),
-													},
-													tp: TimeProgression::new(Duration::from_secs_f32(0.05)),
-												});
-												audio_player.play_sound_effect(SoundEffect::Pew);
-											},
-											ShotCascade::SplitInTwo(side_shots) => {
-												let one_side = CoordsDelta::from((direction.dy, direction.dx));
-												new_anims.push(Animation {
-													action: AnimationAction::Shoot {
-														from: to,
-														direction: one_side,
-														shot: *(*side_shots).clone(),
-													},
-													tp: TimeProgression::new(Duration::from_secs_f32(0.05)),
-												});
-												new_anims.push(Animation {
-													action: AnimationAction::Shoot {
-														from: to,
-														direction: -one_side,
-														shot: *(*side_shots).clone(),
-													},
-													tp: TimeProgression::new(Duration::from_secs_f32(0.05)),
-												});
-												audio_player.play_sound_effect(SoundEffect::Pew);
-											},
-										}
-									} else {
-										new_anims.push(Animation {
-											action: AnimationAction::Shoot {
-												from: to,
-												direction: *direction,
-												shot: shot.clone(),
-											},
-											tp: TimeProgression { start: Instant::now(), duration },
-										});
-									}
-								}
-							},
+						if let AnimationAction::CameraMoveX { to, zoom, .. } = anim.action {
+							map_drawing_config.camera_x = to;
+							if let Some((_, to_zoom)) = zoom {
+								map_drawing_config.zoom = to_zoom;
+							}
 						}
+						new_anims.extend(resolve_animation(&mut map, anim, &mut game, &mut sounds));
 						if end_player_phase_after_animation {
 							end_player_phase_after_animation = false;
 							end_player_phase_right_now = false;
 							selectable_tile_coords.clear();
-							phase = Phase::Enemy;
-							for coords in map.grid.dims.iter() {
-								if let Some(Obj::Enemy { ref mut actions, .. }) =
-									map.grid.get_mut(coords).unwrap().obj
-								{
-									*actions += 1;
-								}
-							}
+							end_player_phase(&mut map, &mut game);
 						}
 					} else {
 						let progress = anim.tp.progress();
 						match &anim.action {
 							AnimationAction::Move { obj, from, to } => {
-								let dst_from = map_drawing_config.tile_coords_to_screen_rect(*from);
-								let dst_to = map_drawing_config.tile_coords_to_screen_rect(*to);
-								let dst = linear_interpolation_rect(progress, dst_from, dst_to);
-								draw_obj(&mut renderer, obj, dst, false);
+								let dst_from =
+									map_drawing_config.tile_coords_to_screen_rect(TileCoords(*from));
+								let dst_to = map_drawing_config.tile_coords_to_screen_rect(TileCoords(*to));
+								let dst = lerp_rect(progress, dst_from, dst_to);
+								draw_obj(&mut renderer, obj, dst, false, ScaleMode::Nearest);
 							},
-							AnimationAction::CameraMoveX { from, to } => {
-								map_drawing_config.camera_x = linear_interpolation(progress, *from, *to);
-								let coords =
+							AnimationAction::CameraMoveX { from, to, zoom } => {
+								// A zoom is eased at both ends, starting and stopping abruptly is jarring.
+								let progress = if zoom.is_some() {
+									quad_in_out(progress)
+								} else {
+									progress
+								};
+								map_drawing_config.camera_x = lerp_f32(progress, *from, *to);
+								if let Some((from_zoom, to_zoom)) = zoom {
+									map_drawing_config.zoom = lerp_f32(progress, *from_zoom, *to_zoom);
+								}
+								let TileCoords(coords) =
 									map_drawing_config.screen_coords_to_tile_coords(cursor_position);
 								if map.grid.dims.contains(coords) {
 									hovered_tile_coords = Some(coords);
@@ -1658,24 +5378,39 @@ fn main() {
 								}
 							},
 							AnimationAction::Appear { obj, to } => {
-								let mut dst = map_drawing_config.tile_coords_to_screen_rect(*to);
-								let side = map_drawing_config.tile_side();
-								dst.top_left.x += ((side / 2) as f32 * (1.0 - progress)) as i32;
-								dst.dims.w = (side as f32 * progress) as i32;
-								dst.top_left.y += ((side / 2) as f32 * (1.0 - progress)) as i32;
-								dst.dims.h = (side as f32 * progress) as i32;
-								draw_obj(&mut renderer, obj, dst, false);
+								let tile_dst =
+									map_drawing_config.tile_coords_to_screen_rect(TileCoords(*to));
+								let side = lerp_i32(back_out(progress), 0, map_drawing_config.tile_side());
+								let dst =
+									Rect::from_center_size(tile_dst.center(), Dimensions::square(side));
+								draw_obj(&mut renderer, obj, dst, false, ScaleMode::Nearest);
 							},
 							AnimationAction::Disappear { obj, from } => {
-								let dst = map_drawing_config.tile_coords_to_screen_rect(*from);
-								draw_obj(&mut renderer, obj, dst, true);
+								let dst = map_drawing_config.tile_coords_to_screen_rect(TileCoords(*from));
+								draw_obj(&mut renderer, obj, dst, true, ScaleMode::Nearest);
 							},
-							AnimationAction::Shoot { from, direction, .. } => {
+							AnimationAction::Shoot { from, direction, shot, .. } => {
 								let to = *from + *direction;
-								let dst_from = map_drawing_config.tile_coords_to_screen_rect(*from);
-								let dst_to = map_drawing_config.tile_coords_to_screen_rect(to);
-								let dst = linear_interpolation_rect(progress, dst_from, dst_to);
+								let dst_from =
+									map_drawing_config.tile_coords_to_screen_rect(TileCoords(*from));
+								let dst_to = map_drawing_config.tile_coords_to_screen_rect(TileCoords(to));
+								let dst = lerp_rect(progress, dst_from, dst_to);
 								draw_shot(&mut renderer, dst);
+								if shot_inspector && dst.contains(cursor_position.0) {
+									let distance = (cursor_position.0 - dst.center()).length_squared();
+									if inspected_shot
+										.as_ref()
+										.is_none_or(|(closest, _)| distance < *closest)
+									{
+										inspected_shot = Some((distance, shot.clone()));
+									}
+								}
+							},
+							AnimationAction::Arc { from, to, .. } => {
+								let dst_from =
+									map_drawing_config.tile_coords_to_screen_rect(TileCoords(*from));
+								let dst_to = map_drawing_config.tile_coords_to_screen_rect(TileCoords(*to));
+								draw_lightning(&mut renderer, dst_from.center(), dst_to.center(), progress);
 							},
 						}
 					}
@@ -1688,237 +5423,130 @@ fn main() {
 				end_player_phase_after_animation = false;
 				end_player_phase_right_now = false;
 				selectable_tile_coords.clear();
-				phase = Phase::Enemy;
-				for coords in map.grid.dims.iter() {
-					if let Some(Obj::Enemy { ref mut actions, .. }) =
-						map.grid.get_mut(coords).unwrap().obj
-					{
-						*actions += 1;
-					}
-				}
+				end_player_phase(&mut map, &mut game);
 			} else {
-				// There might be something to do now.
-				if phase == Phase::Enemy {
-					// The enemies shall play now.
-					// We make the enemies closer to the caravan play first so that they don't
-					// bump into each other too much.
-					// Closer to the caravan here means on a path tile with the smallest distance.
-					// First we find the coords of the closest enemy (if any).
-					let mut min_path_dist_and_coords: Option<(i32, Coords)> = None;
-					for coords in map.grid.dims.iter() {
-						let tile = map.grid.get(coords).unwrap();
-						if let Some(Obj::Enemy { actions, .. }) = tile.obj {
-							if actions >= 1 {
-								if let Some(Path { distance, .. }) = tile.path() {
-									if min_path_dist_and_coords.is_none()
-										|| min_path_dist_and_coords
-											.is_some_and(|(dist_min, _)| *distance < dist_min)
-									{
-										min_path_dist_and_coords = Some((*distance, coords));
-									}
-								}
-							}
-						}
-					}
-					if let Some((_, coords)) = min_path_dist_and_coords {
-						// Found the closest enemy that hasn't played yet. This enemy plays now.
-						// But before playing, we handle fire effect (if any).
-						if let Obj::Enemy { actions, ref mut fire, .. } =
-							map.grid.get_mut(coords).unwrap().obj.as_mut().unwrap()
-						{
-							if *actions >= 1 && *fire >= 1 {
-								*fire -= 1;
-								map.inflict_damage_to_obj_at(coords, 1);
-								audio_player.play_sound_effect(SoundEffect::Hit);
-							}
-						}
-						let tile = map.grid.get_mut(coords).unwrap();
-						if let Some(Obj::Enemy { ref mut actions, .. }) = tile.obj {
-							if *actions >= 1 {
-								// Now the enemy really plays.
-								*actions -= 1;
-								let backward = if let Some(Path { backward, .. }) = tile.path() {
-									*backward
-								} else {
-									panic!("enemy not on a path")
-								};
-								let dst_coords = coords + backward;
-								if map.grid.get(dst_coords).is_some_and(|dst_tile| {
-									dst_tile.obj.is_none()
-										|| dst_tile
-											.obj
-											.as_ref()
-											.is_some_and(|obj| matches!(obj, Obj::Caravan | Obj::Tower { .. }))
-								}) {
-									current_animations.push(Animation {
-										action: AnimationAction::Move {
-											obj: map.grid.get_mut(coords).unwrap().obj.take().unwrap(),
-											from: coords,
-											to: dst_coords,
-										},
-										tp: TimeProgression::new(Duration::from_secs_f32(0.05)),
-									});
-									audio_player.play_sound_effect(SoundEffect::Step);
-								}
-							}
-						}
-					} else {
-						// No enemies left to play.
-						// We finish some enemy buisness and get to next phase.
-
-						// Enemy spawn
+				loop {
+					// There might be something to do now.
+					let phase_before = game.phase;
+					let acting_unit_coords =
+						phase_step(&mut map, &mut game, &mut current_animations, &mut sounds);
+					acting_unit = acting_unit_coords
+						.map(|coords| (coords, TimeProgression::new(Duration::from_secs_f32(0.4))));
+					if phase_before == Phase::Enemy && game.phase == Phase::Tower {
+						// Enemies spawn just out of view, so this part depends on the screen.
 						let tile_side = map_drawing_config.tile_side();
-						while map.grid.dims.w * tile_side
-							<= (map_drawing_config.camera_x + 1.0) as i32 * tile_side
-								+ renderer.dims().w + 1
-						{
-							map.generate_chunk_on_the_right();
+						map.generate_chunks_until_wider_than(
+							(map_drawing_config.camera_x + 1.0) as i32
+								+ (renderer.dims().w + 1) / tile_side,
+						);
+						// At the end of a finite world, they spawn on its last column.
+						let spawn_x = ((map_drawing_config.camera_x + 1.0) as i32
+							+ renderer.dims().w / tile_side)
+							.min(map.grid.dims.w - 1);
+						// An enemy that can't spawn now (because of the crowd) tries again
+						// at the end of the next enemy phase, so that the waves keep their size.
+						if let Some(enemy) = game.next_enemy_spawn.take() {
+							game.next_enemy_spawn =
+								map.spawn_enemy_on_column(spawn_x, enemy, &game.balance);
 						}
-						let spawn_coords: Coords = 'spawn_coords: {
-							let right =
-								(map_drawing_config.camera_x + 1.0) as i32 + renderer.dims().w / tile_side;
-							for y in 0..map.grid.dims.h {
-								if map.grid.get((right, y).into()).unwrap().has_path() {
-									break 'spawn_coords (right, y).into();
-								}
-							}
-							panic!("no path one some column ?");
-						};
-						let spawn_tile = map.grid.get_mut(spawn_coords).unwrap();
-						if spawn_tile.obj.is_none() && rand_range(0.0..1.0) < 0.4 {
-							let rand = rand_range(0.0..1.0);
-							let hp = if rand < 0.1 {
-								12
-							} else if rand < 0.3 {
-								10
-							} else {
-								8
-							};
-							spawn_tile.obj = Some(Obj::Enemy {
-								actions: 0,
-								hp,
-								fire: 0,
-								alive_animation: None,
-								colored_animation: None,
-								variant: Enemy::Basic,
-							});
+						if game.next_enemy_spawn.is_none() {
+							game.next_enemy_spawn = game.waves.roll_next_spawn(&game.balance);
 						}
+					} else if phase_before == Phase::Tower && game.phase == Phase::Player {
+						auto_advance_done_this_phase = false;
+						turn_timer = turn_time_limit.map(TimeProgression::new);
+					}
 
-						// Get to next phase
-						phase = Phase::Tower;
-						for coords in map.grid.dims.iter() {
-							if let Some(Obj::Tower { ref mut actions, .. }) =
-								map.grid.get_mut(coords).unwrap().obj
-							{
-								*actions += 1;
-							}
-						}
+					if !fast_forward || !(game.phase == Phase::Enemy || game.phase == Phase::Tower) {
+						break;
 					}
-				} else if phase == Phase::Tower {
-					// Towers gonna shoot!
-					let mut found_an_tower_to_make_play = false;
-					for coords in map.grid.dims.iter_left_to_right() {
-						// Before playing, we handle fire effect (if any).
-						if let Some(Obj::Tower { actions, ref mut fire, .. }) =
-							map.grid.get_mut(coords).unwrap().obj.as_mut()
-						{
-							if *actions >= 1 && *fire >= 1 {
-								*fire -= 1;
-								map.inflict_damage_to_obj_at(coords, 1);
-								audio_player.play_sound_effect(SoundEffect::Hit);
-							}
-						}
-						let tile = map.grid.get_mut(coords).unwrap();
-						if let Some(Obj::Tower { ref mut actions, ref variant, .. }) = tile.obj {
-							if *actions >= 1 {
-								*actions -= 1;
-								let shot = variant.shot();
-
-								// Towers will shoot at the enemy that they see that is the closest to
-								// the caravan, it seems like a nice default heuristic.
-								let mut min_path_dist_and_dir: Option<(i32, CoordsDelta)> = None;
-								for direction in CoordsDelta::iter_4_directions() {
-									let mut view_coords = coords + direction;
-									loop {
-										let tile = map.grid.get(view_coords);
-										if tile.is_none() {
-											break;
-										}
-										let tile = tile.unwrap();
-										if tile.has_enemy() {
-											if let Some(Path { distance, .. }) = tile.path() {
-												if min_path_dist_and_dir.is_none()
-													|| min_path_dist_and_dir
-														.is_some_and(|(dist_min, _)| *distance < dist_min)
-												{
-													min_path_dist_and_dir = Some((*distance, direction));
-													break;
-												}
-											}
-										}
-										if tile.obj.is_some() {
-											break;
-										}
-										view_coords += direction;
-									}
-								}
+					// Fast-forwarding, the animations end right away and in the order they
+					// were started, so that everything still happens as it would have,
+					// up until the next player phase.
+					while !current_animations.is_empty() {
+						let anim = current_animations.remove(0);
+						let new_anims = resolve_animation(&mut map, &anim, &mut game, &mut sounds);
+						current_animations.extend(new_anims);
+					}
+				}
+				if fast_forward {
+					// A whole phase worth of sounds all at once would just be noise.
+					sounds.clear();
+				}
+			}
 
-								if let Some((_, direction)) = min_path_dist_and_dir {
-									// Shoot!
-									// The shot here is a test for now,
-									// the basic tower isn't supposed to shoot shots like these.
-									current_animations.push(Animation {
-										action: AnimationAction::Shoot { from: coords, direction, shot },
-										tp: TimeProgression::new(Duration::from_secs_f32(0.05)),
-									});
-									audio_player.play_sound_effect(SoundEffect::Pew);
-								}
+			for sound in sounds {
+				audio_player.play_sound_effect(sound);
+			}
 
-								found_an_tower_to_make_play = true;
-								break;
-							}
-						}
+			// The day/night tint applies to the map only, the interface is drawn after it.
+			// It is lighter around the light sources, tile by tile.
+			let tint = day_night_tint(game.turn_counter);
+			if tint.raw() != Color::WHITE.raw() {
+				let mut lights: Vec<LightSource> = map
+					.obj_index
+					.towers
+					.iter()
+					.copied()
+					.chain(map.caravan_coords())
+					.filter_map(|coords| {
+						let obj = map.grid.at(coords).obj.as_ref()?;
+						LightSource::of_obj(obj, (coords.x as f32, coords.y as f32))
+					})
+					.collect();
+				// The objects that are moving (such as the caravan) carry their light along.
+				for anim in current_animations.iter() {
+					if let AnimationAction::Move { obj, from, to } = &anim.action {
+						let progress = anim.tp.progress().min(1.0);
+						let x = lerp_f32(progress, from.x as f32, to.x as f32);
+						let y = lerp_f32(progress, from.y as f32, to.y as f32);
+						lights.extend(LightSource::of_obj(obj, (x, y)));
 					}
-					if !found_an_tower_to_make_play {
-						let the_caravan_is_still_there = 'search_the_caravan: {
-							for coords in map.grid.dims.iter() {
-								if map.grid.get(coords).unwrap().has_caravan() {
-									break 'search_the_caravan true;
-								}
-							}
-							false
-						};
-						if the_caravan_is_still_there {
-							phase = Phase::Player;
-							turn_counter += 1;
-						} else {
-							phase = Phase::GameOver;
-						}
+				}
+				// Objects on the top row can stick out above the map by up to one tile,
+				// that row above the map gets the light of the top row.
+				let screen_w = renderer.dims().w;
+				for x in 0..map.grid.dims.w {
+					let column_dst =
+						map_drawing_config.tile_coords_to_screen_rect(TileCoords((x, 0).into()));
+					if column_dst.right_excluded() < 0 || screen_w < column_dst.left() {
+						continue;
+					}
+					for y in -1..map.grid.dims.h {
+						let dst =
+							map_drawing_config.tile_coords_to_screen_rect(TileCoords((x, y).into()));
+						let light = light_level(&lights, (x, y.max(0)).into());
+						renderer.multiply_rect(dst, lerp_color(light, tint, Color::WHITE));
 					}
 				}
 			}
 
+			weather_particles.draw(&mut renderer);
+
 			if display_path_dist {
 				for coords in map.grid.dims.iter() {
-					let dst = map_drawing_config.tile_coords_to_screen_rect(coords);
+					let dst = map_drawing_config.tile_coords_to_screen_rect(TileCoords(coords));
 					if dst.right_excluded() < 0 || renderer.dims().w < dst.left() {
 						continue;
 					}
-					let distance = if let Ground::Path(Path { distance, .. }) =
-						map.grid.get(coords).unwrap().ground
-					{
-						distance
+					let distance = if let Some(Path { distance, .. }) = map.grid.at(coords).path() {
+						*distance
 					} else {
 						continue;
 					};
-					let center = dst.top_left + CoordsDelta::from(dst.dims) / 2;
+					let center = dst.center();
 					Font {
 						size_factor: 3,
 						horizontal_spacing: 2,
 						space_width: 7,
 						foreground: Color::rgb_u8(80, 255, 255),
 						background: Some(Color::BLACK),
+						outline: None,
+						shadow: None,
 						margins: (3, 3).into(),
+						strict: false,
+						capital_size_factor: None,
+						monospace_width: None,
 					}
 					.draw_text_line(
 						&mut renderer,
@@ -1930,21 +5558,188 @@ fn main() {
 				}
 			}
 
+			if display_debug_grid {
+				// Tile coordinates in the top left corner of every tile.
+				let font_coords = Font {
+					size_factor: 1,
+					horizontal_spacing: 1,
+					space_width: 3,
+					foreground: Color::rgb_u8(255, 255, 80),
+					background: Some(Color::BLACK),
+					outline: None,
+					shadow: None,
+					margins: (1, 1).into(),
+					strict: false,
+					capital_size_factor: None,
+					monospace_width: None,
+				};
+				for coords in map.grid.dims.iter() {
+					let dst = map_drawing_config.tile_coords_to_screen_rect(TileCoords(coords));
+					if dst.right_excluded() < 0 || renderer.dims().w < dst.left() {
+						continue;
+					}
+					font_coords
+						.draw_text_line(
+							&mut renderer,
+							&format!("{},{}", coords.x, coords.y),
+							dst.top_left + CoordsDelta::from((2, 2)),
+							PinPoint::TOP_LEFT,
+						)
+						.unwrap();
+				}
+				// Chunk boundaries (two pixels wide lines so that they can be seen), with the index
+				// of the chunk that starts there written down along the line.
+				let font_chunk = Font { foreground: Color::rgb_u8(255, 0, 255), ..font_coords };
+				for x in (0..=map.grid.dims.w).step_by(map.chunk_dims.w as usize) {
+					let top = map_drawing_config.tile_coords_to_screen_rect(TileCoords((x, 0).into()));
+					let bottom = map_drawing_config
+						.tile_coords_to_screen_rect(TileCoords((x, map.grid.dims.h - 1).into()));
+					for dx in [-1, 0] {
+						renderer.draw_line(
+							top.top_left + CoordsDelta::from((dx, 0)),
+							(bottom.left() + dx, bottom.bottom_excluded() - 1).into(),
+							Color::rgb_u8(255, 0, 255),
+						);
+					}
+					if x < map.grid.dims.w {
+						font_chunk
+							.draw_text_column(
+								&mut renderer,
+								&format!("chunk {}", x / map.chunk_dims.w),
+								(bottom.left() + 1, bottom.bottom_excluded() - 2).into(),
+								PinPoint::BOTTOM_LEFT,
+							)
+							.unwrap();
+					}
+				}
+			}
+
+			// Preview of the shot that the selected tower would fire in the next tower phase
+			// (recomputed every frame so that it follows the enemies as they move).
+			if game.phase == Phase::Player {
+				let selected_tower = selected_tile_coords.filter(|&coords| {
+					map.grid
+						.get(coords)
+						.is_some_and(|tile| matches!(tile.obj, Some(Obj::Tower { .. })))
+				});
+				// Towers only target enemies that their shots would reach, so there is a hit.
+				let hit_coords = selected_tower.and_then(|coords| {
+					let direction = map.tower_target_direction(coords)?;
+					map.shot_first_hit(coords, direction, Team::Player)
+				});
+				if let (Some(coords), Some(hit_coords)) = (selected_tower, hit_coords) {
+					let color = Color::rgba_u8(255, 255, 80, 180);
+					let tower_dst = map_drawing_config.tile_coords_to_screen_rect(TileCoords(coords));
+					let hit_dst = map_drawing_config.tile_coords_to_screen_rect(TileCoords(hit_coords));
+					renderer.draw_line(tower_dst.center(), hit_dst.center(), color);
+					let marker_dst = hit_dst.add_margin(-map_drawing_config.tile_side() / 4);
+					renderer.draw_rect_edge(marker_dst, color);
+					renderer.draw_rect_edge(marker_dst.add_margin(-1), color);
+				}
+			}
+
+			// Fog past the right edge of the generated world, fading in over the last half column,
+			// so that the void there (seen at the end of a finite world or while the camera moves
+			// faster than the generation) looks like a part of the world.
+			{
+				let fog = Color::rgb_u8(170, 180, 205);
+				let last_column_dst = map_drawing_config
+					.tile_coords_to_screen_rect(TileCoords((map.grid.dims.w - 1, 0).into()));
+				let map_right = last_column_dst.right_excluded();
+				let screen_w = renderer.dims().w;
+				if map_right < screen_w {
+					let top = map_drawing_config.top_left.y;
+					let height = map.grid.dims.h * map_drawing_config.tile_side();
+					let gradient_w = map_drawing_config.tile_side() / 2;
+					for i in 0..gradient_w {
+						let alpha = (255 * (i + 1) / (gradient_w + 1)) as u8;
+						renderer.draw_rect(
+							Rect::xywh(map_right - gradient_w + i, top, 1, height),
+							Color::rgba_u8(fog.r(), fog.g(), fog.b(), alpha),
+						);
+					}
+					renderer.draw_rect(
+						Rect::xywh(map_right, top, screen_w - map_right, height),
+						fog,
+					);
+				}
+			}
+
+			// The aura of support towers reaches halfway into the tiles next to them,
+			// the towers there are supported (see `Map::is_supported`).
+			for &coords in map.obj_index.towers.iter() {
+				if let Some(Obj::Tower { variant: Tower::Support, .. }) = map.grid.at(coords).obj {
+					let dst = map_drawing_config.tile_coords_to_screen_rect(TileCoords(coords));
+					let ring_dst = dst.add_margin(map_drawing_config.tile_side() / 2);
+					renderer.draw_rect_edge(ring_dst, SUPPORT_AURA_COLOR);
+					renderer.draw_rect_edge(ring_dst.add_margin(-1), SUPPORT_AURA_COLOR);
+				}
+			}
+
+			if display_enemy_moves && game.phase == Phase::Player {
+				// Enemies follow the path backward, and get one more action when the player
+				// phase ends (they may still be blocked by what is in their way by then).
+				for &coords in map.obj_index.enemies.iter() {
+					let tile = map.grid.at(coords);
+					if let (Some(Obj::Enemy { actions, .. }), Some(path)) = (&tile.obj, tile.path()) {
+						let dst = map_drawing_config.tile_coords_to_screen_rect(TileCoords(coords));
+						draw_enemy_move_arrow(&mut renderer, dst, path.backward, actions + 1);
+					}
+				}
+			}
+
+			if display_spawn_indicator && game.next_enemy_spawn.is_some() && !game.phase.is_over() {
+				// A small arrow on the right side of the window, at the height of the path
+				// where the enemy will spawn (the same column as in the spawning code).
+				let tile_side = map_drawing_config.tile_side();
+				let spawn_x = ((map_drawing_config.camera_x + 1.0) as i32
+					+ renderer.dims().w / tile_side)
+					.min(map.grid.dims.w - 1);
+				if let Some(coords) = map.free_spawn_coords_on_column(spawn_x, &game.balance) {
+					let y = map_drawing_config
+						.tile_coords_to_screen_rect(TileCoords(coords))
+						.center()
+						.y;
+					let right = renderer.dims().w - 4;
+					let size = 12;
+					for i in 0..size {
+						let x = right - size + i;
+						renderer.draw_line(
+							(x, y - i / 2).into(),
+							(x, y + i / 2).into(),
+							Color::rgba_u8(255, 90, 90, 160),
+						);
+					}
+				}
+			}
+
+			// The shadow keeps the text readable over the map.
 			let font_white_3 = Font {
 				size_factor: 3,
 				horizontal_spacing: 2,
 				space_width: 7,
 				foreground: Color::WHITE,
 				background: None,
+				outline: None,
+				shadow: Some(((3, 3).into(), Color::rgba_u8(0, 0, 0, 150))),
 				margins: (0, 0).into(),
+				strict: false,
+				capital_size_factor: None,
+				monospace_width: None,
 			};
+			// For numbers that change often.
+			let font_white_3_monospace = Font { monospace_width: Some(3 * 3 + 2), ..font_white_3 };
+
+			let hud = HudLayout::new(renderer.dims(), 10);
+			// For the debug stuff that sticks to the window borders.
+			let hud_no_padding = HudLayout::new(renderer.dims(), 0);
 
 			{
-				let text_rect = font_white_3
+				let text_rect = font_white_3_monospace
 					.draw_text_line(
 						&mut renderer,
-						&format!("{crystal_amount}"),
-						(10, 30).into(),
+						&format!("{}", game.crystal_amount),
+						hud.point(PinPoint::TOP_LEFT, (0, 20).into()),
 						PinPoint::TOP_LEFT,
 					)
 					.unwrap();
@@ -1959,60 +5754,209 @@ fn main() {
 					Rect::xywh(1, 24, 6, 6),
 					DrawSpriteEffects::none(),
 				);
+				// The tower that would be placed (it can be changed with T) and its price.
+				font_white_3
+					.draw_text_line(
+						&mut renderer,
+						&format!(
+							"{} costs {}",
+							tower_type_to_place.name(),
+							game.balance.tower_price(&tower_type_to_place)
+						),
+						(crystal_symbol_dst.right_excluded() + 20, text_rect.top()).into(),
+						PinPoint::TOP_LEFT,
+					)
+					.unwrap();
+			}
+
+			font_white_3
+				.draw_text_line(
+					&mut renderer,
+					&format!("turn {}", game.turn_counter),
+					hud.point(PinPoint::TOP_LEFT, (0, 50).into()),
+					PinPoint::TOP_LEFT,
+				)
+				.unwrap();
+			font_white_3
+				.draw_text_line(
+					&mut renderer,
+					&format!("traveled {} tiles", game.distance_traveled),
+					hud.point(PinPoint::TOP_LEFT, (0, 70).into()),
+					PinPoint::TOP_LEFT,
+				)
+				.unwrap();
+			if edge_scroll {
+				font_white_3
+					.draw_text_line(
+						&mut renderer,
+						"edge scroll",
+						hud.point(PinPoint::TOP_RIGHT, (0, 100).into()),
+						PinPoint::TOP_RIGHT,
+					)
+					.unwrap();
+			}
+			if AnimationClock::game_speed() != GameSpeed::Normal {
+				font_white_3
+					.draw_text_line(
+						&mut renderer,
+						&format!("speed: {}", AnimationClock::game_speed().name()),
+						hud.point(PinPoint::TOP_RIGHT, (0, 140).into()),
+						PinPoint::TOP_RIGHT,
+					)
+					.unwrap();
+			}
+			if game.auto_mining {
+				font_white_3
+					.draw_text_line(
+						&mut renderer,
+						"auto-mining",
+						hud.point(PinPoint::TOP_RIGHT, (0, 120).into()),
+						PinPoint::TOP_RIGHT,
+					)
+					.unwrap();
+			}
+			if auto_advance {
+				font_white_3
+					.draw_text_line(
+						&mut renderer,
+						"auto-advance",
+						hud.point(PinPoint::TOP_LEFT, (0, 135).into()),
+						PinPoint::TOP_LEFT,
+					)
+					.unwrap();
+			}
+			if display_debug_grid {
+				let chunk_count = map.grid.dims.w / map.chunk_dims.w;
+				font_white_3
+					.draw_text_line(
+						&mut renderer,
+						&format!("grid width {} ({chunk_count} chunks)", map.grid.dims.w),
+						hud.point(PinPoint::TOP_LEFT, (0, 155).into()),
+						PinPoint::TOP_LEFT,
+					)
+					.unwrap();
 			}
 
-			font_white_3
-				.draw_text_line(
-					&mut renderer,
-					&format!("turn {turn_counter}"),
-					(10, 60).into(),
-					PinPoint::TOP_LEFT,
-				)
-				.unwrap();
-			font_white_3
-				.draw_text_line(
-					&mut renderer,
-					&format!("traveled {distance_traveled} tiles"),
-					(10, 80).into(),
-					PinPoint::TOP_LEFT,
-				)
-				.unwrap();
-
-			if phase != Phase::GameOver {
+			if !game.phase.is_over() {
 				font_white_3
 					.draw_text_line(
 						&mut renderer,
-						match phase {
+						match game.phase {
 							Phase::Player => match interface_mode {
 								InterfaceMode::MovingCaravanChoosingDst => {
 									"player phase: moving the caravan"
 								},
+								InterfaceMode::Building { .. } => "player phase: building",
 								_ => "player phase",
 							},
 							Phase::Enemy => "enemy phase",
 							Phase::Tower => "tower phase",
 							_ => panic!("should not be here then"),
 						},
-						(10, 110).into(),
+						hud.point(PinPoint::TOP_LEFT, (0, 100).into()),
 						PinPoint::TOP_LEFT,
 					)
 					.unwrap();
 			} else {
+				let (title, title_color) = if game.phase == Phase::Victory {
+					("victory ^_^", Color::rgb_u8(80, 255, 80))
+				} else {
+					("game over >_<", Color::rgb_u8(255, 0, 0))
+				};
 				Font {
 					size_factor: 6,
 					horizontal_spacing: 4,
 					space_width: 15,
-					foreground: Color::rgb_u8(255, 0, 0),
+					foreground: title_color,
 					background: None,
+					outline: Some(Color::BLACK),
+					shadow: None,
 					margins: (0, 0).into(),
+					strict: false,
+					capital_size_factor: None,
+					monospace_width: None,
 				}
 				.draw_text_line(
 					&mut renderer,
-					"game over >_<",
-					(10, 110).into(),
+					title,
+					hud.point(PinPoint::TOP_LEFT, (0, 100).into()),
 					PinPoint::TOP_LEFT,
 				)
 				.unwrap();
+
+				// The wrap-up of the run, under the big game over text.
+				let stats = &game.stats;
+				let outcome_line = if game.phase == Phase::Victory {
+					format!(
+						"made it {} tiles in {} turns",
+						game.distance_traveled, game.turn_counter
+					)
+				} else {
+					format!("lost after {} turns", game.turn_counter)
+				};
+				let stat_lines = [
+					outcome_line,
+					format!("enemies killed: {}", stats.enemies_killed),
+					format!("damage dealt: {}", stats.damage_dealt),
+					format!("crystals mined: {}", stats.crystals_mined),
+					format!("towers placed: {}", stats.towers_placed),
+					format!("towers lost: {}", stats.towers_lost),
+				];
+				for (i, line) in stat_lines.iter().enumerate() {
+					font_white_3
+						.draw_text_line(
+							&mut renderer,
+							line,
+							hud.point(PinPoint::TOP_LEFT, (0, 185 + 20 * i as i32).into()),
+							PinPoint::TOP_LEFT,
+						)
+						.unwrap();
+				}
+
+				font_white_3
+					.draw_text_line(
+						&mut renderer,
+						&format!("press {:?} for a new run", keys.new_run),
+						hud.point(
+							PinPoint::TOP_LEFT,
+							(0, 185 + 20 * stat_lines.len() as i32).into(),
+						),
+						PinPoint::TOP_LEFT,
+					)
+					.unwrap();
+
+				// The top of the leaderboard, under the stats.
+				let leaderboard_lines = std::iter::once("best runs:".to_string()).chain(
+					leaderboard
+						.entries()
+						.iter()
+						.take(5)
+						.enumerate()
+						.map(|(rank, entry)| {
+							let this_run = if leaderboard_rank == Some(rank) {
+								" <"
+							} else {
+								""
+							};
+							format!(
+								"{}. distance {} in {} turns on {}{this_run}",
+								rank + 1,
+								entry.distance_traveled,
+								entry.turn_count,
+								entry.date(),
+							)
+						}),
+				);
+				for (i, line) in leaderboard_lines.enumerate() {
+					font_white_3
+						.draw_text_line(
+							&mut renderer,
+							&line,
+							hud.point(PinPoint::TOP_LEFT, (0, 300 + 20 * i as i32).into()),
+							PinPoint::TOP_LEFT,
+						)
+						.unwrap();
+				}
 			}
 
 			let map_bottom =
@@ -2020,35 +5964,37 @@ fn main() {
 
 			let coords_to_display = hovered_tile_coords.or(selected_tile_coords);
 			if let Some(coords) = coords_to_display {
-				let tile = map.grid.get(coords).unwrap();
-				let dst = Rect::xywh(10, map_bottom + 10, 8 * 8 * 2, 8 * 8 * 2);
-				map.draw_tile_ground_at(&mut renderer, coords, dst);
-				map.draw_tile_obj_at(&mut renderer, coords, dst);
-				let obj_name = tile.obj.as_ref().map(|obj| match obj {
-					Obj::Caravan => "caravan",
-					Obj::Enemy { variant, .. } => match variant {
-						Enemy::Basic => "basic enemy",
-					},
-					Obj::Rock { .. } => "rock",
-					Obj::Tower { variant, .. } => match variant {
-						Tower::Basic => "basic tower",
-						Tower::Pink => "pink tower",
-						Tower::Blue => "blue tower",
-					},
-					Obj::Tree => "tree",
-					Obj::Crystal => "crystal",
-				});
+				let tile = map.grid.at(coords);
+				// Right under the map if there is room for it, else in the bottom left corner.
+				let bottom_left_dst = hud.rect(
+					PinPoint::BOTTOM_LEFT,
+					(0, 0).into(),
+					Dimensions::square(8 * 8 * 2),
+				);
+				let dst = if map_bottom + 10 < bottom_left_dst.top() {
+					Rect {
+						top_left: (bottom_left_dst.left(), map_bottom + 10).into(),
+						..bottom_left_dst
+					}
+				} else {
+					bottom_left_dst
+				};
+				let text_left = dst.right_excluded() + 10;
+				map.draw_tile_ground_at(&mut renderer, coords, dst, water_phase);
+				map.draw_tile_obj_at(&mut renderer, coords, dst, ScaleMode::Bilinear);
+				let obj_name = tile.obj.as_ref().map(Obj::name);
 				let obj_hp = tile.obj.as_ref().and_then(|obj| obj.hp());
 				let ground_name = match tile.ground {
 					Ground::Grass { .. } => "grass",
 					Ground::Path(_) => "path",
 					Ground::Water => "water",
+					Ground::Bridge(_) => "bridge",
 				};
 				font_white_3
 					.draw_text_line(
 						&mut renderer,
 						ground_name,
-						(10 + 8 * 8 * 2 + 10, map_bottom + 10).into(),
+						(text_left, dst.top()).into(),
 						PinPoint::TOP_LEFT,
 					)
 					.unwrap();
@@ -2057,7 +6003,7 @@ fn main() {
 						.draw_text_line(
 							&mut renderer,
 							obj_name,
-							(10 + 8 * 8 * 2 + 10, map_bottom + 10 + 20).into(),
+							(text_left, dst.top() + 20).into(),
 							PinPoint::TOP_LEFT,
 						)
 						.unwrap();
@@ -2067,28 +6013,132 @@ fn main() {
 						.draw_text_line(
 							&mut renderer,
 							&format!("hp: {obj_hp}"),
-							(10 + 8 * 8 * 2 + 10, map_bottom + 10 + 20 * 2).into(),
+							(text_left, dst.top() + 20 * 2).into(),
 							PinPoint::TOP_LEFT,
 						)
 						.unwrap();
 				}
 			}
 
-			Font {
+			if !game.phase.is_over() {
+				// The enemy that will spawn next is part of the wave.
+				let enemies_left =
+					game.waves.enemies_left_in_wave + game.next_enemy_spawn.is_some() as i32;
+				let text = if enemies_left > 0 {
+					let enemies = if enemies_left == 1 {
+						"enemy"
+					} else {
+						"enemies"
+					};
+					format!(
+						"wave {}: {enemies_left} {enemies} to come",
+						game.waves.wave_count
+					)
+				} else {
+					// The wave is rolled at the end of the enemy phase where the countdown is
+					// already at zero, and its first enemy spawns at the end of the next one.
+					let turns = game.waves.turns_before_next_wave + 1;
+					if turns == 1 {
+						"next wave next turn".to_string()
+					} else {
+						format!("next wave in {turns} turns")
+					}
+				};
+				font_white_3
+					.draw_text_line(
+						&mut renderer,
+						&text,
+						hud.point(PinPoint::TOP_CENTER, (0, 60).into()),
+						PinPoint::TOP_CENTER,
+					)
+					.unwrap();
+			}
+
+			if let Some(turn_timer) = &turn_timer {
+				if game.phase == Phase::Player {
+					// The bar empties as the time runs out.
+					let dst = hud.rect(PinPoint::TOP_CENTER, (0, 40).into(), (200, 10).into());
+					let remaining = 1.0 - turn_timer.progress().min(1.0);
+					renderer.draw_rect(dst, Color::rgb_u8(60, 60, 60));
+					renderer.draw_rect(
+						Rect {
+							dims: ((dst.dims.w as f32 * remaining) as i32, dst.dims.h).into(),
+							..dst
+						},
+						Color::rgb_u8(255, 200, 40),
+					);
+					renderer.draw_rect_edge(dst, Color::WHITE);
+				}
+			}
+
+			// The last seconds of the idle countdown are shown, so that the end of the player phase
+			// does not come as a surprise.
+			if let (Some(idle_timer), Some(idle_time_limit)) = (&idle_timer, idle_time_limit) {
+				let remaining = idle_time_limit.as_secs_f32() * (1.0 - idle_timer.progress());
+				if game.phase == Phase::Player && 0.0 < remaining && remaining <= 3.0 {
+					font_white_3
+						.draw_text_line(
+							&mut renderer,
+							&format!("idle, the turn ends in {}", remaining.ceil()),
+							hud.point(PinPoint::TOP_CENTER, (0, 100).into()),
+							PinPoint::TOP_CENTER,
+						)
+						.unwrap();
+				}
+			}
+
+			toasts.draw(&mut renderer, &hud);
+			if let Some(console_input) = &console_input {
+				console_input.draw(&mut renderer, &hud);
+			}
+
+			if let Some((_, shot)) = &inspected_shot {
+				let mut lines = vec!["shot in flight".to_string()];
+				lines.extend(shot.inspector_lines());
+				draw_tooltip(&mut renderer, cursor_position.0, &lines);
+			} else if let Some(coords) = hovered_tile_coords {
+				let lines = obj_tooltip_lines(&map, coords, &game.balance);
+				if let Some(lines) = lines {
+					draw_tooltip(&mut renderer, cursor_position.0, &lines);
+				}
+			}
+
+			let font_debug = Font {
 				size_factor: 2,
 				horizontal_spacing: 2,
 				space_width: 7,
 				foreground: Color::WHITE,
 				background: Some(Color::BLACK),
+				outline: None,
+				shadow: None,
 				margins: (3, 3).into(),
+				strict: false,
+				capital_size_factor: None,
+				monospace_width: None,
+			};
+			font_debug
+				.draw_text_line(
+					&mut renderer,
+					&format!("fps: {fps}"),
+					hud_no_padding.point(PinPoint::TOP_LEFT, (0, 0).into()),
+					PinPoint::TOP_LEFT,
+				)
+				.unwrap();
+			if display_frame_time_graph {
+				let dst = hud.rect(PinPoint::TOP_RIGHT, (0, 20).into(), (240, 60).into());
+				frame_time_history.draw(&mut renderer, dst);
+			}
+			if !audio_player.is_available() {
+				let top_right = hud_no_padding.point(PinPoint::TOP_RIGHT, (0, 0).into());
+				font_debug
+					.draw_text_line(
+						&mut renderer,
+						"audio disabled",
+						top_right,
+						PinPoint::TOP_RIGHT,
+					)
+					.unwrap();
 			}
-			.draw_text_line(
-				&mut renderer,
-				&format!("fps: {fps}"),
-				(0, 0).into(),
-				PinPoint::TOP_LEFT,
-			)
-			.unwrap();
 
 			window.request_redraw();
 		},
@@ -2098,5 +6148,934 @@ fn main() {
 		},
 
 		_ => {},
+	};
+
+	event_loop.run(move |event, _, control_flow| {
+		if let Event::WindowEvent { ref event, window_id } = event {
+			if window_id == main_window_id {
+				if let Some(recorded_event) = RecordedEvent::from_window_event(event) {
+					// The replay is in control of the inputs for now, but the window can still
+					// really be resized (by the replay or not) and it must be handled.
+					let is_resize = matches!(recorded_event, RecordedEvent::Resized(_));
+					if replay_player.is_some() && !is_resize {
+						return;
+					}
+					if let Some(recorder) = &mut recorder {
+						recorder.record(&recorded_event);
+					}
+					input_happened_in_event_loop.set(true);
+				}
+			}
+		}
+		if let Event::MainEventsCleared = event {
+			if let Some(player) = &mut replay_player {
+				for recorded_event in player.due_events() {
+					if let RecordedEvent::Resized(size) = recorded_event {
+						// Only a request, the real `Resized` event that follows (with the size
+						// actually given to the window) is the one that resizes the game.
+						window_in_event_loop.set_inner_size(size);
+						continue;
+					}
+					if let Some(recorder) = &mut recorder {
+						recorder.record(&recorded_event);
+					}
+					let event = recorded_event.to_window_event();
+					input_happened_in_event_loop.set(true);
+					handle_event(
+						Event::WindowEvent { event, window_id: main_window_id },
+						control_flow,
+					);
+				}
+				if player.is_finished() {
+					log::info!("end of the replay, the game can be played from here");
+					replay_player = None;
+				}
+			}
+		}
+		handle_event(event, control_flow);
 	});
 }
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	/// The state of a run that just started on a map made for the test.
+	fn test_game() -> GameState {
+		let balance = Balance::default();
+		GameState {
+			phase: Phase::Player,
+			turn_counter: 0,
+			distance_traveled: 0,
+			crystal_amount: balance.starting_crystal_amount,
+			stats: RunStats::default(),
+			next_enemy_spawn: None,
+			waves: Waves::new(&balance),
+			balance,
+			auto_mining: false,
+			distance_goal: None,
+		}
+	}
+
+	fn test_enemy(hp: i32) -> Obj {
+		Obj::Enemy {
+			actions: 0,
+			hp,
+			fire: 0,
+			shield: 0,
+			alive_animation: None,
+			colored_animation: None,
+			base_tint: None,
+			variant: Enemy::Basic,
+		}
+	}
+
+	/// Resolves the move of the object at `from` to `to` as if its animation just ended.
+	fn resolve_move(map: &mut Map, game: &mut GameState, from: Coords, to: Coords) {
+		let obj = map.take_obj(from).unwrap();
+		let anim = Animation {
+			action: AnimationAction::Move { obj, from, to },
+			tp: TimeProgression::new(Duration::ZERO),
+		};
+		let new_anims = resolve_animation(map, &anim, game, &mut vec![]);
+		assert!(new_anims.is_empty());
+	}
+
+	/// Makes the animations end instantly, like `simulate_turn` does.
+	fn resolve_animations(map: &mut Map, game: &mut GameState, mut animations: Vec<Animation>) {
+		while !animations.is_empty() {
+			let anim = animations.remove(0);
+			let new_anims = resolve_animation(map, &anim, game, &mut vec![]);
+			animations.extend(new_anims);
+		}
+	}
+
+	#[test]
+	fn test_world_has_a_straight_path() {
+		let map = Map::test_world(8, 3, 1).with_obj((0, 1).into(), Obj::Caravan);
+		let path_coords = map.path_coords().unwrap();
+		assert_eq!(
+			path_coords,
+			(0..8).map(|x| (x, 1).into()).collect::<Vec<Coords>>()
+		);
+		assert_eq!(map.caravan_coords(), Some((0, 1).into()));
+		assert_eq!(map.enemies().count() + map.towers().count(), 0);
+	}
+
+	#[test]
+	fn enemy_moving_onto_the_caravan_ends_the_run() {
+		let mut map = Map::test_world(6, 3, 1)
+			.with_obj((0, 1).into(), Obj::Caravan)
+			.with_obj((1, 1).into(), test_enemy(5));
+		let mut game = test_game();
+		simulate_turn(&mut map, &mut game);
+		assert_eq!(map.caravan_coords(), None);
+		assert_eq!(
+			map.enemies().map(|(coords, _)| coords).collect::<Vec<_>>(),
+			[(0, 1).into()]
+		);
+		assert!(matches!(game.phase, Phase::GameOver));
+	}
+
+	#[test]
+	fn blocked_move_goes_back() {
+		let mut map = Map::test_world(6, 3, 1)
+			.with_obj((2, 1).into(), Obj::Caravan)
+			.with_obj((3, 1).into(), test_enemy(5));
+		let mut game = test_game();
+		resolve_move(&mut map, &mut game, (2, 1).into(), (3, 1).into());
+		assert_eq!(map.caravan_coords(), Some((2, 1).into()));
+		assert_eq!(
+			map.enemies().map(|(coords, _)| coords).collect::<Vec<_>>(),
+			[(3, 1).into()]
+		);
+	}
+
+	#[test]
+	fn enemy_walking_to_the_caravan_ends_the_run() {
+		let mut map = Map::test_world(8, 3, 1)
+			.with_obj((0, 1).into(), Obj::Caravan)
+			.with_obj((5, 1).into(), test_enemy(5));
+		let mut game = test_game();
+		for _ in 0..4 {
+			simulate_turn(&mut map, &mut game);
+			assert!(matches!(game.phase, Phase::Player));
+		}
+		simulate_turn(&mut map, &mut game);
+		assert!(matches!(game.phase, Phase::GameOver));
+	}
+
+	#[test]
+	fn tower_kills_enemy_in_its_line_of_sight() {
+		let mut map = Map::test_world(8, 3, 1)
+			.with_obj((0, 1).into(), Obj::Caravan)
+			.with_obj((3, 0).into(), Tower::Basic.new_obj())
+			.with_obj((5, 1).into(), test_enemy(1));
+		let mut game = test_game();
+		// Not in line with the tower yet.
+		simulate_turn(&mut map, &mut game);
+		assert_eq!(
+			map.enemies().map(|(coords, _)| coords).collect::<Vec<_>>(),
+			[(4, 1).into()]
+		);
+		// Right below the tower.
+		simulate_turn(&mut map, &mut game);
+		assert_eq!(map.enemies().count(), 0);
+		assert_eq!(game.stats.enemies_killed, 1);
+		assert_eq!(map.caravan_coords(), Some((0, 1).into()));
+		assert!(matches!(game.phase, Phase::Player));
+	}
+
+	#[test]
+	fn placing_a_tower_costs_crystals() {
+		let mut map = Map::test_world(8, 3, 1).with_obj((0, 1).into(), Obj::Caravan);
+		let mut game = test_game();
+		let price = game.balance.tower_price(&Tower::Basic);
+		game.crystal_amount = price;
+		let mut animations = vec![];
+		assert!(start_placing_tower(
+			&map,
+			&mut game,
+			&Tower::Basic,
+			(3, 0).into(),
+			&mut animations
+		));
+		resolve_animations(&mut map, &mut game, animations);
+		assert_eq!(
+			map.towers().map(|(coords, _)| coords).collect::<Vec<_>>(),
+			[(3, 0).into()]
+		);
+		assert_eq!(game.crystal_amount, 0);
+		// Not enough crystals left for another one.
+		let mut animations = vec![];
+		assert!(!start_placing_tower(
+			&map,
+			&mut game,
+			&Tower::Basic,
+			(4, 0).into(),
+			&mut animations
+		));
+		assert!(animations.is_empty());
+	}
+
+	#[test]
+	fn caravan_steps_forward_along_the_path() {
+		let mut map = Map::test_world(8, 3, 1).with_obj((0, 1).into(), Obj::Caravan);
+		let mut game = test_game();
+		let mut animations = vec![];
+		assert_eq!(
+			start_caravan_step_forward(&mut map, &mut animations),
+			Some(1)
+		);
+		resolve_animations(&mut map, &mut game, animations);
+		assert_eq!(map.caravan_coords(), Some((1, 1).into()));
+		// Blocked by an enemy.
+		let mut map = map.with_obj((2, 1).into(), test_enemy(5));
+		let mut animations = vec![];
+		assert_eq!(start_caravan_step_forward(&mut map, &mut animations), None);
+		assert_eq!(map.caravan_coords(), Some((1, 1).into()));
+	}
+
+	#[test]
+	fn obj_index_stays_consistent() {
+		let mut map = Map::test_world(80, 4, 1).with_obj((0, 1).into(), Obj::Caravan);
+		for x in (20..80).step_by(6) {
+			map = map.with_obj((x, 1).into(), test_enemy(x / 10));
+		}
+		for x in (10..60).step_by(12) {
+			map = map
+				.with_obj((x, 0).into(), Tower::Basic.new_obj())
+				.with_obj((x + 4, 2).into(), Tower::Fortress.new_obj());
+		}
+		assert!(map.obj_index_is_consistent());
+		let mut game = test_game();
+		for _ in 0..60 {
+			simulate_turn(&mut map, &mut game);
+			assert!(map.obj_index_is_consistent());
+		}
+		// The towers killed every enemy on the way, so all the turns were played.
+		assert_eq!(game.stats.enemies_killed, 10);
+		assert!(!game.phase.is_over());
+	}
+
+	#[test]
+	fn fortress_is_not_placed_on_the_path() {
+		let map = Map::test_world(8, 5, 2);
+		let fortress = Tower::Fortress.new_obj();
+		assert!(map.can_place_obj_at((3, 0).into(), &fortress));
+		assert!(map.can_place_obj_at((3, 3).into(), &fortress));
+		assert!(!map.can_place_obj_at((3, 1).into(), &fortress));
+		assert!(!map.can_place_obj_at((3, 2).into(), &fortress));
+		// Small towers can, enemies crush them.
+		assert!(map.can_place_obj_at((3, 2).into(), &Tower::Basic.new_obj()));
+	}
+
+	/// A map of only grass and a path that goes through the given tiles (that must be neighbors,
+	/// the first one coming from the left and the last one going to the right).
+	fn map_with_path(dims: Dimensions, tiles: &[Coords], first_distance: i32) -> Map {
+		let mut map = Map::new(dims, Some(1));
+		map.grid = Chunk::grass_grid(dims);
+		for (index, &coords) in tiles.iter().enumerate() {
+			let backward = index
+				.checked_sub(1)
+				.map_or(CoordsDelta::LEFT, |previous| tiles[previous] - coords);
+			let forward = tiles
+				.get(index + 1)
+				.map_or(CoordsDelta::RIGHT, |&next| next - coords);
+			map.grid.at_mut(coords).ground =
+				Ground::Path(Path { forward, backward, distance: first_distance + index as i32 });
+		}
+		map
+	}
+
+	#[test]
+	fn path_is_followed_along_its_turns() {
+		let snake: Vec<Coords> = [
+			(0, 1),
+			(1, 1),
+			(1, 2),
+			(1, 3),
+			(2, 3),
+			(3, 3),
+			(3, 2),
+			(3, 1),
+		]
+		.into_iter()
+		.chain([(3, 0), (4, 0)])
+		.map(Coords::from)
+		.collect();
+		// The tile at distance 0 went off the grid on the left.
+		let map = map_with_path((5, 4).into(), &snake, 7);
+		let path_coords = map.path_coords().unwrap();
+		assert_eq!(path_coords, snake);
+		let distances: Vec<i32> = path_coords
+			.iter()
+			.map(|&coords| map.grid.at(coords).path().unwrap().distance)
+			.collect();
+		assert_eq!(distances, (7..17).collect::<Vec<i32>>());
+		assert_eq!(map.rightmost_path_y_and_dist(), Some((0, 16)));
+	}
+
+	#[test]
+	fn path_that_does_not_leave_has_no_exit() {
+		let dead_end: Vec<Coords> = [(0, 1), (1, 1), (1, 0)]
+			.into_iter()
+			.map(Coords::from)
+			.collect();
+		let mut map = map_with_path((3, 2).into(), &dead_end, 0);
+		if let Ground::Path(path) = &mut map.grid.at_mut((1, 0).into()).ground {
+			path.forward = CoordsDelta::UP;
+		}
+		assert_eq!(map.path_coords().unwrap(), dead_end);
+		assert_eq!(map.rightmost_path_y_and_dist(), None);
+	}
+
+	#[test]
+	fn empty_map_has_no_path() {
+		let mut map = Map::new((4, 5).into(), None);
+		map.grid = Chunk::grass_grid((0, 5).into());
+		assert_eq!(map.path_coords(), None);
+		assert_eq!(map.rightmost_path_y_and_dist(), None);
+	}
+
+	#[test]
+	fn shield_absorbs_damages() {
+		// Partially.
+		let mut shield = 2;
+		assert_eq!(absorb_damages(&mut shield, 5), 3);
+		assert_eq!(shield, 0);
+		// Fully.
+		let mut shield = 5;
+		assert_eq!(absorb_damages(&mut shield, 3), 0);
+		assert_eq!(shield, 2);
+		// Not healing.
+		assert_eq!(absorb_damages(&mut shield, -2), -2);
+		assert_eq!(shield, 2);
+	}
+
+	#[test]
+	fn pink_shot_shields_what_it_hits() {
+		let mut map = Map::test_world(8, 3, 1).with_obj((3, 1).into(), test_enemy(5));
+		let shot = Tower::Pink.shot().unwrap();
+		map.shot_hits_obj_at((3, 1).into(), &shot, Team::Player, &mut RunStats::default());
+		assert!(matches!(
+			map.grid.at((3, 1).into()).obj,
+			Some(Obj::Enemy { hp: 5, shield: 2, .. })
+		));
+		// The next hits go to the shield first.
+		let mut stats = RunStats::default();
+		map.inflict_damage_to_obj_at((3, 1).into(), 3, &mut stats);
+		assert!(matches!(
+			map.grid.at((3, 1).into()).obj,
+			Some(Obj::Enemy { hp: 4, shield: 0, .. })
+		));
+		assert_eq!(stats.damage_dealt, 1);
+	}
+
+	#[test]
+	fn water_sprite_depends_on_the_top_and_left() {
+		let adjacency = |top: &str, water_on_the_left: bool| WaterAdjacency {
+			water_on_the_top: top == "water" || top == "water, ground corner",
+			nothing_on_the_top: top == "nothing",
+			ground_on_the_top_left_corner: top == "water, ground corner",
+			water_on_the_left,
+		};
+		let cases = [
+			("ground", true, 6),
+			("ground", false, 7),
+			("nothing", true, 8),
+			("nothing", false, 9),
+			("water", true, 10),
+			("water", false, 11),
+			("water, ground corner", true, 12),
+			("water, ground corner", false, 13),
+		];
+		for (top, water_on_the_left, index) in cases {
+			assert_eq!(
+				water_sprite_index(adjacency(top, water_on_the_left)),
+				index,
+				"{top}"
+			);
+		}
+	}
+
+	#[test]
+	fn water_adjacency_of_a_pond() {
+		// Water on the top row and in the bottom right corner, grass elsewhere.
+		let mut map = Map::test_world(3, 3, 1);
+		for coords in [(0, 0), (1, 0), (2, 0), (1, 2), (2, 2), (2, 1)] {
+			map.grid.at_mut(coords.into()).ground = Ground::Water;
+		}
+		let index_at = |coords: (i32, i32)| water_sprite_index(map.water_adjacency(coords.into()));
+		// On the top row, and water on the left except out of the map.
+		assert_eq!(index_at((0, 0)), 8);
+		assert_eq!(index_at((1, 0)), 8);
+		// Water on the top and on the top left corner, ground on the left.
+		assert_eq!(index_at((2, 1)), 11);
+		// Ground on the top and on the left.
+		assert_eq!(index_at((1, 2)), 7);
+		// Water on the top and on the left, ground on the top left corner.
+		assert_eq!(index_at((2, 2)), 12);
+	}
+
+	#[test]
+	fn spawning_stops_at_the_max_enemy_count() {
+		seed_rng(1124);
+		// The rock holds the enemies back so that they pile up.
+		let mut map = Map::test_world(40, 3, 1)
+			.with_obj((0, 1).into(), Obj::Caravan)
+			.with_obj((10, 1).into(), Obj::Rock { visual_variant: 0 });
+		let mut game = test_game();
+		game.balance.max_enemy_count = 5;
+		for _ in 0..100 {
+			simulate_turn(&mut map, &mut game);
+			// Like at the end of an enemy phase in the game.
+			if let Some(enemy) = game.next_enemy_spawn.take() {
+				game.next_enemy_spawn = map.spawn_enemy_on_column(39, enemy, &game.balance);
+			}
+			if game.next_enemy_spawn.is_none() {
+				game.next_enemy_spawn = game.waves.roll_next_spawn(&game.balance);
+			}
+			assert!(map.enemies().count() <= 5);
+		}
+		assert_eq!(map.enemies().count(), 5);
+		// The next one waits for room instead of being dropped.
+		assert!(game.next_enemy_spawn.is_some());
+	}
+
+	#[test]
+	fn towers_target_according_to_their_priority() {
+		// The path winds around the tile (2, 2) so that the tower there sees a path tile
+		// in each direction, at the distances 0 (left), 3 (up), 5 (right) and 7 (down).
+		let path: Vec<Coords> = [
+			(0, 2),
+			(0, 1),
+			(1, 1),
+			(2, 1),
+			(3, 1),
+			(3, 2),
+			(3, 3),
+			(2, 3),
+		]
+		.into_iter()
+		.chain([(1, 3), (1, 4), (2, 4), (3, 4), (4, 4)])
+		.map(Coords::from)
+		.collect();
+		let mut map = map_with_path((5, 5).into(), &path, 0)
+			.with_obj((0, 2).into(), test_enemy(4))
+			.with_obj((2, 1).into(), test_enemy(9))
+			.with_obj((3, 2).into(), test_enemy(1))
+			.with_obj((2, 3).into(), test_enemy(6));
+		let expected_directions = [
+			(TargetPriority::First, CoordsDelta::LEFT),
+			(TargetPriority::Last, CoordsDelta::DOWN),
+			(TargetPriority::Strongest, CoordsDelta::UP),
+			(TargetPriority::Weakest, CoordsDelta::RIGHT),
+		];
+		for (priority, direction) in expected_directions {
+			let mut tower = Tower::Basic.new_obj();
+			if let Obj::Tower { target_priority, .. } = &mut tower {
+				*target_priority = priority;
+			}
+			map.set_obj((2, 2).into(), Some(tower));
+			assert_eq!(
+				map.tower_target_direction((2, 2).into()),
+				Some(direction),
+				"{}",
+				priority.name()
+			);
+		}
+	}
+
+	/// The coords of the given objects, sorted (as they come in no particular order).
+	fn sorted_coords<'a>(objs: impl Iterator<Item = (Coords, &'a Obj)>) -> Vec<(i32, i32)> {
+		let mut coords: Vec<(i32, i32)> = objs.map(|(coords, _)| (coords.x, coords.y)).collect();
+		coords.sort();
+		coords
+	}
+
+	#[test]
+	fn indexed_objs_are_the_placed_ones() {
+		let mut map = Map::test_world(10, 4, 1)
+			.with_obj((3, 1).into(), Obj::Caravan)
+			.with_obj((5, 1).into(), test_enemy(3))
+			.with_obj((8, 1).into(), test_enemy(3))
+			.with_obj((4, 0).into(), Tower::Basic.new_obj())
+			.with_obj((6, 2).into(), Tower::Fortress.new_obj())
+			.with_obj((1, 0).into(), Obj::Rock { visual_variant: 0 });
+		assert_eq!(map.caravan_coords(), Some((3, 1).into()));
+		assert_eq!(sorted_coords(map.enemies()), [(5, 1), (8, 1)]);
+		// Only the top left tile of the fortress.
+		assert_eq!(sorted_coords(map.towers()), [(4, 0), (6, 2)]);
+		map.take_obj((5, 1).into());
+		map.take_obj((6, 2).into());
+		let caravan = map.take_obj((3, 1).into()).unwrap();
+		map.place_obj((4, 1).into(), caravan);
+		assert_eq!(map.caravan_coords(), Some((4, 1).into()));
+		assert_eq!(sorted_coords(map.enemies()), [(8, 1)]);
+		assert_eq!(sorted_coords(map.towers()), [(4, 0)]);
+		assert!(map.obj_index_is_consistent());
+	}
+
+	fn regenerator(hp: i32, max_hp: i32) -> Obj {
+		Obj::Enemy {
+			actions: 0,
+			hp,
+			fire: 0,
+			shield: 0,
+			alive_animation: None,
+			colored_animation: None,
+			base_tint: None,
+			variant: Enemy::Regenerator { max_hp, turns_since_hit: 0 },
+		}
+	}
+
+	fn hp_at(map: &Map, coords: Coords) -> i32 {
+		match map.grid.at(coords).obj {
+			Some(Obj::Enemy { hp, .. }) => hp,
+			_ => panic!("no enemy at {coords}"),
+		}
+	}
+
+	#[test]
+	fn regenerator_heals_when_left_alone() {
+		let mut map = Map::test_world(8, 3, 1).with_obj((5, 1).into(), regenerator(3, 5));
+		let mut game = test_game();
+		game.balance.regeneration_delay = 2;
+		game.balance.regeneration = 1;
+		let mut hps = vec![];
+		for _ in 0..5 {
+			// Where regenerators heal (the enemy phase is not played so that it stays there).
+			end_player_phase(&mut map, &mut game);
+			hps.push(hp_at(&map, (5, 1).into()));
+		}
+		// Not before the delay, and not over the max hp.
+		assert_eq!(hps, [3, 4, 5, 5, 5]);
+	}
+
+	#[test]
+	fn regenerator_does_not_heal_when_hit() {
+		let mut map = Map::test_world(8, 3, 1).with_obj((5, 1).into(), regenerator(8, 10));
+		let mut game = test_game();
+		game.balance.regeneration_delay = 2;
+		for turn in 0..5 {
+			end_player_phase(&mut map, &mut game);
+			// Every other hit is taken by a shield, it still counts as a hit.
+			if let Some(Obj::Enemy { shield, .. }) = &mut map.grid.at_mut((5, 1).into()).obj {
+				*shield = turn % 2;
+			}
+			map.inflict_damage_to_obj_at((5, 1).into(), 1, &mut game.stats);
+		}
+		assert_eq!(hp_at(&map, (5, 1).into()), 5);
+	}
+
+	#[test]
+	fn support_buffs_the_towers_next_to_it() {
+		let map = Map::test_world(10, 5, 2)
+			.with_obj((2, 0).into(), Tower::Support.new_obj())
+			.with_obj((3, 0).into(), Tower::Basic.new_obj())
+			.with_obj((1, 1).into(), Tower::Basic.new_obj())
+			.with_obj((5, 0).into(), Tower::Basic.new_obj())
+			.with_obj((6, 3).into(), Tower::Support.new_obj())
+			.with_obj((7, 3).into(), Tower::Support.new_obj())
+			.with_obj((4, 3).into(), Tower::Fortress.new_obj());
+		// On the side.
+		assert!(map.is_supported((3, 0).into()));
+		// On a corner, or further away.
+		assert!(!map.is_supported((1, 1).into()));
+		assert!(!map.is_supported((5, 0).into()));
+		// Next to any tile of a big tower (the fortress takes (4, 3) to (5, 4)).
+		assert!(map.is_supported((4, 3).into()));
+		// Supports buff each other too.
+		assert!(map.is_supported((6, 3).into()));
+		assert!(!map.is_supported((2, 0).into()));
+	}
+
+	#[test]
+	fn supported_tower_deals_more_damages() {
+		let hp_after_a_turn = |supported: bool| {
+			let mut map = Map::test_world(8, 3, 1)
+				.with_obj((0, 1).into(), Obj::Caravan)
+				.with_obj((3, 0).into(), Tower::Basic.new_obj())
+				.with_obj((4, 1).into(), test_enemy(10));
+			if supported {
+				map = map.with_obj((2, 0).into(), Tower::Support.new_obj());
+			}
+			let mut game = test_game();
+			simulate_turn(&mut map, &mut game);
+			hp_at(&map, (3, 1).into())
+		};
+		assert_eq!(hp_after_a_turn(false), 9);
+		assert_eq!(hp_after_a_turn(true), 8);
+	}
+
+	#[test]
+	fn path_generation_gives_up_on_impossible_constraints() {
+		seed_rng(1143);
+		// Too small for the path to go westward or to be long enough, the constraints have to be
+		// dropped (or the path made straight) for the generation to end.
+		for (dims, last_path_y_and_dist) in [((3, 1), None), ((1, 1), Some((0, 7)))] {
+			let dims: Dimensions = dims.into();
+			let mut map = Map::new(dims, Some(1));
+			map.grid = Chunk::generate(dims, last_path_y_and_dist, Biome::Plains).grid;
+			let path_coords = map.path_coords().unwrap();
+			assert!(path_coords.len() >= dims.w as usize);
+			let (_exit_y, exit_dist) = map.rightmost_path_y_and_dist().unwrap();
+			let first_dist = last_path_y_and_dist.map_or(0, |(_y, dist)| dist + 1);
+			assert_eq!(exit_dist, first_dist + path_coords.len() as i32 - 1);
+		}
+	}
+
+	/// Adds a side path tile that joins the path, it goes `backward` and is at `distance`.
+	fn with_side_path(mut map: Map, coords: Coords, backward: CoordsDelta, distance: i32) -> Map {
+		map.grid.at_mut(coords).ground =
+			Ground::Path(Path { forward: -backward, backward, distance });
+		map
+	}
+
+	fn ready_enemy() -> Obj {
+		let mut enemy = test_enemy(5);
+		if let Obj::Enemy { actions, .. } = &mut enemy {
+			*actions = 1;
+		}
+		enemy
+	}
+
+	#[test]
+	fn equidistant_enemies_play_top_to_bottom_then_left_to_right() {
+		// The tiles (3, 0), (2, 1) and (1, 0) are all at the distance 2.
+		let map = Map::test_world(6, 3, 1);
+		let map = with_side_path(map, (3, 0).into(), CoordsDelta::DOWN, 2);
+		let map = with_side_path(map, (1, 0).into(), CoordsDelta::DOWN, 2);
+		let mut map = map
+			.with_obj((2, 1).into(), ready_enemy())
+			.with_obj((3, 0).into(), ready_enemy());
+		assert_eq!(map.next_enemy_to_play(), Some((3, 0).into()));
+		map.place_obj((1, 0).into(), ready_enemy());
+		assert_eq!(map.next_enemy_to_play(), Some((1, 0).into()));
+		// Closer to the caravan first, whatever the coords.
+		map.place_obj((1, 1).into(), ready_enemy());
+		assert_eq!(map.next_enemy_to_play(), Some((1, 1).into()));
+	}
+
+	#[test]
+	fn enemies_funneling_into_the_path_never_stack() {
+		// Two side path tiles join the path at (3, 1), both at the same distance as (4, 1).
+		let map = Map::test_world(6, 3, 1);
+		let map = with_side_path(map, (3, 0).into(), CoordsDelta::DOWN, 4);
+		let map = with_side_path(map, (3, 2).into(), CoordsDelta::UP, 4);
+		// They pile up behind the rock.
+		let mut map = map
+			.with_obj((0, 1).into(), Obj::Caravan)
+			.with_obj((1, 1).into(), Obj::Rock { visual_variant: 0 })
+			.with_obj((3, 0).into(), test_enemy(5))
+			.with_obj((4, 1).into(), test_enemy(5))
+			.with_obj((3, 2).into(), test_enemy(5))
+			.with_obj((5, 1).into(), test_enemy(5));
+		let mut game = test_game();
+		for _ in 0..8 {
+			simulate_turn(&mut map, &mut game);
+			assert_eq!(map.enemies().count(), 4);
+			assert!(map.obj_index_is_consistent());
+		}
+		// The one from the bottom side path tile (which played after the one from the top)
+		// is still waiting to get in.
+		assert_eq!(
+			sorted_coords(map.enemies()),
+			[(2, 1), (3, 1), (3, 2), (4, 1)]
+		);
+
+		// Even if a step starts while a move to the same tile is still going on.
+		let mut map = Map::test_world(6, 3, 1);
+		map = with_side_path(map, (3, 0).into(), CoordsDelta::DOWN, 4)
+			.with_obj((3, 0).into(), ready_enemy())
+			.with_obj((4, 1).into(), ready_enemy());
+		game.phase = Phase::Enemy;
+		let mut animations = vec![];
+		phase_step(&mut map, &mut game, &mut animations, &mut vec![]);
+		phase_step(&mut map, &mut game, &mut animations, &mut vec![]);
+		assert_eq!(animations.len(), 1);
+		assert_eq!(sorted_coords(map.enemies()), [(4, 1)]);
+		resolve_animations(&mut map, &mut game, animations);
+		assert_eq!(sorted_coords(map.enemies()), [(3, 1), (4, 1)]);
+	}
+
+	#[test]
+	fn forests_have_more_trees_than_plains() {
+		seed_rng(1100);
+		// Generating whole chunks takes a while (for the path), the trees are what matters here.
+		let average_tree_count = |biome: Biome| {
+			let chunk_count = 100;
+			let tree_count: usize = (0..chunk_count)
+				.map(|_| {
+					let mut grid = Chunk::straight_path_grid(Chunk::DEFAULT_DIMS, 5, 0);
+					Chunk::generate_trees(&mut grid, &biome.world_gen_params());
+					grid
+						.dims
+						.iter()
+						.filter(|&coords| matches!(grid.at(coords).obj, Some(Obj::Tree { .. })))
+						.count()
+				})
+				.sum();
+			tree_count as f32 / chunk_count as f32
+		};
+		let (plains, forest) = (
+			average_tree_count(Biome::Plains),
+			average_tree_count(Biome::Forest),
+		);
+		assert!(
+			plains * 2.0 < forest,
+			"{plains} trees in plains and {forest} in forests"
+		);
+		// Everywhere but on the path.
+		let mut grid = Chunk::straight_path_grid(Chunk::DEFAULT_DIMS, 5, 0);
+		let params = WorldGenParams {
+			tree_probability: 1.0,
+			tree_probability_on_edges: 1.0,
+			..Biome::Forest.world_gen_params()
+		};
+		Chunk::generate_trees(&mut grid, &params);
+		assert!(grid
+			.dims
+			.iter()
+			.all(|coords| grid.at(coords).has_path() != grid.at(coords).obj.is_some()));
+	}
+
+	#[test]
+	fn screen_coords_to_tile_coords_at_tile_boundaries() {
+		let config = MapDrawingConfig { top_left: (10, 20).into(), zoom: 1.0, camera_x: 0.0 };
+		let tile_at = |config: &MapDrawingConfig, x: i32, y: i32| {
+			let TileCoords(coords) = config.screen_coords_to_tile_coords(PixelCoords((x, y).into()));
+			(coords.x, coords.y)
+		};
+		assert_eq!(tile_at(&config, 10, 20), (0, 0));
+		assert_eq!(tile_at(&config, 25, 35), (0, 0));
+		assert_eq!(tile_at(&config, 26, 36), (1, 1));
+		// Left of and above the map, the tiles have negative coords (not rounded towards 0).
+		assert_eq!(tile_at(&config, 9, 19), (-1, -1));
+		assert_eq!(tile_at(&config, -6, 4), (-1, -1));
+		assert_eq!(tile_at(&config, -7, 3), (-2, -2));
+
+		// Scrolled by a tile and a half with tiles of 32 pixels.
+		let config = MapDrawingConfig { top_left: (0, 0).into(), zoom: 2.0, camera_x: 1.5 };
+		assert_eq!(tile_at(&config, 0, 0), (1, 0));
+		assert_eq!(tile_at(&config, 15, 31), (1, 0));
+		assert_eq!(tile_at(&config, 16, 32), (2, 1));
+		assert_eq!(tile_at(&config, -48, -1), (0, -1));
+		assert_eq!(tile_at(&config, -49, -32), (-1, -1));
+	}
+
+	fn plain_shot(damages: i32, cascade: ShotCascade) -> Shot {
+		Shot { damages, fire: 0, shield: 0, additional_actions: 0, cascade }
+	}
+
+	/// The animation of a shot that is about to leave `from`, to give to `resolve_animations`.
+	fn shoot(from: Coords, direction: CoordsDelta, shot: Shot, team: Team) -> Animation {
+		Animation {
+			action: AnimationAction::Shoot { from, direction, shot, team },
+			tp: TimeProgression::new(Duration::ZERO),
+		}
+	}
+
+	#[test]
+	fn shot_leaving_the_map_is_lost() {
+		let mut map = Map::test_world(8, 3, 1);
+		let right = CoordsDelta::from((1, 0));
+		let shot = plain_shot(1, ShotCascade::None);
+		let mut resolve = |from: Coords| {
+			let (mut stats, mut sounds) = (RunStats::default(), vec![]);
+			resolve_shot(
+				&mut map,
+				from,
+				right,
+				&shot,
+				Team::Player,
+				Duration::ZERO,
+				&mut stats,
+				&mut sounds,
+			)
+		};
+		// Flies on over the last column, then leaves.
+		let anims = resolve((6, 1).into());
+		assert!(matches!(
+			anims[..],
+			[Animation {
+				action: AnimationAction::Shoot { from: Coords { x: 7, y: 1 }, .. },
+				..
+			}]
+		));
+		assert!(resolve((7, 1).into()).is_empty());
+	}
+
+	#[test]
+	fn piercing_shot_hits_two_enemies_in_a_row() {
+		let mut map = Map::test_world(8, 3, 1)
+			.with_obj((3, 1).into(), test_enemy(5))
+			.with_obj((4, 1).into(), test_enemy(5))
+			.with_obj((6, 1).into(), test_enemy(5));
+		let mut game = test_game();
+		let shot = plain_shot(
+			2,
+			ShotCascade::Piercing(Box::new(plain_shot(1, ShotCascade::None))),
+		);
+		let anim = shoot((1, 1).into(), (1, 0).into(), shot, Team::Player);
+		resolve_animations(&mut map, &mut game, vec![anim]);
+		// The second enemy stops the pierced shot, the third one is not reached.
+		let hps: Vec<i32> = [3, 4, 6].map(|x| hp_at(&map, (x, 1).into())).to_vec();
+		assert_eq!(hps, [3, 4, 5]);
+	}
+
+	#[test]
+	fn shot_splits_on_a_rock() {
+		let mut map = Map::test_world(8, 3, 1)
+			.with_obj((4, 1).into(), Obj::Rock { visual_variant: 0 })
+			.with_obj((4, 0).into(), test_enemy(5))
+			.with_obj((4, 2).into(), test_enemy(5));
+		let mut game = test_game();
+		let shot = plain_shot(
+			1,
+			ShotCascade::SplitInTwo(Box::new(plain_shot(2, ShotCascade::None))),
+		);
+		let anim = shoot((1, 1).into(), (1, 0).into(), shot, Team::Player);
+		resolve_animations(&mut map, &mut game, vec![anim]);
+		// The rock stops the shot (unharmed) and the split goes both ways.
+		assert!(matches!(
+			map.grid.at((4, 1).into()).obj,
+			Some(Obj::Rock { .. })
+		));
+		assert_eq!(hp_at(&map, (4, 0).into()), 3);
+		assert_eq!(hp_at(&map, (4, 2).into()), 3);
+	}
+
+	#[test]
+	fn shots_and_the_caravan() {
+		let mut map = Map::test_world(8, 3, 1)
+			.with_obj((3, 1).into(), Obj::Caravan)
+			.with_obj((5, 1).into(), test_enemy(5));
+		let mut game = test_game();
+		// A shot of the player flies through the caravan.
+		let shot = plain_shot(2, ShotCascade::None);
+		let anim = shoot((1, 1).into(), (1, 0).into(), shot.clone(), Team::Player);
+		resolve_animations(&mut map, &mut game, vec![anim]);
+		assert_eq!(hp_at(&map, (5, 1).into()), 3);
+		// A hostile shot stops on the caravan, which has no hp to lose.
+		let (mut stats, mut sounds) = (RunStats::default(), vec![]);
+		let anims = resolve_shot(
+			&mut map,
+			(4, 1).into(),
+			(-1, 0).into(),
+			&shot,
+			Team::Enemy,
+			Duration::ZERO,
+			&mut stats,
+			&mut sounds,
+		);
+		assert!(anims.is_empty());
+		assert!(matches!(sounds[..], [SoundEffect::Hit]));
+		assert_eq!(map.caravan_coords(), Some((3, 1).into()));
+		assert_eq!(stats.damage_dealt, 0);
+	}
+
+	#[test]
+	fn piercing_shot_flies_through_friendly_towers() {
+		let mut map = Map::test_world(8, 3, 1)
+			.with_obj((2, 1).into(), Tower::Basic.new_obj())
+			.with_obj((3, 1).into(), test_enemy(5))
+			.with_obj((4, 1).into(), Tower::Basic.new_obj())
+			.with_obj((5, 1).into(), test_enemy(5));
+		let tower_hp = map.grid.at((2, 1).into()).obj.as_ref().unwrap().hp();
+		let mut game = test_game();
+		let shot = plain_shot(
+			2,
+			ShotCascade::Piercing(Box::new(plain_shot(1, ShotCascade::None))),
+		);
+		let anim = shoot((1, 1).into(), (1, 0).into(), shot, Team::Player);
+		resolve_animations(&mut map, &mut game, vec![anim]);
+		// The towers neither take the hits nor use up the piercing.
+		assert_eq!(hp_at(&map, (3, 1).into()), 3);
+		assert_eq!(hp_at(&map, (5, 1).into()), 4);
+		for x in [2, 4] {
+			assert_eq!(
+				map.grid.at((x, 1).into()).obj.as_ref().unwrap().hp(),
+				tower_hp
+			);
+		}
+	}
+
+	#[test]
+	fn who_is_hostile_to_whom() {
+		let part = |team| Obj::Part { to_anchor: (-1, 0).into(), team };
+		// Each object, then whether it is hostile to the player and to the enemies.
+		let matrix = [
+			(Obj::Caravan, false, true),
+			(Tower::Basic.new_obj(), false, true),
+			(test_enemy(5), true, false),
+			(Obj::Tree { visual_variant: 0 }, false, false),
+			(Obj::Rock { visual_variant: 0 }, false, false),
+			(Obj::Crystal { visual_variant: 0 }, false, false),
+			(part(Some(Team::Player)), false, true),
+			(part(Some(Team::Enemy)), true, false),
+			(part(None), false, false),
+		];
+		for (obj, to_player, to_enemy) in matrix {
+			let name = obj.name();
+			assert_eq!(obj.is_hostile_to(Team::Player), to_player, "{name}");
+			assert_eq!(obj.is_hostile_to(Team::Enemy), to_enemy, "{name}");
+		}
+
+		// Shots only damage the fighters of the other team.
+		let mut map = Map::test_world(8, 3, 1)
+			.with_obj((2, 0).into(), Tower::Basic.new_obj())
+			.with_obj((4, 0).into(), test_enemy(5));
+		let shot = plain_shot(1, ShotCascade::None);
+		let mut stats = RunStats::default();
+		let hps = |map: &Map| [2, 4].map(|x| map.grid.at((x, 0).into()).obj.as_ref().unwrap().hp());
+		let initial_hps = hps(&map);
+		for team in [Team::Player, Team::Enemy] {
+			map.shot_hits_obj_at((2, 0).into(), &shot, team, &mut stats);
+			map.shot_hits_obj_at((4, 0).into(), &shot, team, &mut stats);
+		}
+		let [tower_hp, enemy_hp] = initial_hps.map(Option::unwrap);
+		assert_eq!(hps(&map), [Some(tower_hp - 1), Some(enemy_hp - 1)]);
+	}
+}
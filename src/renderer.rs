@@ -52,20 +52,41 @@ fn char_sprite(ch: char) -> Result<Rect, CharSpriteError> {
 	}
 }
 
+/// The dimensions (before scaling) of the box drawn in place of unsupported characters,
+/// which are the same as the dimensions of most glyphs.
+const TOFU_DIMS: Dimensions = Dimensions { w: 3, h: 5 };
+
 pub struct Font {
 	/// By how many times do we make the sprites bigger?
 	pub size_factor: i32,
+	/// The sprites of uppercase letters are the same as the lowercase ones, but uppercase letters
+	/// can be made bigger than the rest by using this size factor instead (for titles).
+	/// They stay aligned on the bottom of the line, which gets taller if needed.
+	pub capital_size_factor: Option<i32>,
 	/// The number of pixels between each character that has a sprite
 	/// (note that the space character does not have a sprite).
 	pub horizontal_spacing: i32,
 	/// The width of space characters in pixels.
 	pub space_width: i32,
+	/// If some, then every character (spaces included) takes exactly that many pixels
+	/// of width, with its glyph centered in that width, and `horizontal_spacing` is ignored.
+	/// Useful for numbers that change often, so that they don't jitter horizontally.
+	pub monospace_width: Option<i32>,
 	/// The color of the character sprites.
 	pub foreground: Color,
 	/// The background can be filled with the given color, if any.
 	pub background: Option<Color>,
+	/// If some, the glyphs are surrounded by a 1 pixel wide outline of that color
+	/// (which takes one more pixel on each edge, in addition to the margins).
+	pub outline: Option<Color>,
+	/// If some, the glyphs cast a shadow of the given color, which is the glyph drawn under it
+	/// with the given offset (the edges are pushed on the sides the shadow goes to).
+	pub shadow: Option<(CoordsDelta, Color)>,
 	/// How many margin pixels on the edges? Each axis has two margins, one on each side.
 	pub margins: Dimensions,
+	/// If strict, unsupported characters make the drawing fail with an error,
+	/// else they are drawn as a box (a "tofu").
+	pub strict: bool,
 }
 
 /// Assume you want to draw something on a point of the canvas.
@@ -99,24 +120,90 @@ impl PinPoint {
 	/// When drawing something of the given dimensions to the given destination point
 	/// that should be interpreted thanks to the given pin point, then the coords of where
 	/// the top left corner of the thing being drawn end up is what this returns.
-	fn actual_top_left_coords(self, dst: Coords, dims: Dimensions) -> Coords {
+	pub fn actual_top_left_coords(self, dst: Coords, dims: Dimensions) -> Coords {
 		(
 			dst.x - (dims.w as f32 * self.x) as i32,
 			dst.y - (dims.h as f32 * self.y) as i32,
 		)
 			.into()
 	}
+
+	/// The point of the given rect that is at this pin point's position,
+	/// for example its (excluded) bottom right corner for `PinPoint::BOTTOM_RIGHT`.
+	pub fn point_in_rect(self, rect: Rect) -> Coords {
+		(
+			rect.left() + (rect.dims.w as f32 * self.x) as i32,
+			rect.top() + (rect.dims.h as f32 * self.y) as i32,
+		)
+			.into()
+	}
 }
 
 #[derive(Debug)]
 pub enum CharError {
+	#[allow(dead_code)] // Only read through `Debug` when unwrapped.
 	Unsupported(char),
 }
 
 impl Font {
+	/// The space between the top left edges and the text, which is the margins, the outline
+	/// and the shadow (if it goes up or left).
+	fn padding_top_left(&self) -> CoordsDelta {
+		let (shadow_dx, shadow_dy) = self
+			.shadow
+			.map_or((0, 0), |(offset, _)| (offset.dx, offset.dy));
+		self.padding_without_shadow() + CoordsDelta::from(((-shadow_dx).max(0), (-shadow_dy).max(0)))
+	}
+
+	/// The space between the text and the bottom right edges, which is the margins, the outline
+	/// and the shadow (if it goes down or right).
+	fn padding_bottom_right(&self) -> CoordsDelta {
+		let (shadow_dx, shadow_dy) = self
+			.shadow
+			.map_or((0, 0), |(offset, _)| (offset.dx, offset.dy));
+		self.padding_without_shadow() + CoordsDelta::from((shadow_dx.max(0), shadow_dy.max(0)))
+	}
+
+	fn padding_without_shadow(&self) -> CoordsDelta {
+		let outline_width = self.outline.is_some() as i32;
+		CoordsDelta::from((
+			self.margins.w + outline_width,
+			self.margins.h + outline_width,
+		))
+	}
+
+	/// The width and height taken by the padding on both sides.
+	fn padding_dims(&self) -> Dimensions {
+		let padding = self.padding_top_left() + self.padding_bottom_right();
+		(padding.dx, padding.dy).into()
+	}
+
+	/// The size factor used for the sprite of the given character.
+	fn char_size_factor(&self, ch: char) -> i32 {
+		match self.capital_size_factor {
+			Some(capital_size_factor) if ch.is_ascii_uppercase() => capital_size_factor,
+			_ => self.size_factor,
+		}
+	}
+
+	/// The height of a line of text, without margins.
+	/// Bigger capital letters make the line taller even if there are none in the text,
+	/// so that the lines drawn with a font all have the same height.
+	fn line_height(&self) -> i32 {
+		5 * self.size_factor.max(self.capital_size_factor.unwrap_or(0))
+	}
+
+	/// The width taken by the given character in a line of text.
 	fn char_width(&self, ch: char) -> Result<i32, CharError> {
+		let glyph_width = self.glyph_width(ch)?;
+		Ok(self.monospace_width.unwrap_or(glyph_width))
+	}
+
+	/// The width of the glyph of the given character, which uses its own size factor
+	/// (so bigger capitals are wider too).
+	fn glyph_width(&self, ch: char) -> Result<i32, CharError> {
 		match char_sprite(ch) {
-			Ok(sprite) => Ok(sprite.dims.w * self.size_factor),
+			Ok(sprite) => Ok(sprite.dims.w * self.char_size_factor(ch)),
 			Err(CharSpriteError::Whitespace(whitespace)) => {
 				if whitespace == ' ' {
 					Ok(self.space_width)
@@ -126,12 +213,38 @@ impl Font {
 					unreachable!()
 				}
 			},
-			Err(CharSpriteError::Unsupported(unsupported)) => Err(CharError::Unsupported(unsupported)),
+			Err(CharSpriteError::Unsupported(unsupported)) if self.strict => {
+				Err(CharError::Unsupported(unsupported))
+			},
+			Err(CharSpriteError::Unsupported(_)) => Ok(TOFU_DIMS.w * self.size_factor),
+		}
+	}
+
+	/// Draws the empty box that stands for an unsupported character.
+	fn draw_tofu(&self, renderer: &mut SoftwareRenderer, dst: Rect, color: Color) {
+		let thickness = self.size_factor;
+		for side in [
+			Rect { top_left: dst.top_left, dims: (dst.dims.w, thickness).into() },
+			Rect { top_left: dst.top_left, dims: (thickness, dst.dims.h).into() },
+			Rect::xywh(
+				dst.left(),
+				dst.bottom_excluded() - thickness,
+				dst.dims.w,
+				thickness,
+			),
+			Rect::xywh(
+				dst.right_excluded() - thickness,
+				dst.top(),
+				thickness,
+				dst.dims.h,
+			),
+		] {
+			renderer.draw_rect(side, color);
 		}
 	}
 
 	fn char_can_have_spacing_around_it(&self, ch: char) -> bool {
-		ch != ' ' && ch != '\n'
+		self.monospace_width.is_none() && ch != ' ' && ch != '\n'
 	}
 
 	fn text_line_width(&self, text: &str) -> Result<i32, CharError> {
@@ -148,67 +261,620 @@ impl Font {
 		Ok(width)
 	}
 
+	/// Draws the glyphs of the given characters (each with the bottom left corner of its glyph,
+	/// see `draw_glyph`), with their shadow and outline if any.
+	/// All the shadows then all the outlines are drawn first so that they don't cover
+	/// the neighboring glyphs.
+	fn draw_glyphs(&self, renderer: &mut SoftwareRenderer, glyphs: &[(char, Coords)]) {
+		if let Some((offset, shadow)) = self.shadow {
+			for &(ch, bottom_left) in glyphs {
+				self.draw_glyph(renderer, ch, bottom_left + offset, shadow);
+			}
+		}
+		if let Some(outline) = self.outline {
+			for &(ch, bottom_left) in glyphs {
+				for dx in -1..=1 {
+					for dy in -1..=1 {
+						if (dx, dy) != (0, 0) {
+							let offset = CoordsDelta::from((dx, dy));
+							self.draw_glyph(renderer, ch, bottom_left + offset, outline);
+						}
+					}
+				}
+			}
+		}
+		for &(ch, bottom_left) in glyphs {
+			self.draw_glyph(renderer, ch, bottom_left, self.foreground);
+		}
+	}
+
+	/// Draws the glyph of the given character in the given color so that its bottom left corner
+	/// ends up on `bottom_left` (excluded on the bottom side).
+	/// Unsupported characters are expected to have been rejected before if the font is strict.
+	fn draw_glyph(
+		&self,
+		renderer: &mut SoftwareRenderer,
+		ch: char,
+		bottom_left: Coords,
+		color: Color,
+	) {
+		match char_sprite(ch) {
+			Ok(sprite) => {
+				let dims = sprite.dims * self.char_size_factor(ch);
+				let dst = Rect { top_left: bottom_left - CoordsDelta::from((0, dims.h)), dims };
+				renderer.draw_sprite(
+					dst,
+					sprite,
+					DrawSpriteEffects {
+						flip_horizontally: false,
+						flip_vertically: false,
+						flip_diagonally_id: false,
+						paint: Some(color),
+						scale_mode: ScaleMode::Nearest,
+						rotation: 0.0,
+					},
+				);
+			},
+			Err(CharSpriteError::Whitespace(' ')) => {},
+			Err(CharSpriteError::Whitespace(_)) => todo!(),
+			Err(CharSpriteError::Unsupported(_)) => {
+				let dims = TOFU_DIMS * self.size_factor;
+				let dst = Rect { top_left: bottom_left - CoordsDelta::from((0, dims.h)), dims };
+				self.draw_tofu(renderer, dst, color);
+			},
+		}
+	}
+
+	/// The dimensions of the rect that `draw_text_line` would draw the given text in
+	/// (margins, outline and shadow included).
+	pub fn text_line_dims(&self, text: &str) -> Result<Dimensions, CharError> {
+		let width = self.text_line_width(text)? + self.padding_dims().w;
+		let height = self.line_height() + self.padding_dims().h;
+		Ok((width, height).into())
+	}
+
 	/// Draws some text at the given destination.
 	/// The part of the text ends up exactly on the destination is specified via
 	/// the `PinPoint` (see its documentation for an explanation).
 	pub fn draw_text_line(
 		&self,
-		renderer: &mut Renderer,
+		renderer: &mut SoftwareRenderer,
 		text: &str,
 		dst: Coords,
 		pp: PinPoint,
 	) -> Result<Rect, CharError> {
-		let width = self.text_line_width(text)? + self.margins.w * 2;
-		let height = 5 * self.size_factor + self.margins.h * 2;
-		let dims: Dimensions = (width, height).into();
+		let dims = self.text_line_dims(text)?;
 		let top_left = pp.actual_top_left_coords(dst, dims);
 		let rect = Rect { top_left, dims };
 		if let Some(background) = self.background {
 			renderer.draw_rect(rect, background);
 		}
-		let mut head = top_left + self.margins.into();
+		let mut head = top_left + self.padding_top_left();
+		let bottom = head.y + self.line_height();
+		let mut glyphs = vec![];
 		let mut last_can_have_spacing = false;
 		for ch in text.chars() {
 			let current_can_have_spacing = self.char_can_have_spacing_around_it(ch);
 			if last_can_have_spacing && current_can_have_spacing {
 				head.x += self.horizontal_spacing;
 			}
-			head.x += match char_sprite(ch) {
-				Ok(sprite) => {
-					let dst = Rect { top_left: head, dims: sprite.dims * self.size_factor };
-					renderer.draw_sprite(
-						dst,
-						sprite,
-						DrawSpriteEffects {
-							flip_horizontally: false,
-							flip_vertically: false,
-							flip_diagonally_id: false,
-							paint: Some(self.foreground),
-						},
-					);
-					dst.dims.w
+			let advance = self.char_width(ch)?;
+			// The glyph is centered in the space it takes (which only matters in monospace).
+			let glyph_left = head.x + (advance - self.glyph_width(ch)?) / 2;
+			glyphs.push((ch, (glyph_left, bottom).into()));
+			head.x += advance;
+			last_can_have_spacing = current_can_have_spacing;
+		}
+		self.draw_glyphs(renderer, &glyphs);
+		Ok(rect)
+	}
+
+	/// Draws some text so that its right end ends up on the given destination
+	/// (vertically centered on it), laying the characters out from right to left.
+	/// This is the same as `draw_text_line` with `PinPoint::CENTER_RIGHT`, handy to put counters
+	/// on the left of some icon.
+	pub fn draw_text_line_right_anchored(
+		&self,
+		renderer: &mut SoftwareRenderer,
+		text: &str,
+		dst: Coords,
+	) -> Result<Rect, CharError> {
+		let height = self.line_height() + self.padding_dims().h;
+		let top = dst.y - height / 2;
+		let bottom = top + self.padding_top_left().dy + self.line_height();
+		// The glyphs are laid out from the right, then drawn once the background is drawn.
+		let mut right = dst.x - self.padding_bottom_right().dx;
+		let mut glyphs = vec![];
+		let mut last_can_have_spacing = false;
+		for ch in text.chars().rev() {
+			let current_can_have_spacing = self.char_can_have_spacing_around_it(ch);
+			if last_can_have_spacing && current_can_have_spacing {
+				right -= self.horizontal_spacing;
+			}
+			let advance = self.char_width(ch)?;
+			right -= advance;
+			let glyph_left = right + (advance - self.glyph_width(ch)?) / 2;
+			glyphs.push((ch, (glyph_left, bottom).into()));
+			last_can_have_spacing = current_can_have_spacing;
+		}
+		let left = right - self.padding_top_left().dx;
+		let rect = Rect::xywh(left, top, dst.x - left, height);
+		if let Some(background) = self.background {
+			renderer.draw_rect(rect, background);
+		}
+		self.draw_glyphs(renderer, &glyphs);
+		Ok(rect)
+	}
+
+	/// Draws some text with the characters stacked vertically (from top to bottom),
+	/// each of them horizontally centered in the column.
+	/// The `horizontal_spacing` is used as the spacing between lines.
+	pub fn draw_text_column(
+		&self,
+		renderer: &mut SoftwareRenderer,
+		text: &str,
+		dst: Coords,
+		pp: PinPoint,
+	) -> Result<Rect, CharError> {
+		let char_count = text.chars().count() as i32;
+		let mut column_width = 0;
+		for ch in text.chars() {
+			column_width = column_width.max(self.char_width(ch)?);
+		}
+		let width = column_width + self.padding_dims().w;
+		let height = char_count * self.line_height()
+			+ (char_count - 1).max(0) * self.horizontal_spacing
+			+ self.padding_dims().h;
+		let dims: Dimensions = (width, height).into();
+		let top_left = pp.actual_top_left_coords(dst, dims);
+		let rect = Rect { top_left, dims };
+		if let Some(background) = self.background {
+			renderer.draw_rect(rect, background);
+		}
+		let mut head = top_left + self.padding_top_left();
+		let mut glyphs = vec![];
+		for ch in text.chars() {
+			let glyph_left = head.x + (column_width - self.glyph_width(ch)?) / 2;
+			glyphs.push((ch, (glyph_left, head.y + self.line_height()).into()));
+			head.y += self.line_height() + self.horizontal_spacing;
+		}
+		self.draw_glyphs(renderer, &glyphs);
+		Ok(rect)
+	}
+}
+
+/// The side of the tiles in the spritesheet, in pixels. The tile sprites are found with
+/// `Rect::tile(coords, TILE_PX)` and the sprite pixels are scaled up by `dst side / TILE_PX`.
+pub const TILE_PX: i32 = 16;
+
+/// The sprite coordinates used everywhere assume that the spritesheet is at least this big
+/// (which is the size of the embedded one). The font glyphs are found from the bottom
+/// of the spritesheet assuming it is exactly 256 pixels tall (see `char_sprite`),
+/// so the height must match exactly.
+const SPRITESHEET_MIN_WIDTH: u32 = 256;
+const SPRITESHEET_HEIGHT: u32 = 256;
+
+fn embedded_spritesheet() -> image::DynamicImage {
+	image::load_from_memory(include_bytes!("../assets/spritesheet.png")).unwrap()
+}
+
+#[derive(Debug)]
+pub enum SpritesheetError {
+	Image(image::ImageError),
+	BadDimensions { w: u32, h: u32 },
+}
+impl std::fmt::Display for SpritesheetError {
+	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+		match self {
+			SpritesheetError::Image(error) => write!(f, "{error}"),
+			SpritesheetError::BadDimensions { w, h } => write!(
+				f,
+				"the spritesheet is {w}x{h} but it must be at least {SPRITESHEET_MIN_WIDTH} wide \
+				and exactly {SPRITESHEET_HEIGHT} tall"
+			),
+		}
+	}
+}
+
+/// Loads a spritesheet from a file, making sure it can be used in place of the embedded one.
+pub fn load_spritesheet(path: &std::path::Path) -> Result<image::DynamicImage, SpritesheetError> {
+	let spritesheet = image::open(path).map_err(SpritesheetError::Image)?;
+	let (w, h) = spritesheet.dimensions();
+	if w < SPRITESHEET_MIN_WIDTH || h != SPRITESHEET_HEIGHT {
+		return Err(SpritesheetError::BadDimensions { w, h });
+	}
+	Ok(spritesheet)
+}
+
+/// Draws into a plain in-memory pixel buffer, without needing any window or GPU.
+///
+/// All the drawing happens here, the `Renderer` only presents the resulting frame on screen
+/// (and derefs to its `SoftwareRenderer` so that it can be drawn on the same way).
+pub struct SoftwareRenderer {
+	/// RGBA pixels, row by row.
+	frame: Vec<u8>,
+	frame_dims: Dimensions,
+	spritesheet: image::DynamicImage,
+	clear_color: Color,
+	/// The drawing only touches the pixels that are in the last rect (if any), see `push_clip`.
+	clip_stack: Vec<Rect>,
+}
+
+impl SoftwareRenderer {
+	#[allow(dead_code)] // Only useful to render without a window.
+	pub fn new(dims: Dimensions, clear_color: Color) -> SoftwareRenderer {
+		SoftwareRenderer::with_spritesheet(dims, clear_color, embedded_spritesheet())
+	}
+
+	pub fn with_spritesheet(
+		dims: Dimensions,
+		clear_color: Color,
+		spritesheet: image::DynamicImage,
+	) -> SoftwareRenderer {
+		SoftwareRenderer {
+			frame: vec![0; dims.area() * 4],
+			frame_dims: dims,
+			spritesheet,
+			clear_color,
+			clip_stack: vec![],
+		}
+	}
+
+	/// Swaps the spritesheet for the given one (for example when reloading it from disk).
+	pub fn set_spritesheet(&mut self, spritesheet: image::DynamicImage) {
+		self.spritesheet = spritesheet;
+	}
+
+	pub fn frame(&self) -> &[u8] {
+		&self.frame
+	}
+
+	/// Returns the color of the given pixel of the frame, if in bounds.
+	#[cfg(test)]
+	pub fn pixel(&self, coords: Coords) -> Option<Color> {
+		let pixel_byte_index = self.frame_dims.index_of_coords(coords)? * 4;
+		let raw: [u8; 4] = self.frame[pixel_byte_index..(pixel_byte_index + 4)]
+			.try_into()
+			.unwrap();
+		Some(Color::rgba_u8(raw[0], raw[1], raw[2], raw[3]))
+	}
+
+	pub fn clear(&mut self) {
+		let clear_color = self.clear_color.raw();
+		self
+			.frame
+			.chunks_exact_mut(4)
+			.for_each(|pixel| pixel.copy_from_slice(&clear_color));
+	}
+
+	pub fn resized(&mut self, new_dims: Dimensions) {
+		self.frame.resize(new_dims.area() * 4, 0);
+		self.frame_dims = new_dims;
+	}
+
+	pub fn dims(&self) -> Dimensions {
+		self.frame_dims
+	}
+
+	/// Until the matching `pop_clip`, the drawing only touches the pixels that are in `clip`
+	/// (and in the clips pushed before it, a nested clip can't draw outside of its parent).
+	/// Clearing the frame is not affected.
+	pub fn push_clip(&mut self, clip: Rect) {
+		let clip = match self.clip() {
+			Some(current_clip) => current_clip.intersection(clip),
+			None => clip,
+		};
+		self.clip_stack.push(clip);
+	}
+
+	/// Goes back to the clip that was active before the last `push_clip`.
+	pub fn pop_clip(&mut self) {
+		self.clip_stack.pop();
+	}
+
+	/// The rect outside of which nothing is drawn, if any.
+	pub fn clip(&self) -> Option<Rect> {
+		self.clip_stack.last().copied()
+	}
+
+	/// A color that is not fully opaque is blended over the pixel that is already there.
+	fn set_pixel(&mut self, coords: Coords, color: [u8; 4]) {
+		if self.clip().is_some_and(|clip| !clip.contains(coords)) {
+			return;
+		}
+		if let Some(pixel_index) = self.frame_dims.index_of_coords(coords) {
+			let pixel_byte_index = pixel_index * 4;
+			if color[3] == 255 {
+				let pixel_bytes = pixel_byte_index..(pixel_byte_index + 4);
+				self.frame[pixel_bytes].copy_from_slice(&color);
+			} else {
+				let alpha = color[3] as u32;
+				let pixel = &mut self.frame[pixel_byte_index..(pixel_byte_index + 3)];
+				for (channel, new_channel) in pixel.iter_mut().zip(color) {
+					*channel =
+						((new_channel as u32 * alpha + *channel as u32 * (255 - alpha)) / 255) as u8;
+				}
+			}
+		}
+	}
+
+	/// Draw a rect from the spritesheet onto a rect in the pixel buffer.
+	/// The `paint` argument, if some, will paint all the non-transparent pixels to the given color.
+	pub fn draw_sprite(&mut self, dst: Rect, sprite: Rect, effects: DrawSpriteEffects) {
+		// Some animations shrink the destination down to nothing (and we divide by its dims).
+		if dst.dims.w <= 0 || dst.dims.h <= 0 || sprite.dims.w <= 0 || sprite.dims.h <= 0 {
+			return;
+		}
+		// The sprites are hardcoded, one that sticks out of the spritesheet is a bug.
+		debug_assert!(
+			0 <= sprite.left()
+				&& 0 <= sprite.top()
+				&& sprite.right_excluded() <= self.spritesheet.width() as i32
+				&& sprite.bottom_excluded() <= self.spritesheet.height() as i32,
+			"sprite {sprite} out of the bounds of the spritesheet of dims {:?}",
+			self.spritesheet.dimensions()
+		);
+		if effects.rotation != 0.0 {
+			self.draw_sprite_rotated(dst, sprite, effects);
+			return;
+		}
+		// When flipping diagonally, the sprite's x axis is stretched along the dst's y axis
+		// and the other way around.
+		let (dst_w, dst_h) = if effects.flip_diagonally_id {
+			(dst.dims.h, dst.dims.w)
+		} else {
+			(dst.dims.w, dst.dims.h)
+		};
+		// `coords_dst_dims` is a pixel in the dst rect but with (0, 0) being the top left corner.
+		for coords_dst_dims in dst.dims.iter() {
+			// `(sx, sy)` is the pixel to read from the spritesheet.
+			let (cddx, cddy) = if effects.flip_diagonally_id {
+				(coords_dst_dims.y, coords_dst_dims.x)
+			} else {
+				(coords_dst_dims.x, coords_dst_dims.y)
+			};
+			let color = match effects.scale_mode {
+				ScaleMode::Nearest => {
+					// Offsets in the sprite, `cddx < dst_w` so these are always in
+					// `0..sprite.dims.w` (and same for y), the `min` is just here to make sure.
+					let offset_x = (cddx * sprite.dims.w / dst_w).min(sprite.dims.w - 1);
+					let offset_y = (cddy * sprite.dims.h / dst_h).min(sprite.dims.h - 1);
+					self.sprite_pixel(sprite, &effects, offset_x, offset_y)
 				},
-				Err(CharSpriteError::Whitespace(' ')) => self.space_width,
-				Err(CharSpriteError::Whitespace(_)) => todo!(),
-				Err(CharSpriteError::Unsupported(unsupported)) => {
-					return Err(CharError::Unsupported(unsupported));
+				ScaleMode::Bilinear => {
+					// The position in the sprite of the center of the dst pixel, so that
+					// the sprite pixel centers land on where their color is exactly theirs.
+					let fx = ((cddx as f32 + 0.5) * sprite.dims.w as f32 / dst_w as f32 - 0.5)
+						.clamp(0.0, (sprite.dims.w - 1) as f32);
+					let fy = ((cddy as f32 + 0.5) * sprite.dims.h as f32 / dst_h as f32 - 0.5)
+						.clamp(0.0, (sprite.dims.h - 1) as f32);
+					let (x0, y0) = (fx as i32, fy as i32);
+					let (x1, y1) = (
+						(x0 + 1).min(sprite.dims.w - 1),
+						(y0 + 1).min(sprite.dims.h - 1),
+					);
+					let (tx, ty) = (fx - x0 as f32, fy - y0 as f32);
+					// The colors are weighted by their alpha so that the color of transparent
+					// pixels (that is meaningless) doesn't bleed into the visible ones.
+					let mut sum = [0.0; 4];
+					for (x, y, weight) in [
+						(x0, y0, (1.0 - tx) * (1.0 - ty)),
+						(x1, y0, tx * (1.0 - ty)),
+						(x0, y1, (1.0 - tx) * ty),
+						(x1, y1, tx * ty),
+					] {
+						let color = self.sprite_pixel(sprite, &effects, x, y);
+						let alpha_weight = weight * color[3] as f32;
+						for channel in 0..3 {
+							sum[channel] += color[channel] as f32 * alpha_weight;
+						}
+						sum[3] += alpha_weight;
+					}
+					if sum[3] == 0.0 {
+						[0, 0, 0, 0]
+					} else {
+						[
+							(sum[0] / sum[3]) as u8,
+							(sum[1] / sum[3]) as u8,
+							(sum[2] / sum[3]) as u8,
+							sum[3].round() as u8,
+						]
+					}
 				},
 			};
-			last_can_have_spacing = current_can_have_spacing;
+			// Skip transparent pixels.
+			if color[3] == 0 {
+				continue;
+			}
+			let color = painted(color, effects.paint);
+
+			// `coords_pixel_buffer` is the pixel to write to in the pixel buffer,
+			// each of which is visited once.
+			let coords_pixel_buffer = coords_dst_dims + dst.top_left;
+			self.set_pixel(coords_pixel_buffer, color);
+		}
+	}
+
+	/// Like `draw_sprite` but the sprite is rotated by `effects.rotation` about the center of `dst`
+	/// (the corners can thus stick out of `dst`). Always nearest sampling.
+	///
+	/// Each pixel of the bounding box of the rotated `dst` is mapped back through the rotation
+	/// to find where it lands in the unrotated `dst`, this costs a few floating point operations
+	/// per pixel and visits more pixels than `dst` has (up to twice as many at 45 degrees).
+	fn draw_sprite_rotated(&mut self, dst: Rect, sprite: Rect, effects: DrawSpriteEffects) {
+		let (sin, cos) = effects.rotation.sin_cos();
+		let (half_w, half_h) = (dst.dims.w as f32 / 2.0, dst.dims.h as f32 / 2.0);
+		let center_x = dst.top_left.x as f32 + half_w;
+		let center_y = dst.top_left.y as f32 + half_h;
+		let bounding_half_w = (half_w * cos.abs() + half_h * sin.abs()).ceil() as i32;
+		let bounding_half_h = (half_w * sin.abs() + half_h * cos.abs()).ceil() as i32;
+		let (dst_w, dst_h) = if effects.flip_diagonally_id {
+			(dst.dims.h, dst.dims.w)
+		} else {
+			(dst.dims.w, dst.dims.h)
+		};
+		for y in (center_y as i32 - bounding_half_h)..=(center_y as i32 + bounding_half_h) {
+			for x in (center_x as i32 - bounding_half_w)..=(center_x as i32 + bounding_half_w) {
+				// Rotate the center of the pixel by the opposite angle.
+				let (rel_x, rel_y) = (x as f32 + 0.5 - center_x, y as f32 + 0.5 - center_y);
+				let unrotated_x = rel_x * cos + rel_y * sin + half_w;
+				let unrotated_y = -rel_x * sin + rel_y * cos + half_h;
+				if unrotated_x < 0.0 || unrotated_y < 0.0 {
+					continue;
+				}
+				let (ux, uy) = (unrotated_x as i32, unrotated_y as i32);
+				if ux >= dst.dims.w || uy >= dst.dims.h {
+					continue;
+				}
+				let (cddx, cddy) = if effects.flip_diagonally_id {
+					(uy, ux)
+				} else {
+					(ux, uy)
+				};
+				let offset_x = (cddx * sprite.dims.w / dst_w).min(sprite.dims.w - 1);
+				let offset_y = (cddy * sprite.dims.h / dst_h).min(sprite.dims.h - 1);
+				let color = self.sprite_pixel(sprite, &effects, offset_x, offset_y);
+				if color[3] == 0 {
+					continue;
+				}
+				self.set_pixel((x, y).into(), painted(color, effects.paint));
+			}
+		}
+	}
+
+	/// The color of the pixel of the given sprite at the given offset from its top left corner,
+	/// with the (non-diagonal) flips applied.
+	fn sprite_pixel(
+		&self,
+		sprite: Rect,
+		effects: &DrawSpriteEffects,
+		offset_x: i32,
+		offset_y: i32,
+	) -> [u8; 4] {
+		let sx = if effects.flip_horizontally {
+			(sprite.top_left.x + sprite.dims.w - 1 - offset_x) as u32
+		} else {
+			(sprite.top_left.x + offset_x) as u32
+		};
+		let sy = if effects.flip_vertically {
+			(sprite.top_left.y + sprite.dims.h - 1 - offset_y) as u32
+		} else {
+			(sprite.top_left.y + offset_y) as u32
+		};
+		self.spritesheet.get_pixel(sx, sy).0
+	}
+
+	pub fn draw_rect(&mut self, dst: Rect, color: Color) {
+		let color = color.raw();
+		// The pixels that can be drawn on.
+		let bounds = Rect { top_left: (0, 0).into(), dims: self.frame_dims };
+		let bounds = self.clip().map_or(bounds, |clip| clip.intersection(bounds));
+		let fully_in_bounds = bounds.left() <= dst.left()
+			&& bounds.top() <= dst.top()
+			&& dst.right_excluded() <= bounds.right_excluded()
+			&& dst.bottom_excluded() <= bounds.bottom_excluded();
+		if color[3] == 255 && fully_in_bounds && 0 < dst.dims.w {
+			// Fast path for the big opaque rects (such as the HUD backgrounds): no blending and
+			// no bounds checks, the rows are copied whole from a row of the color.
+			let row = color.repeat(dst.dims.w as usize);
+			for y in dst.top()..dst.bottom_excluded() {
+				let row_start = (y * self.frame_dims.w + dst.left()) as usize * 4;
+				self.frame[row_start..(row_start + row.len())].copy_from_slice(&row);
+			}
+		} else {
+			for coords in dst.iter() {
+				self.set_pixel(coords, color);
+			}
+		}
+	}
+
+	/// Multiplies the color of the pixels in `dst` by `color` (its alpha is ignored),
+	/// so white leaves them as they are and darker colors darken and tint them.
+	pub fn multiply_rect(&mut self, dst: Rect, color: Color) {
+		let factors = [color.r() as u32, color.g() as u32, color.b() as u32];
+		for coords in dst.iter() {
+			if self.clip().is_some_and(|clip| !clip.contains(coords)) {
+				continue;
+			}
+			if let Some(pixel_index) = self.frame_dims.index_of_coords(coords) {
+				let pixel = &mut self.frame[pixel_index * 4..pixel_index * 4 + 3];
+				for (channel, factor) in pixel.iter_mut().zip(factors) {
+					*channel = (*channel as u32 * factor / 255) as u8;
+				}
+			}
+		}
+	}
+
+	/// Draws a one-pixel-wide line from `from` to `to` (both included).
+	pub fn draw_line(&mut self, from: Coords, to: Coords, color: Color) {
+		// Bresenham's line algorithm, handling all the octants.
+		let delta = to - from;
+		let (dx, dy) = (delta.dx.abs(), -delta.dy.abs());
+		let (step_x, step_y) = (delta.dx.signum(), delta.dy.signum());
+		let mut error = dx + dy;
+		let mut head = from;
+		loop {
+			self.set_pixel(head, color.raw());
+			if head == to {
+				break;
+			}
+			let double_error = error * 2;
+			if double_error >= dy {
+				error += dy;
+				head.x += step_x;
+			}
+			if double_error <= dx {
+				error += dx;
+				head.y += step_y;
+			}
+		}
+	}
+
+	pub fn draw_rect_edge(&mut self, dst: Rect, color: Color) {
+		let dst_inside = Rect {
+			top_left: dst.top_left + CoordsDelta::from((2, 2)),
+			dims: dst.dims - (4, 4).into(),
+		};
+		for coords in dst.iter() {
+			if !dst_inside.contains(coords) {
+				self.set_pixel(coords, color.raw());
+			}
 		}
-		Ok(rect)
 	}
 }
 
 pub struct Renderer {
 	pix_buf: pixels::Pixels,
-	pix_buf_dims: Dimensions,
-	spritesheet: image::DynamicImage,
-	clear_color: Color,
+	software: SoftwareRenderer,
 }
 
 impl Renderer {
 	pub fn new(window: &winit::window::Window, clear_color: Color) -> Renderer {
+		Renderer::with_spritesheet(window, clear_color, embedded_spritesheet())
+	}
+
+	/// Same as `Renderer::new` but loads the spritesheet from the given file instead of using
+	/// the embedded one (which is still used as a fallback if the file cannot be used).
+	pub fn with_spritesheet_path(
+		window: &winit::window::Window,
+		clear_color: Color,
+		path: &std::path::Path,
+	) -> Renderer {
+		let spritesheet = load_spritesheet(path).unwrap_or_else(|error| {
+			log::warn!(
+				"could not use the spritesheet at \"{}\", using the embedded one instead: {error}",
+				path.display()
+			);
+			embedded_spritesheet()
+		});
+		Renderer::with_spritesheet(window, clear_color, spritesheet)
+	}
+
+	fn with_spritesheet(
+		window: &winit::window::Window,
+		clear_color: Color,
+		spritesheet: image::DynamicImage,
+	) -> Renderer {
 		let clear_color_wgpu = {
 			fn conv_srgb_to_linear(x: f64) -> f64 {
 				// See https://github.com/gfx-rs/wgpu/issues/2326
@@ -240,21 +906,17 @@ impl Renderer {
 				.unwrap()
 		};
 
-		let spritesheet =
-			image::load_from_memory(include_bytes!("../assets/spritesheet.png")).unwrap();
+		let software = SoftwareRenderer::with_spritesheet(pix_buf_dims, clear_color, spritesheet);
 
-		Renderer { pix_buf, pix_buf_dims, spritesheet, clear_color }
+		Renderer { pix_buf, software }
 	}
 
-	pub fn clear(&mut self) {
+	/// Presents the frame drawn so far on the window.
+	pub fn render(&mut self) {
 		self
 			.pix_buf
 			.frame_mut()
-			.chunks_exact_mut(4)
-			.for_each(|pixel| pixel.copy_from_slice(&self.clear_color.raw()));
-	}
-
-	pub fn render(&mut self) {
+			.copy_from_slice(self.software.frame());
 		self.pix_buf.render().unwrap();
 	}
 
@@ -267,85 +929,52 @@ impl Renderer {
 			.pix_buf
 			.resize_buffer(new_dims.w as u32, new_dims.h as u32)
 			.unwrap();
-		self.pix_buf_dims = new_dims;
+		self.software.resized(new_dims);
 	}
+}
 
-	pub fn dims(&self) -> Dimensions {
-		self.pix_buf_dims
+impl std::ops::Deref for Renderer {
+	type Target = SoftwareRenderer;
+	fn deref(&self) -> &SoftwareRenderer {
+		&self.software
 	}
-
-	/// Draw a rect from the spritesheet onto a rect in the pixel buffer.
-	/// The `paint` argument, if some, will paint all the non-transparent pixels to the given color.
-	pub fn draw_sprite(&mut self, dst: Rect, sprite: Rect, effects: DrawSpriteEffects) {
-		// `coords_dst_dims` is a pixel in the dst rect but with (0, 0) being the top left corner.
-		for coords_dst_dims in dst.dims.iter() {
-			// `(sx, sy)` is the pixel to read from the spritesheet.
-			let (cddx, cddy) = if effects.flip_diagonally_id {
-				(coords_dst_dims.y, coords_dst_dims.x)
-			} else {
-				(coords_dst_dims.x, coords_dst_dims.y)
-			};
-			let sx = if effects.flip_horizontally {
-				(sprite.top_left.x + sprite.dims.w - 1 - cddx * sprite.dims.w / dst.dims.w) as u32
-			} else {
-				(sprite.top_left.x + cddx * sprite.dims.w / dst.dims.w) as u32
-			};
-			let sy = if effects.flip_vertically {
-				(sprite.top_left.y + sprite.dims.h - 1 - cddy * sprite.dims.h / dst.dims.h) as u32
-			} else {
-				(sprite.top_left.y + cddy * sprite.dims.h / dst.dims.h) as u32
-			};
-
-			let color = self.spritesheet.get_pixel(sx, sy).0;
-			// Skip transparent pixels.
-			if color[3] == 0 {
-				continue;
-			}
-			let color = effects.paint.map(Color::raw).unwrap_or(color);
-
-			// `coords_pixel_buffer` is the pixel to write to in the pixel buffer,
-			// each of which is visited once.
-			let coords_pixel_buffer = coords_dst_dims + dst.top_left.into();
-			if let Some(pixel_index) = self.pix_buf_dims.index_of_coords(coords_pixel_buffer) {
-				let pixel_byte_index = pixel_index * 4;
-				let pixel_bytes = pixel_byte_index..(pixel_byte_index + 4);
-				self.pix_buf.frame_mut()[pixel_bytes].copy_from_slice(&color);
-			}
-		}
+}
+impl std::ops::DerefMut for Renderer {
+	fn deref_mut(&mut self) -> &mut SoftwareRenderer {
+		&mut self.software
 	}
+}
 
-	pub fn draw_rect(&mut self, dst: Rect, color: Color) {
-		for coords in dst.iter() {
-			if let Some(pixel_index) = self.pix_buf_dims.index_of_coords(coords) {
-				let pixel_byte_index = pixel_index * 4;
-				let pixel_bytes = pixel_byte_index..(pixel_byte_index + 4);
-				self.pix_buf.frame_mut()[pixel_bytes].copy_from_slice(&color.raw());
-			}
-		}
+/// The given sprite color painted with the given paint color (if any).
+fn painted(color: [u8; 4], paint: Option<Color>) -> [u8; 4] {
+	if let Some(paint) = paint {
+		// The alpha of the sprite can be partial when smoothly scaled.
+		let [r, g, b, a] = paint.raw();
+		[r, g, b, (a as u32 * color[3] as u32 / 255) as u8]
+	} else {
+		color
 	}
+}
 
-	pub fn draw_rect_edge(&mut self, dst: Rect, color: Color) {
-		let dst_inside = Rect {
-			top_left: dst.top_left + (2, 2).into(),
-			dims: dst.dims - (4, 4).into(),
-		};
-		for coords in dst.iter() {
-			if !dst_inside.contains(coords) {
-				if let Some(pixel_index) = self.pix_buf_dims.index_of_coords(coords) {
-					let pixel_byte_index = pixel_index * 4;
-					let pixel_bytes = pixel_byte_index..(pixel_byte_index + 4);
-					self.pix_buf.frame_mut()[pixel_bytes].copy_from_slice(&color.raw());
-				}
-			}
-		}
-	}
+/// How a sprite is stretched to its destination.
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum ScaleMode {
+	/// Crisp pixel art, each destination pixel takes the color of a sprite pixel.
+	Nearest,
+	/// Smooth, the colors of the sprite pixels are interpolated.
+	Bilinear,
 }
 
+#[derive(Clone, Copy)]
 pub struct DrawSpriteEffects {
 	pub flip_horizontally: bool,
 	pub flip_vertically: bool,
 	pub flip_diagonally_id: bool,
 	pub paint: Option<Color>,
+	pub scale_mode: ScaleMode,
+	/// Clockwise, in radians, about the center of the destination. Anything but zero takes
+	/// the slower path (see `SoftwareRenderer::draw_sprite_rotated`).
+	pub rotation: f32,
 }
 impl DrawSpriteEffects {
 	pub fn none() -> DrawSpriteEffects {
@@ -354,6 +983,8 @@ impl DrawSpriteEffects {
 			flip_vertically: false,
 			flip_diagonally_id: false,
 			paint: None,
+			scale_mode: ScaleMode::Nearest,
+			rotation: 0.0,
 		}
 	}
 }
@@ -367,10 +998,10 @@ impl Color {
 	pub const BLACK: Color = Color { rgba: [0, 0, 0, 255] };
 	pub const WHITE: Color = Color { rgba: [255, 255, 255, 255] };
 
-	pub fn rgba_u8(r: u8, g: u8, b: u8, a: u8) -> Color {
+	pub const fn rgba_u8(r: u8, g: u8, b: u8, a: u8) -> Color {
 		Color { rgba: [r, g, b, a] }
 	}
-	pub fn rgb_u8(r: u8, g: u8, b: u8) -> Color {
+	pub const fn rgb_u8(r: u8, g: u8, b: u8) -> Color {
 		Color::rgba_u8(r, g, b, 255)
 	}
 
@@ -392,3 +1023,113 @@ impl Color {
 	channel!(b, 2);
 	channel!(a, 3);
 }
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn pixel_raw(renderer: &SoftwareRenderer, coords: Coords) -> Option<[u8; 4]> {
+		renderer.pixel(coords).map(Color::raw)
+	}
+
+	#[test]
+	fn draw_rect_sets_the_pixels_in_it() {
+		let mut renderer = SoftwareRenderer::new((8, 6).into(), Color::BLACK);
+		renderer.clear();
+		renderer.draw_rect(Rect::xywh(2, 1, 3, 2), Color::WHITE);
+		for coords in renderer.dims().iter() {
+			let inside = (2..5).contains(&coords.x) && (1..3).contains(&coords.y);
+			let expected = if inside { Color::WHITE } else { Color::BLACK };
+			assert_eq!(pixel_raw(&renderer, coords), Some(expected.raw()));
+		}
+		assert_eq!(pixel_raw(&renderer, (8, 0).into()), None);
+		assert_eq!(pixel_raw(&renderer, (-1, 0).into()), None);
+	}
+
+	#[test]
+	fn translucent_rect_is_blended() {
+		let mut renderer = SoftwareRenderer::new((4, 4).into(), Color::rgb_u8(0, 100, 200));
+		renderer.clear();
+		// Partly out of the frame.
+		renderer.draw_rect(Rect::xywh(-2, -2, 4, 4), Color::rgba_u8(255, 0, 0, 51));
+		assert_eq!(
+			pixel_raw(&renderer, (1, 1).into()),
+			Some([51, 80, 160, 255])
+		);
+		assert_eq!(
+			pixel_raw(&renderer, (2, 2).into()),
+			Some([0, 100, 200, 255])
+		);
+	}
+
+	#[test]
+	fn drawing_stays_in_the_clip() {
+		let mut renderer = SoftwareRenderer::new((10, 10).into(), Color::BLACK);
+		renderer.clear();
+		renderer.push_clip(Rect::xywh(2, 2, 6, 6));
+		// Nested, only the intersection with the outer clip is drawn on.
+		renderer.push_clip(Rect::xywh(4, 1, 10, 3));
+		renderer.draw_rect(Rect::xywh(0, 0, 10, 10), Color::WHITE);
+		renderer.pop_clip();
+		renderer.draw_line((0, 9).into(), (9, 9).into(), Color::WHITE);
+		renderer.draw_line((0, 6).into(), (9, 6).into(), Color::WHITE);
+		for coords in renderer.dims().iter() {
+			let in_nested_clip = (4..8).contains(&coords.x) && (2..4).contains(&coords.y);
+			let on_line = (2..8).contains(&coords.x) && coords.y == 6;
+			let expected = if in_nested_clip || on_line {
+				Color::WHITE
+			} else {
+				Color::BLACK
+			};
+			assert_eq!(
+				pixel_raw(&renderer, coords),
+				Some(expected.raw()),
+				"at {coords:?}"
+			);
+		}
+		renderer.pop_clip();
+		assert!(renderer.clip().is_none());
+	}
+
+	#[test]
+	fn rotated_sprite() {
+		let clear_color = Color::rgb_u8(1, 2, 3);
+		let draw = |effects: DrawSpriteEffects| {
+			let mut renderer = SoftwareRenderer::new((32, 32).into(), clear_color);
+			renderer.clear();
+			renderer.draw_sprite(
+				Rect::xywh(8, 8, 16, 16),
+				Rect::tile((0, 0).into(), TILE_PX),
+				effects,
+			);
+			renderer
+		};
+		// A half turn is the same as flipping both ways.
+		let half_turn =
+			draw(DrawSpriteEffects { rotation: std::f32::consts::PI, ..DrawSpriteEffects::none() });
+		let flipped = draw(DrawSpriteEffects {
+			flip_horizontally: true,
+			flip_vertically: true,
+			..DrawSpriteEffects::none()
+		});
+		assert_eq!(half_turn.frame(), flipped.frame());
+		// An eighth of a turn makes the corners stick out of the destination, where its
+		// corners were is left untouched.
+		let eighth_turn = draw(DrawSpriteEffects {
+			rotation: std::f32::consts::FRAC_PI_4,
+			..DrawSpriteEffects::none()
+		});
+		for outside_dst in [(16, 6), (16, 25), (6, 16), (25, 16)] {
+			assert_ne!(
+				pixel_raw(&eighth_turn, outside_dst.into()),
+				Some(clear_color.raw())
+			);
+		}
+		for dst_corner in [(8, 8), (23, 8), (8, 23), (23, 23)] {
+			assert_eq!(
+				pixel_raw(&eighth_turn, dst_corner.into()),
+				Some(clear_color.raw())
+			);
+		}
+	}
+}
@@ -0,0 +1,205 @@
+//! Recording the window events of a session to a file, and playing them back.
+//!
+//! A recording file starts with a header line (the RNG seed and the initial window size),
+//! then there is one line per recorded event (with the time since the start of the recording).
+//! All the lines are JSON.
+//!
+//! Playing back a recording with the same seed should replay the same run, as long as the
+//! animations take about the same time (an input that arrives during an animation is ignored
+//! by the game, so a really slow playback could make an input get ignored when it was not).
+
+use std::{
+	collections::VecDeque,
+	fs::File,
+	io::{BufRead, BufReader, Write},
+	path::Path,
+	time::{Duration, Instant},
+};
+
+use serde::{Deserialize, Serialize};
+use winit::{
+	dpi::{PhysicalPosition, PhysicalSize},
+	event::{DeviceId, ElementState, KeyboardInput, MouseButton, VirtualKeyCode, WindowEvent},
+};
+
+/// The window events that matter to the game, in a form that can be saved to a file.
+#[derive(Clone, Serialize, Deserialize)]
+pub enum RecordedEvent {
+	Resized(PhysicalSize<u32>),
+	CursorMoved(PhysicalPosition<f64>),
+	CursorLeft,
+	MouseInput {
+		state: ElementState,
+		button: MouseButton,
+	},
+	KeyboardInput {
+		state: ElementState,
+		virtual_keycode: VirtualKeyCode,
+	},
+	ReceivedCharacter(char),
+}
+
+impl RecordedEvent {
+	pub fn from_window_event(event: &WindowEvent) -> Option<RecordedEvent> {
+		match event {
+			WindowEvent::Resized(size) => Some(RecordedEvent::Resized(*size)),
+			WindowEvent::CursorMoved { position, .. } => Some(RecordedEvent::CursorMoved(*position)),
+			WindowEvent::CursorLeft { .. } => Some(RecordedEvent::CursorLeft),
+			WindowEvent::MouseInput { state, button, .. } => {
+				Some(RecordedEvent::MouseInput { state: *state, button: *button })
+			},
+			WindowEvent::KeyboardInput {
+				input: KeyboardInput { state, virtual_keycode: Some(virtual_keycode), .. },
+				..
+			} => {
+				Some(RecordedEvent::KeyboardInput { state: *state, virtual_keycode: *virtual_keycode })
+			},
+			WindowEvent::ReceivedCharacter(ch) => Some(RecordedEvent::ReceivedCharacter(*ch)),
+			_ => None,
+		}
+	}
+
+	/// The device id, scancode and modifiers are not recorded (the game doesn't look at these)
+	/// so dummy values are used instead.
+	#[allow(deprecated)] // The deprecated `modifiers` fields still have to be set.
+	pub fn to_window_event(&self) -> WindowEvent<'static> {
+		// SAFETY: The dummy device id is only ever seen by the game event handling,
+		// it is never given to winit.
+		let device_id = unsafe { DeviceId::dummy() };
+		let modifiers = Default::default();
+		match *self {
+			RecordedEvent::Resized(size) => WindowEvent::Resized(size),
+			RecordedEvent::CursorMoved(position) => {
+				WindowEvent::CursorMoved { device_id, position, modifiers }
+			},
+			RecordedEvent::CursorLeft => WindowEvent::CursorLeft { device_id },
+			RecordedEvent::MouseInput { state, button } => {
+				WindowEvent::MouseInput { device_id, state, button, modifiers }
+			},
+			RecordedEvent::KeyboardInput { state, virtual_keycode } => WindowEvent::KeyboardInput {
+				device_id,
+				input: KeyboardInput {
+					scancode: 0,
+					state,
+					virtual_keycode: Some(virtual_keycode),
+					modifiers,
+				},
+				is_synthetic: false,
+			},
+			RecordedEvent::ReceivedCharacter(ch) => WindowEvent::ReceivedCharacter(ch),
+		}
+	}
+}
+
+/// Everything needed to start the recorded game again.
+#[derive(Serialize, Deserialize)]
+pub struct RecordingHeader {
+	pub seed: u64,
+	pub window_size: PhysicalSize<u32>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct TimedEvent {
+	time_ms: u64,
+	event: RecordedEvent,
+}
+
+#[derive(Debug)]
+pub enum ReplayError {
+	Io(std::io::Error),
+	Json(serde_json::Error),
+	NoHeader,
+}
+
+impl std::fmt::Display for ReplayError {
+	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+		match self {
+			ReplayError::Io(error) => write!(f, "{error}"),
+			ReplayError::Json(error) => write!(f, "{error}"),
+			ReplayError::NoHeader => write!(f, "the recording is empty"),
+		}
+	}
+}
+
+impl From<std::io::Error> for ReplayError {
+	fn from(error: std::io::Error) -> ReplayError {
+		ReplayError::Io(error)
+	}
+}
+
+impl From<serde_json::Error> for ReplayError {
+	fn from(error: serde_json::Error) -> ReplayError {
+		ReplayError::Json(error)
+	}
+}
+
+/// Writes the events to a recording file as they happen.
+pub struct Recorder {
+	file: File,
+	start: Instant,
+}
+
+impl Recorder {
+	pub fn create(path: &Path, header: &RecordingHeader) -> Result<Recorder, ReplayError> {
+		let mut file = File::create(path)?;
+		writeln!(file, "{}", serde_json::to_string(header)?)?;
+		Ok(Recorder { file, start: Instant::now() })
+	}
+
+	pub fn record(&mut self, event: &RecordedEvent) {
+		let time_ms = Instant::now().duration_since(self.start).as_millis() as u64;
+		let timed_event = TimedEvent { time_ms, event: event.clone() };
+		// The file is not buffered so that nothing is lost when the game is closed.
+		let result = serde_json::to_string(&timed_event)
+			.map_err(ReplayError::from)
+			.and_then(|line| Ok(writeln!(self.file, "{line}")?));
+		if let Err(error) = result {
+			log::warn!("failed to record an event: {error}");
+		}
+	}
+}
+
+/// Gives back the events of a recording file when their time comes.
+pub struct Player {
+	events: VecDeque<TimedEvent>,
+	start: Instant,
+}
+
+impl Player {
+	pub fn load(path: &Path) -> Result<(RecordingHeader, Player), ReplayError> {
+		let mut lines = BufReader::new(File::open(path)?).lines();
+		let header: RecordingHeader =
+			serde_json::from_str(&lines.next().ok_or(ReplayError::NoHeader)??)?;
+		let mut events = VecDeque::new();
+		for line in lines {
+			let line = line?;
+			if !line.trim().is_empty() {
+				events.push_back(serde_json::from_str(&line)?);
+			}
+		}
+		Ok((header, Player { events, start: Instant::now() }))
+	}
+
+	/// The recorded times are relative to the moment this is called.
+	pub fn start_now(&mut self) {
+		self.start = Instant::now();
+	}
+
+	/// Returns the events that should have happened by now and that were not returned yet.
+	pub fn due_events(&mut self) -> Vec<RecordedEvent> {
+		let now = Instant::now().duration_since(self.start);
+		let mut due_events = vec![];
+		while self
+			.events
+			.front()
+			.is_some_and(|timed_event| Duration::from_millis(timed_event.time_ms) <= now)
+		{
+			due_events.push(self.events.pop_front().unwrap().event);
+		}
+		due_events
+	}
+
+	pub fn is_finished(&self) -> bool {
+		self.events.is_empty()
+	}
+}
@@ -0,0 +1,105 @@
+//! Interpolation between values and easing of the progress of animations.
+//!
+//! Everything takes a progress that goes from 0.0 (the start) to 1.0 (the end), such as
+//! the one of a `TimeProgression`. An easing function reshapes the progress before it is given
+//! to an interpolation function, like `lerp_rect(quad_out(progress), from, to)`.
+
+use crate::coords::*;
+use crate::renderer::Color;
+
+pub fn lerp_f32(progress: f32, start: f32, end: f32) -> f32 {
+	start + progress * (end - start)
+}
+
+/// Rounded to the nearest integer.
+pub fn lerp_i32(progress: f32, start: i32, end: i32) -> i32 {
+	lerp_f32(progress, start as f32, end as f32).round() as i32
+}
+
+pub fn lerp_rect(progress: f32, start: Rect, end: Rect) -> Rect {
+	Rect::xywh(
+		lerp_i32(progress, start.left(), end.left()),
+		lerp_i32(progress, start.top(), end.top()),
+		lerp_i32(progress, start.dims.w, end.dims.w),
+		lerp_i32(progress, start.dims.h, end.dims.h),
+	)
+}
+
+/// Each channel (alpha included) is interpolated on its own.
+pub fn lerp_color(progress: f32, start: Color, end: Color) -> Color {
+	let [r, g, b, a] = std::array::from_fn(|i| {
+		lerp_i32(progress, start.raw()[i] as i32, end.raw()[i] as i32).clamp(0, 255) as u8
+	});
+	Color::rgba_u8(r, g, b, a)
+}
+
+/// Starts slow, ends fast.
+pub fn quad_in(progress: f32) -> f32 {
+	progress * progress
+}
+
+/// Starts fast, ends slow.
+pub fn quad_out(progress: f32) -> f32 {
+	1.0 - quad_in(1.0 - progress)
+}
+
+/// Starts slow, ends slow.
+pub fn quad_in_out(progress: f32) -> f32 {
+	if progress < 0.5 {
+		quad_in(progress * 2.0) / 2.0
+	} else {
+		0.5 + quad_out(progress * 2.0 - 1.0) / 2.0
+	}
+}
+
+/// Overshoots the end a little then settles back on it.
+pub fn back_out(progress: f32) -> f32 {
+	// The usual overshoot constant, it makes the curve go about 10% past the end.
+	let overshoot = 1.70158;
+	let t = progress - 1.0;
+	1.0 + t * t * ((overshoot + 1.0) * t + overshoot)
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn easings_go_from_start_to_end() {
+		for easing in [quad_in, quad_out, quad_in_out, back_out] {
+			assert!(easing(0.0).abs() < 1e-6);
+			assert!((easing(1.0) - 1.0).abs() < 1e-6);
+		}
+		assert_eq!(quad_in(0.5), 0.25);
+		assert_eq!(quad_out(0.5), 0.75);
+		assert_eq!(quad_in_out(0.5), 0.5);
+		// It overshoots somewhere in the middle.
+		assert!((0..100).any(|i| back_out(i as f32 / 100.0) > 1.05));
+	}
+
+	#[test]
+	fn lerps_go_from_start_to_end() {
+		assert_eq!(lerp_f32(0.0, 2.0, 6.0), 2.0);
+		assert_eq!(lerp_f32(0.5, 2.0, 6.0), 4.0);
+		assert_eq!(lerp_f32(1.0, 2.0, 6.0), 6.0);
+		assert_eq!(lerp_i32(0.5, 0, 5), 3);
+		assert_eq!(lerp_i32(0.5, 10, -10), 0);
+		let (start, end) = (Rect::xywh(0, 0, 10, 10), Rect::xywh(10, -20, 20, 30));
+		let rect = lerp_rect(0.5, start, end);
+		assert_eq!(
+			(rect.left(), rect.top(), rect.dims.w, rect.dims.h),
+			(5, -10, 15, 20)
+		);
+		assert_eq!(lerp_rect(1.0, start, end).top_left, end.top_left);
+		let color = lerp_color(
+			0.5,
+			Color::rgba_u8(0, 100, 200, 0),
+			Color::rgba_u8(200, 100, 0, 255),
+		);
+		assert_eq!(color.raw(), [100, 100, 100, 128]);
+		assert_eq!(
+			lerp_color(0.0, Color::BLACK, Color::WHITE).raw(),
+			Color::BLACK.raw()
+		);
+	}
+}